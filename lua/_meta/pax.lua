@@ -4,31 +4,47 @@ error("Cannot require a meta file")
 
 pax = {}
 
+--- A debian package.
 --- @class pax.BuildSpec
 --- @field package string
---- @field version string
 --- @field name? string
+--- @field version string
 --- @field description? string
 --- @field essential? boolean
 --- @field author? string
 --- @field email? string
 --- @field maintainer? string
 --- @field homepage? string
---- @field files pax.File[]
---- @field dependencies string[]
+--- @field files? (pax.File|string)[]
+--- @field conffiles? string[] Files outside /etc that dpkg should treat as conffiles. Everything installed under /etc already is one.
+--- @field pre_depends? string[] Packages that have to be configured before this one is unpacked.
+--- @field dependencies? string[]
 --- @field recommends? string[]
 --- @field suggests? string[]
 --- @field conflicts? string[]
 --- @field breaks? string[]
 --- @field provides? string[]
 --- @field replaces? string[]
---- @field priority? string
---- @field arch string
---- @field urgency? string
---- @field apt_sources? any[]
---- @field scripts? pax.MaintainerScripts
-
---- @enum pax.Urgency
+--- @field priority? pax.Priority
+--- @field arch? string
+--- @field urgency? pax.Urgency
+--- @field section? string Archive section, such as "utils" or "devel".
+--- @field apt_sources? pax.AptSources[] Repositories installed as /etc/apt/sources.list.d/<name>.sources.
+--- @field scripts? pax.MaintainerScripts|string Maintainer scripts, or a directory containing them.
+--- @field changelog? pax.ChangelogOpts|string|boolean Generate /usr/share/doc/<package>/changelog.Debian.gz from git.
+--- @field copyright? pax.Copyright Generate a machine-readable /usr/share/doc/<package>/copyright file.
+--- @field services? (pax.Service|string)[] Systemd units to install into /lib/systemd/system.
+--- @field users? (pax.User|string)[] Accounts created before the package is unpacked.
+--- @field dbgsym? boolean Split debug info out of ELF binaries into a <package>-dbgsym package.
+--- @field compression? pax.Compression Compression of the data archive. Default is gzip.
+--- @field compression_threads? integer Threads used by xz and zstd compression, 0 uses one per CPU. Default is 1.
+
+--- @class pax.BuildAllOpts
+--- @field jobs? integer Number of packages built at the same time. Default is the value of --jobs or one per CPU.
+
+--- @alias pax.Urgency pax.UrgencyValue|"low"|"medium"|"high"|"emergency"|"critical"
+
+--- @enum pax.UrgencyValue
 pax.Urgency = {
   Low = 0,
   Medium = 1,
@@ -37,7 +53,9 @@ pax.Urgency = {
   Critical = 4,
 }
 
---- @enum pax.Priority
+--- @alias pax.Priority pax.PriorityValue|"required"|"important"|"standard"|"optional"|"extra"|"invalid"
+
+--- @enum pax.PriorityValue
 pax.Priority = {
   Required = 0,
   Important = 1,
@@ -47,66 +65,156 @@ pax.Priority = {
   Invalid = 5,
 }
 
---- @enum pax.Architecture
-pax.Architecture = {
-  All = 0,
-  Any = 1,
-  Source = 2,
-  Invalid = 3,
+--- Compression used for the data archive.
+--- @alias pax.Compression pax.CompressionValue|"gzip"|"xz"|"zstd"
+
+--- @enum pax.CompressionValue
+pax.Compression = {
+  Gzip = 0,
+  Xz = 1,
+  Zstd = 2,
 }
 
---- @class pax.File
---- @field src string
---- @field dst string
---- @field mode number
+--- @class pax.ChangelogOpts
+--- @field repo? string Path to the git repository. Default is the current directory.
+--- @field distribution? string Distribution written into each entry header. Default is "unstable".
+--- @field max_entries? integer Only include the most recent entries.
+--- @field merges? boolean Include merge commits in the list of changes.
+
+--- Structured data used to write a machine-readable (DEP-5) copyright file.
+--- @class pax.Copyright
+--- @field upstream_name? string Upstream-Name header. Defaults to the package name.
+--- @field upstream_contact? string
+--- @field source? string
+--- @field files? pax.CopyrightFiles[]
+--- @field licenses? pax.CopyrightLicense[] Stand-alone license paragraphs holding the full license text.
+--- @field cargo? string Crate directory whose dependencies, found with 'cargo metadata', are added to the "Files: *" paragraph.
+--- @field cargo_target? string Target triple used to filter crate dependencies. Defaults to the host.
+--- @field go? string Go module directory whose dependencies, found with 'go list -m', are added to the "Files: *" paragraph.
+
+--- @class pax.CopyrightFiles
+--- @field files string[] File globs.
+--- @field copyright string[] Copyright holders.
+--- @field license string
+--- @field comment? string
+
+--- @class pax.CopyrightLicense
+--- @field name string
+--- @field text? string
+--- @field file? string Read the license text from a file instead.
+
+--- @class pax.TaskOpts
+--- @field deps? string[] Tasks that have to run before this one.
+--- @field description? string Shown by `pax list`.
 
 --- @class pax.DownloadOpts
+--- @field url? string
 --- @field release? string
 --- @field arch? string
 --- @field out? string
---- @field compression? number 1 for gzip, 2 for xz
+--- @field compression? integer 1 for gzip, 2 for xz
 
 --- @class pax.Go
---- @field root      string  Root directory to run the go command in.
---- @field generate  boolean Run `go generate ./...` before building.
---- @field cmd?      string  Command directory to build. Default is '.'
---- @field out?      string
---- @field mode?     string
+--- @field root string Root directory to run the go command in.
+--- @field cmd? string Command directory to build. Default is '.'
+--- @field out? string Path of the built binary.
+--- @field mode? string Passed to '-mod'.
 --- @field trimpath? boolean
---- @field ldflags?  string[] Pass additional flags to '-ldflags'
---- @field asmflags? string[] Pass additional flags to '-asmflags'
---- @field tags?     string[] Pass a list of tags to '-tags'
+--- @field ldflags? string[] Passed to '-ldflags'. Default is "-s -w".
+--- @field asmflags? string[] Passed to '-asmflags'.
+--- @field tags? string[] Passed to '-tags'.
 --- @field compiler? string
+--- @field generate? boolean Run 'go generate ./...' before running build commands
+--- @field build_ldflags? fun(data: pax.GoBuildData): string[] Called with the git sha and build date, returns extra flags for '-ldflags'.
+--- @field bin_access_mode? integer Mode of the installed binary. Default is 0755.
+
+--- @class pax.GoBuildData
+--- @field git_sha string Commit of HEAD in the module's repository.
+--- @field date string Time of the build in RFC 3339 format.
 
 --- @class pax.Cargo
 --- @field root string
 --- @field pkgid? string
 --- @field target_dir? string
---- @field profile? string
---- @field verbosity? number
+--- @field profile? string profile can be "release" or "debug" and corresponds with cargo's --profile and --release flags. Default is "release".
+--- @field verbosity? integer verbosity level. 0 for off, 1 for on, 2 for very verbose
 --- @field features? string[]
---- @field quiet? boolean
---- @field keep_going? boolean
+--- @field quiet? boolean run cargo quietly.
+--- @field keep_going? boolean Don't stop the build on failure.
 --- @field ignore_rust_version? boolean
---- @field config? string[]
+--- @field config? string[] key value pairs that are equivilent to using --config <KEY=VAL> in the cargo cli.
 --- @field target? string
---- @field embeded_cargo? boolean
---- @field clean? boolean
+--- @field embeded_cargo? boolean run cargo as an embedded library (it doesn't always work as expected)
+--- @field clean? boolean remove the target directory before building.
 
---- @class pax.MaintainerScripts
---- @field preinst? string
---- @field postinst? string
---- @field prerm? string
---- @field postrm? string
+--- @class pax.GitCloneOpts
+--- @field repo string
+--- @field dest? string
+--- @field branch? string
+--- @field depth? integer
+--- @field force? boolean
+
+--- @class pax.ExecOptions
+--- @field dir? string Directory the command runs in.
+--- @field stdin_file? string Read the command's stdin from this file.
+--- @field stdout_file? string Write the command's stdout to this file.
+
+--- @class pax.LibcVersion
+--- @field major integer
+--- @field minor integer
 
 --- @class pax.SCDocOpts
 --- @field input string
 --- @field output string
 --- @field compress? boolean
 
+--- @class pax.File
+--- @field src string
+--- @field dst string
+--- @field mode? integer
+--- @field owner? string Owner of the installed file. Default is root.
+--- @field group? string Group of the installed file. Default is root.
+--- @field link? string Install a symlink to this target at `dst` instead of copying `src`.
+
+--- Scripts are merged with any snippets pax generates. Put "#PAX#" on a line of its own to choose
+--- where the snippets go, otherwise they are added to the top of the script.
+--- @class pax.MaintainerScripts
+--- @field dir? string Load preinst, postinst, prerm and postrm from this directory.
+--- @field preinst? string|pax.ScriptFile
+--- @field postinst? string|pax.ScriptFile
+--- @field prerm? string|pax.ScriptFile
+--- @field postrm? string|pax.ScriptFile
+
+--- @class pax.ScriptFile
+--- @field file string Path of the script.
+
+--- @class pax.Service
+--- @field unit                string  Path to the unit file (.service, .socket, .timer, ...).
+--- @field enable?             boolean Enable the unit on first install. Default is true.
+--- @field start?              boolean Start the unit after install. Default is true.
+--- @field restart_on_upgrade? boolean Restart after upgrading instead of stopping before. Default is true.
+
+--- @class pax.AptSources
+--- @field name           string
+--- @field url            string
+--- @field suites         string[]|string Suites, as a list or separated by spaces.
+--- @field components?    string[]|string
+--- @field architectures? string[]|string
+--- @field key_url?       string Signing key downloaded at build time into /usr/share/keyrings.
+--- @field key_file?      string Signing key read at build time into /usr/share/keyrings.
+--- @field signed_by?     string Keyring that already exists on the target system.
+
+--- @class pax.User
+--- @field name             string
+--- @field system?          boolean  Create a system account. Default is true.
+--- @field home?            string   Home directory, created when missing.
+--- @field shell?           string   Login shell. System accounts default to /usr/sbin/nologin.
+--- @field groups?          string[] Supplementary groups, created when missing.
+--- @field remove_on_purge? boolean  Delete the account when the package is purged.
+
 --- @class pax.Stat
 --- @field size number
---- @field mode number
+--- @field mode? integer
 --- @field mtime number
 --- @field atime number
 --- @field ctime number
@@ -125,6 +233,54 @@ function pax.project(spec) end
 --- @return string
 function pax.cwd() end
 
+--- Number of packages built at the same time, set with --jobs.
+--- @type number?
+pax.jobs = nil
+
+--- Define a task that runs with `pax run <name>`. Every task runs at most once, after the tasks it
+--- depends on. The task named "default" runs when no task is given.
+--- @param name string
+--- @param fn fun()
+--- @param opts? pax.TaskOpts
+function pax.task(name, fn, opts) end
+
+--- Arguments given after '--', as in `pax run -- a b`.
+--- @type string[]
+pax.args = {}
+
+--- Variables set with -D key=value.
+--- @type table<string, string>
+pax.vars = {}
+
+--- Read a variable set with -D, falling back to the environment (and .env) and then `default`.
+--- @param name string
+--- @param default? string
+--- @return string?
+function pax.var(name, default) end
+
+--- Like pax.var, but the value must be a number.
+--- @param name string
+--- @param default? number
+--- @return number?
+function pax.var_number(name, default) end
+
+--- Like pax.var, but the value must be true/false, yes/no, on/off or 1/0.
+--- @param name string
+--- @param default? boolean
+--- @return boolean?
+function pax.var_bool(name, default) end
+
+--- Like pax.var, but the value is split on commas.
+--- @param name string
+--- @param default? string[]
+--- @return string[]?
+function pax.var_list(name, default) end
+
+--- Build every package added with pax:add() in parallel. Every package is attempted and the
+--- failures are reported together.
+--- @param opts? pax.BuildAllOpts
+function pax:package_all(opts) end
+
 ---@param ... any
 function pax.print(...) end
 
@@ -149,13 +305,6 @@ function pax.in_dir(dir, fn) end
 
 pax.git = {}
 
---- @class pax.GitCloneOpts
---- @field repo string
---- @field dest? string
---- @field branch? string
---- @field depth? number
---- @field force? boolean
-
 --- @return string
 function pax.git.email() end
 
@@ -245,11 +394,6 @@ function pax.fs.stat(dir) end
 
 pax.os = {}
 
---- @class pax.ExecOptions
---- @field dir?         string
---- @field stdout_file? string
---- @field stdin_file?  string
-
 --- @param bin string
 --- @param args? string[]
 --- @param opts? pax.ExecOptions
@@ -261,10 +405,6 @@ function pax.os.exec(bin, args, opts) end
 --- @return string
 function pax.os.which(name) end
 
---- @class pax.LibcVersion
---- @field major number
---- @field minor number
-
 --- Return the current version of libc.
 --- @return pax.LibcVersion
 function pax.os.libc_version() end
@@ -291,6 +431,78 @@ function pax.path.is_relative() end
 --- @return string
 function pax.path.parent(...) end
 
+pax.control = {}
+
+--- Parse the first paragraph of a debian control file.
+--- @param text string
+--- @return pax.BuildSpec
+function pax.control.parse(text) end
+
+--- Parse every paragraph of a deb822 formatted file.
+--- @param text string
+--- @return pax.BuildSpec[]
+function pax.control.parse_all(text) end
+
+--- Read the control file and maintainer scripts out of a .deb package.
+--- @param path string
+--- @return pax.BuildSpec
+function pax.control.read_deb(path) end
+
+pax.deb = {}
+
+--- Open a .deb package to look at its contents or to import them into a project. Any
+--- compression of the data archive is supported.
+--- @param path string
+--- @return pax.Deb
+function pax.deb.open(path) end
+
+--- A .deb file opened with `pax.deb.open` or read back in `require("pax.testing")`.
+--- @class pax.Deb
+--- @field path     string
+--- @field package? string
+--- @field version? string
+--- @field arch?    string
+--- @field control  table<string, string> Fields of the control file.
+--- @field scripts  table<string, string> The other control archive files by name, like postinst and md5sums.
+--- @field files    pax.DebEntry[] Entries of the data archive sorted by path.
+local Deb = {}
+
+--- @param path string Such as "/usr/bin/foo".
+--- @return pax.DebEntry?
+function Deb:file(path) end
+
+--- A control field, matched without regard to case.
+--- @param name string
+--- @return string?
+function Deb:field(name) end
+
+--- Add the files matching the glob patterns to a project, keeping their modes, owners and
+--- symlinks. A pattern matching a directory also copies what is inside it. Without patterns the
+--- whole package is copied. Conffiles stay conffiles, and files the project already has at the
+--- same paths are replaced.
+--- @param project pax.Project
+--- @param patterns? string|string[] Such as "/usr/lib/foo/**" or { "/usr/bin/*", "/etc/foo" }.
+--- @return integer copied The number of files, directories and links added.
+function Deb:copy(project, patterns) end
+
+--- Add the dependencies of the package to a project's Pre-Depends, Depends, Recommends and
+--- Suggests, skipping the ones it already has.
+--- @param project pax.Project
+--- @return integer added
+function Deb:inherit_deps(project) end
+
+--- @class pax.DebEntry
+--- @field path    string Installed path, like "/usr/bin/foo".
+--- @field kind    "file"|"dir"|"symlink"|"hardlink"
+--- @field mode    integer
+--- @field owner   string
+--- @field group   string
+--- @field uid     integer
+--- @field gid     integer
+--- @field size    integer
+--- @field target? string Target of a link.
+--- @field md5?    string md5 of a file's content.
+
 ---@class pax.Project
 ---@field base_dir     string
 ---@field man_dir      string
@@ -300,6 +512,7 @@ function pax.path.parent(...) end
 ---@field essential    boolean
 ---@field author?      string Package author.
 ---@field email?       string Package email.
+---@field maintainer?  string
 ---@field description? string
 local Project = {}
 
@@ -310,6 +523,13 @@ function Project:finish() end
 ---@return string
 function Project:dir() end
 
+--- Files added to the package so far.
+--- @return pax.File[]
+function Project:files() end
+
+--- @param source pax.AptSources
+function Project:apt_source(source) end
+
 ---@param path string
 function Project:add_binary(path) end
 
@@ -319,6 +539,7 @@ function Project:add_file(...) end
 --- @param files pax.File[]
 function Project:add_files(files) end
 
+--- Copy every file of a .deb package into the project. Same as `pax.deb.open(path):copy(project)`.
 --- @param path string
 function Project:merge_deb(path) end
 
@@ -2,6 +2,7 @@ extern crate proc_macro;
 
 mod from_lua;
 mod into_lua;
+mod meta;
 mod userdata;
 
 use from_lua::{from_lua, from_lua_table};
@@ -20,34 +21,46 @@ pub fn derive_into_lua(item: TokenStream) -> TokenStream {
     into_lua(input)
 }
 
-#[proc_macro_derive(FromLua, attributes(lua_default, ignored))]
+#[proc_macro_derive(FromLua, attributes(lua_default, ignored, lua_type, lua_class))]
 pub fn derive_from_lua(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    from_lua(input)
+    let meta = meta::lua_meta(&input);
+    let mut out = from_lua(input);
+    out.extend(TokenStream::from(meta));
+    out
 }
 
-#[proc_macro_derive(FromLuaTable, attributes(lua_default, ignored))]
+#[proc_macro_derive(FromLuaTable, attributes(lua_default, ignored, lua_type, lua_class))]
 pub fn derive_from_lua_table(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    from_lua_table(input).into()
+    let meta = meta::lua_meta(&input);
+    let mut out = from_lua_table(input);
+    out.extend(meta);
+    out.into()
 }
 
-#[proc_macro_derive(UserData)]
+#[proc_macro_derive(UserData, attributes(lua_class))]
 pub fn derive_userdata(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as DeriveInput);
-    userdata::userdata(input, false).into()
+    let mut out = userdata::userdata(input.clone(), false);
+    out.extend(meta::lua_meta(&input));
+    out.into()
 }
 
-#[proc_macro_derive(UserDataWithDefault)]
+#[proc_macro_derive(UserDataWithDefault, attributes(lua_class))]
 pub fn derive_userdata_with_default(item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as DeriveInput);
-    userdata::userdata(input, true).into()
+    let mut out = userdata::userdata(input.clone(), true);
+    out.extend(meta::lua_meta(&input));
+    out.into()
 }
 
 #[derive(Debug, Default)]
 struct Attrs<'a> {
     lua_default: Option<&'a proc_macro2::TokenStream>,
     ignored: bool,
+    /// LuaLS type of the field, overriding the one worked out from the Rust type.
+    lua_type: Option<String>,
 }
 
 impl syn::parse::Parse for Attrs<'_> {
@@ -83,6 +96,8 @@ impl<'a> From<&'a syn::Field> for Attrs<'a> {
                 res.lua_default = get_tokens(attr).ok();
             } else if attr.path().is_ident("ignored") {
                 res.ignored = true;
+            } else if attr.path().is_ident("lua_type") {
+                res.lua_type = attr.parse_args::<syn::LitStr>().ok().map(|s| s.value());
             } else {
                 continue;
             }
@@ -0,0 +1,250 @@
+//! LuaLS annotations for derived types. Each derive emits a `LUA_META` constant holding the
+//! `---@class` block for the type, or the `---@enum` block and `---@alias` of an enum, which pax
+//! assembles into lua/_meta/pax.lua.
+
+use quote::quote;
+use syn::{Attribute, Data, DataEnum, DataStruct, DeriveInput, Fields, GenericArgument, Type};
+
+use crate::Attrs;
+
+/// Prefix of every generated class name.
+static NAMESPACE: &str = "pax";
+
+/// The `impl` block holding the `LUA_META` constant for `input`.
+pub(crate) fn lua_meta(input: &DeriveInput) -> proc_macro2::TokenStream {
+    let meta = match &input.data {
+        Data::Struct(DataStruct { fields, .. }) => class(input, fields),
+        Data::Enum(DataEnum { variants, .. }) => {
+            // FromLua also takes the lowercase variant names, so fields of the enum type accept
+            // either the enum values or those strings.
+            let name = class_name(input);
+            let strings: Vec<String> = variants
+                .iter()
+                .map(|v| format!("\"{}\"", v.ident.to_string().to_lowercase()))
+                .collect();
+            let mut out = doc_lines(&input.attrs);
+            out.push_str(&format!(
+                "--- @alias {} {}Value|{}\n\n",
+                name,
+                name,
+                strings.join("|")
+            ));
+            out.push_str(&format!("--- @enum {}Value\n{} = {{\n", name, name));
+            for (i, v) in variants.iter().enumerate() {
+                out.push_str(&format!("  {} = {},\n", v.ident, i));
+            }
+            out.push_str("}\n");
+            out
+        }
+        Data::Union(_) => return proc_macro2::TokenStream::new(),
+    };
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// LuaLS annotations for this type.
+            #[allow(dead_code)]
+            pub(crate) const LUA_META: &'static str = #meta;
+        }
+    }
+}
+
+fn class(input: &DeriveInput, fields: &Fields) -> String {
+    let mut out = doc_lines(&input.attrs);
+    out.push_str(&format!("--- @class {}\n", class_name(input)));
+    let Fields::Named(named) = fields else {
+        return out;
+    };
+    for f in &named.named {
+        let attrs = Attrs::from(f);
+        if attrs.ignored {
+            continue;
+        }
+        let name = f.ident.as_ref().unwrap().to_string();
+        let (ty, optional) = match attrs.lua_type {
+            Some(ty) => (ty, is_optional(&f.ty)),
+            None => lua_type(&f.ty),
+        };
+        // Missing booleans are read as false, and fields with a default can be left out.
+        let optional = optional || attrs.lua_default.is_some() || ty == "boolean";
+        out.push_str(&format!(
+            "--- @field {}{} {}",
+            name,
+            if optional { "?" } else { "" },
+            ty
+        ));
+        let doc = doc(&f.attrs)
+            .into_iter()
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !doc.is_empty() {
+            out.push(' ');
+            out.push_str(&doc);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The class name set with `#[lua_class("...")]`, or the type name under the pax namespace.
+fn class_name(input: &DeriveInput) -> String {
+    input
+        .attrs
+        .iter()
+        .find(|a| a.path().is_ident("lua_class"))
+        .and_then(|a| a.parse_args::<syn::LitStr>().ok())
+        .map(|s| s.value())
+        .unwrap_or_else(|| format!("{}.{}", NAMESPACE, input.ident))
+}
+
+fn doc(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(s),
+                        ..
+                    }),
+                ..
+            }) => Some(s.value().trim().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn doc_lines(attrs: &[Attribute]) -> String {
+    doc(attrs)
+        .into_iter()
+        .map(|line| match line.is_empty() {
+            true => "---\n".to_string(),
+            false => format!("--- {}\n", line),
+        })
+        .collect()
+}
+
+fn is_optional(ty: &Type) -> bool {
+    matches!(last_segment(ty), Some((name, _)) if name == "Option")
+}
+
+fn last_segment(ty: &Type) -> Option<(String, Vec<&Type>)> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let seg = path.path.segments.last()?;
+    let args = match &seg.arguments {
+        syn::PathArguments::AngleBracketed(args) => args
+            .args
+            .iter()
+            .filter_map(|a| match a {
+                GenericArgument::Type(t) => Some(t),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    Some((seg.ident.to_string(), args))
+}
+
+/// The LuaLS type of a Rust type, and whether it can be nil.
+pub(crate) fn lua_type(ty: &Type) -> (String, bool) {
+    if let Type::Reference(r) = ty {
+        return lua_type(&r.elem);
+    }
+    let Some((name, args)) = last_segment(ty) else {
+        return ("any".to_string(), false);
+    };
+    let ty = match (name.as_str(), args.as_slice()) {
+        ("Option", [inner]) => return (lua_type(inner).0, true),
+        ("Vec", [inner]) => {
+            let inner = lua_type(inner).0;
+            if inner.contains('|') {
+                format!("({})[]", inner)
+            } else {
+                format!("{}[]", inner)
+            }
+        }
+        ("HashMap" | "BTreeMap", [k, v]) => {
+            format!("table<{}, {}>", lua_type(k).0, lua_type(v).0)
+        }
+        ("String" | "str" | "PathBuf" | "Path" | "char", _) => "string".to_string(),
+        ("bool", _) => "boolean".to_string(),
+        (
+            "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128"
+            | "isize",
+            _,
+        ) => "integer".to_string(),
+        ("f32" | "f64", _) => "number".to_string(),
+        ("Function", _) => "function".to_string(),
+        ("Table", _) => "table".to_string(),
+        ("Value", _) => "any".to_string(),
+        (name, _) => format!("{}.{}", NAMESPACE, name),
+    };
+    (ty, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lua_meta;
+    use syn::{parse_str, DeriveInput};
+
+    fn meta(src: &str) -> String {
+        let input: DeriveInput = parse_str(src).unwrap();
+        let tokens = lua_meta(&input).to_string();
+        let start = tokens.find("str = \"").unwrap() + 7;
+        let end = tokens.rfind("\" ;").unwrap();
+        tokens[start..end]
+            .replace("\\n", "\n")
+            .replace("\\\"", "\"")
+    }
+
+    #[test]
+    fn class() {
+        let out = meta(
+            r#"
+            /// Options for a thing.
+            #[lua_class("pax.ThingOpts")]
+            struct Opts<'lua> {
+                /// Name of the
+                /// thing.
+                name: String,
+                count: Option<u32>,
+                #[lua_default(vec![])]
+                tags: Vec<String>,
+                enabled: bool,
+                #[lua_type("fun(): string")]
+                callback: Option<mlua::Function<'lua>>,
+                files: Vec<Option<File>>,
+                env: HashMap<String, String>,
+                #[ignored]
+                internal: u32,
+            }
+            "#,
+        );
+        assert_eq!(
+            out,
+            "--- Options for a thing.\n\
+             --- @class pax.ThingOpts\n\
+             --- @field name string Name of the thing.\n\
+             --- @field count? integer\n\
+             --- @field tags? string[]\n\
+             --- @field enabled? boolean\n\
+             --- @field callback? fun(): string\n\
+             --- @field files pax.File[]\n\
+             --- @field env table<string, string>\n"
+        );
+    }
+
+    #[test]
+    fn enumeration() {
+        let out = meta("enum Letters { A, B }");
+        assert_eq!(
+            out,
+            "--- @alias pax.Letters pax.LettersValue|\"a\"|\"b\"\n\n\
+             --- @enum pax.LettersValue\npax.Letters = {\n  A = 0,\n  B = 1,\n}\n"
+        );
+    }
+}
@@ -103,7 +103,12 @@ fn try_from_lua_string(
         impl #impl_generics TryFrom<::mlua::String<'_>> for #ident #ty_generics #where_clause {
             type Error = ::mlua::Error;
             fn try_from(value: ::mlua::String<'_>) -> Result<Self, Self::Error> {
-                let s = value.to_str()?;
+                <Self as ::std::str::FromStr>::from_str(value.to_str()?)
+            }
+        }
+        impl #impl_generics ::std::str::FromStr for #ident #ty_generics #where_clause {
+            type Err = ::mlua::Error;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
                 match s.to_lowercase().as_str() {
                     #(
                         #match_strs,
@@ -130,6 +135,17 @@ fn impl_from_lua(
     let ident = &input.ident;
     let ident_str = ident.to_string();
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let copies = match &input.data {
+        Data::Enum(DataEnum { variants, .. }) => variants
+            .iter()
+            .filter(|v| matches!(v.fields, Fields::Unit))
+            .map(|v| {
+                let ident = &v.ident;
+                quote_spanned!(v.span()=> Self::#ident => Ok(Self::#ident))
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
     let extra = if defaultable {
         quote!(::mlua::Value::Nil => Ok(::core::default::Default::default()),)
     } else {
@@ -140,6 +156,11 @@ fn impl_from_lua(
             fn from_lua(value: ::mlua::Value<'_>, lua: &'_ ::mlua::Lua) -> ::mlua::Result<Self> {
                 match value {
                     ::mlua::Value::String(string) => Self::try_from(string),
+                    ::mlua::Value::UserData(ud) => match *ud.borrow::<Self>()? {
+                        #(
+                            #copies,
+                        )*
+                    },
                     #extra
                     ::mlua::Value::Number(n) => match n as usize {
                         #(
@@ -24,6 +24,8 @@ fn main() {
         assert_eq!(l0, tt.1);
         assert_eq!(l1, tt.1);
     }
+    let ud: Letters = lua.load("return Letters").eval().unwrap();
+    assert_eq!(ud, Letters::A);
     assert!(lua.load("return Letters.is_enum()").eval::<bool>().unwrap());
     assert_eq!(
         lua.load("return Letters.variants()")
@@ -30,12 +30,15 @@ pax.cargo.build({
 	verbosity = 0,
 	profile = "release",
 	config = {
-		"profile.release.strip='symbols'",
+		-- keep debug info so that it can be split into pax-dbgsym
+		"profile.release.strip='none'",
+		"profile.release.debug=true",
 	},
 })
 
 pax:package_crate("./pax", {
 	arch = "amd64",
+	dbgsym = true,
 	files = {
 		file("README.md", "/usr/share/pax/README.md", "0644"),
 		"pax.lua:/usr/share/pax/example.lua",
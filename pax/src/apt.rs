@@ -0,0 +1,308 @@
+use std::{fmt::Write as _, fs, io::Write};
+
+use anyhow::{anyhow, bail, Result};
+use mlua::FromLua;
+
+use crate::deb::DataBuilder;
+use crate::dl;
+
+static KEYRING_DIR: &str = "/usr/share/keyrings";
+static SOURCES_DIR: &str = "/etc/apt/sources.list.d";
+
+/// An apt repository shipped as a deb822 .sources file. The signing key is downloaded or read
+/// when the package is built and installed into /usr/share/keyrings, so installing the package
+/// never touches the network.
+#[derive(Clone, Debug, PartialEq, pax_derive::IntoLua, serde::Deserialize)]
+#[serde(from = "SourcesRepr")]
+pub(crate) struct AptSources {
+    /// Used for the .sources and keyring file names.
+    pub name: String,
+    pub url: String,
+    pub suites: Vec<String>,
+    pub components: Vec<String>,
+    /// Limit the repository to these architectures. Default is all of apt's architectures.
+    pub architectures: Option<Vec<String>>,
+    /// URL of the signing key, fetched at build time.
+    pub key_url: Option<String>,
+    /// Path of the signing key, read at build time.
+    pub key_file: Option<String>,
+    /// Path of a keyring that already exists on the target system.
+    pub signed_by: Option<String>,
+}
+
+impl AptSources {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.suites.is_empty() {
+            bail!("apt source {:?} needs at least one suite", self.name);
+        }
+        let keys = [&self.key_url, &self.key_file, &self.signed_by]
+            .iter()
+            .filter(|k| k.is_some())
+            .count();
+        if keys > 1 {
+            bail!(
+                "apt source {:?}: only one of key_url, key_file and signed_by can be set",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Load the signing key. Armored keys are installed as .asc and binary keys as .gpg, both
+    /// of which apt accepts in Signed-By.
+    fn key(&self) -> Result<Option<(String, Vec<u8>)>> {
+        let key = if let Some(ref url) = self.key_url {
+            dl::fetch_bytes(url).map_err(|e| anyhow!("{}: failed to fetch key {}", e, url))?
+        } else if let Some(ref file) = self.key_file {
+            fs::read(file).map_err(|e| anyhow!("{}: failed to read key {:?}", e, file))?
+        } else {
+            return Ok(None);
+        };
+        let ext = if key.starts_with(b"-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+            "asc"
+        } else {
+            "gpg"
+        };
+        Ok(Some((
+            format!("{}/{}.{}", KEYRING_DIR, self.name, ext),
+            key,
+        )))
+    }
+
+    /// Render the .sources file.
+    pub(crate) fn sources(&self, signed_by: Option<&str>) -> String {
+        let mut out = String::new();
+        _ = writeln!(out, "Types: deb");
+        _ = writeln!(out, "URIs: {}", self.url);
+        _ = writeln!(out, "Suites: {}", self.suites.join(" "));
+        // Flat repositories, where the suite ends with a '/', have no components.
+        if !self.components.is_empty() {
+            _ = writeln!(out, "Components: {}", self.components.join(" "));
+        }
+        if let Some(ref arches) = self.architectures {
+            _ = writeln!(out, "Architectures: {}", arches.join(" "));
+        }
+        if let Some(path) = signed_by {
+            _ = writeln!(out, "Signed-By: {}", path);
+        }
+        out
+    }
+
+    /// Add the keyring and .sources file to the package.
+    pub(crate) fn add_files<W: Write>(&self, b: &mut DataBuilder<W>) -> Result<()> {
+        let key = self.key()?;
+        if let Some((ref path, ref key)) = key {
+            b.add_reader(path, key.as_slice(), key.len() as u64, 0o644)?;
+        }
+        let signed_by = key
+            .as_ref()
+            .map(|(path, _)| path.as_str())
+            .or(self.signed_by.as_deref());
+        let sources = self.sources(signed_by);
+        b.add_reader(
+            format!("{}/{}.sources", SOURCES_DIR, self.name),
+            sources.as_bytes(),
+            sources.len() as u64,
+            0o644,
+        )?;
+        Ok(())
+    }
+}
+
+/// Older specs wrote the suite as the first word of components.
+fn legacy_suite(suites: &mut Vec<String>, components: &mut Vec<String>) {
+    if suites.is_empty() && !components.is_empty() {
+        suites.push(components.remove(0));
+    }
+}
+
+/// A list written either as an array or as a space separated string.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ListRepr {
+    Words(String),
+    List(Vec<String>),
+}
+
+impl From<ListRepr> for Vec<String> {
+    fn from(value: ListRepr) -> Self {
+        match value {
+            ListRepr::Words(s) => s.split_whitespace().map(String::from).collect(),
+            ListRepr::List(l) => l,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourcesRepr {
+    name: String,
+    url: String,
+    suites: Option<ListRepr>,
+    components: Option<ListRepr>,
+    architectures: Option<ListRepr>,
+    key_url: Option<String>,
+    gpg_key_url: Option<String>,
+    key_file: Option<String>,
+    signed_by: Option<String>,
+}
+
+impl From<SourcesRepr> for AptSources {
+    fn from(value: SourcesRepr) -> Self {
+        let mut suites = value.suites.map(Vec::from).unwrap_or_default();
+        let mut components = value.components.map(Vec::from).unwrap_or_default();
+        legacy_suite(&mut suites, &mut components);
+        Self {
+            name: value.name,
+            url: value.url,
+            suites,
+            components,
+            architectures: value.architectures.map(Vec::from),
+            key_url: value.key_url.or(value.gpg_key_url),
+            key_file: value.key_file,
+            signed_by: value.signed_by,
+        }
+    }
+}
+
+/// Read a list that can be written either as a table or as a space separated string.
+fn get_list(t: &mlua::Table, key: &str, lua: &mlua::Lua) -> mlua::Result<Option<Vec<String>>> {
+    Ok(match t.get::<_, mlua::Value>(key)? {
+        mlua::Value::Nil => None,
+        mlua::Value::String(s) => Some(s.to_str()?.split_whitespace().map(String::from).collect()),
+        v => Some(Vec::<String>::from_lua(v, lua)?),
+    })
+}
+
+impl FromLua<'_> for AptSources {
+    fn from_lua(
+        value: mlua::prelude::LuaValue<'_>,
+        lua: &'_ mlua::prelude::Lua,
+    ) -> mlua::prelude::LuaResult<Self> {
+        let t = match value {
+            mlua::Value::Table(t) => t,
+            _ => {
+                return Err(mlua::Error::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: std::any::type_name::<Self>(),
+                    message: None,
+                })
+            }
+        };
+        let mut suites = get_list(&t, "suites", lua)?.unwrap_or_default();
+        let mut components = get_list(&t, "components", lua)?.unwrap_or_default();
+        legacy_suite(&mut suites, &mut components);
+        Ok(Self {
+            name: t.get("name")?,
+            url: t.get("url")?,
+            suites,
+            components,
+            architectures: get_list(&t, "architectures", lua)?,
+            key_url: match t.get::<_, Option<String>>("key_url")? {
+                Some(url) => Some(url),
+                None => t.get("gpg_key_url")?,
+            },
+            key_file: t.get("key_file")?,
+            signed_by: t.get("signed_by")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AptSources;
+    use crate::build::tests::{test_spec, write_test_deb};
+    use crate::build::BuildSpec;
+
+    #[test]
+    fn sources() {
+        let lua = mlua::Lua::new();
+        let legacy: AptSources = lua
+            .load(
+                r#"{
+                    name = "docker",
+                    url = "https://download.docker.com/linux/debian",
+                    components = "bookworm stable",
+                    gpg_key_url = "https://download.docker.com/linux/debian/gpg",
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(legacy.suites, vec!["bookworm"]);
+        assert_eq!(legacy.components, vec!["stable"]);
+        assert_eq!(
+            legacy.key_url.as_deref(),
+            Some("https://download.docker.com/linux/debian/gpg")
+        );
+        legacy.validate().unwrap();
+
+        let src: AptSources = lua
+            .load(
+                r#"{
+                    name = "example",
+                    url = "https://example.com/apt",
+                    suites = { "stable", "testing" },
+                    components = { "main", "contrib" },
+                    architectures = "amd64 arm64",
+                    signed_by = "/usr/share/keyrings/example.gpg",
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        src.validate().unwrap();
+        assert_eq!(
+            src.sources(src.signed_by.as_deref()),
+            "Types: deb\n\
+            URIs: https://example.com/apt\n\
+            Suites: stable testing\n\
+            Components: main contrib\n\
+            Architectures: amd64 arm64\n\
+            Signed-By: /usr/share/keyrings/example.gpg\n"
+        );
+
+        let both = AptSources {
+            key_file: Some("key.gpg".to_string()),
+            ..src.clone()
+        };
+        assert!(both.validate().is_err());
+        let no_suite = AptSources {
+            suites: Vec::new(),
+            ..src
+        };
+        assert!(no_suite.validate().is_err());
+    }
+
+    #[test]
+    fn package() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("key.asc");
+        std::fs::write(&key, "-----BEGIN PGP PUBLIC KEY BLOCK-----\n").unwrap();
+        let spec = BuildSpec {
+            apt_sources: Some(vec![AptSources {
+                name: "example".to_string(),
+                url: "https://example.com/apt".to_string(),
+                suites: vec!["stable".to_string()],
+                components: vec!["main".to_string()],
+                architectures: None,
+                key_url: None,
+                key_file: Some(key.to_string_lossy().to_string()),
+                signed_by: None,
+            }]),
+            ..test_spec("example-archive", "1.0", Vec::new())
+        };
+        let deb = write_test_deb(spec, &dir.path().join("dist"));
+        let sources = deb
+            .entry("/etc/apt/sources.list.d/example.sources")
+            .unwrap();
+        assert_eq!((sources.kind.as_str(), sources.mode), ("file", 0o644));
+        assert_eq!(
+            deb.entry("/usr/share/keyrings/example.asc").unwrap().size,
+            37
+        );
+        assert_eq!(
+            deb.control_files["conffiles"],
+            "/etc/apt/sources.list.d/example.sources\n"
+        );
+        assert!(deb.control_files["md5sums"].contains("  usr/share/keyrings/example.asc\n"));
+    }
+}
@@ -1,8 +1,14 @@
 use std::{
     cell::{Ref, RefCell, RefMut},
     fs,
-    io::{self, BufRead, BufWriter, Write},
+    io::{self, BufWriter, Read, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
 };
 
 use md5::{Digest, Md5};
@@ -13,12 +19,29 @@ use mlua::{
 use pax_derive;
 use pax_derive::{FromLua, IntoLua as PaxIntoLua};
 
+use crate::apt::AptSources;
+use crate::changelog::{self, ChangelogOpts};
+use crate::control;
+use crate::copyright::Copyright;
+use crate::dbgsym;
 use crate::deb::{self, MaintainerScripts};
-use crate::util::{mtime_now, to_io_err};
+use crate::manifest::Manifest;
+use crate::plan;
+use crate::sandbox;
+use crate::scripts::{Script, ScriptComposer};
+use crate::systemd::Service;
+use crate::testing;
+use crate::users::User;
+use crate::util::{log, mtime_now, to_io_err};
+use crate::watch;
 
 pub(crate) static DEFAULT_DIST: &str = "dist";
+static DBGSYM_DIR: &str = ".pax/dbgsym";
+static FORCE: AtomicBool = AtomicBool::new(false);
 
-#[derive(Default, Debug, Clone, FromLua, PaxIntoLua)]
+/// A debian package.
+#[derive(Default, Debug, Clone, FromLua, PaxIntoLua, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub(crate) struct BuildSpec {
     pub(crate) package: String,
     pub(crate) name: Option<String>,
@@ -30,7 +53,13 @@ pub(crate) struct BuildSpec {
     pub(crate) maintainer: Option<String>,
     pub(crate) homepage: Option<String>,
     #[lua_default(vec![])]
+    #[lua_type("(pax.File|string)[]")]
     pub(crate) files: Vec<File>,
+    /// Files outside /etc that dpkg should treat as conffiles. Everything installed under /etc
+    /// already is one.
+    pub(crate) conffiles: Option<Vec<String>>,
+    /// Packages that have to be configured before this one is unpacked.
+    pub(crate) pre_depends: Option<Vec<String>>,
     #[lua_default(vec![])]
     pub(crate) dependencies: Vec<String>,
     pub(crate) recommends: Option<Vec<String>>,
@@ -39,17 +68,130 @@ pub(crate) struct BuildSpec {
     pub(crate) breaks: Option<Vec<String>>,
     pub(crate) provides: Option<Vec<String>>,
     pub(crate) replaces: Option<Vec<String>>,
+    #[lua_default(deb::Priority::default())]
     pub(crate) priority: deb::Priority,
     #[lua_default("all".to_string())]
+    #[serde(default = "default_arch")]
     pub(crate) arch: String,
     pub(crate) urgency: Option<deb::Urgency>,
+    /// Archive section, such as "utils" or "devel".
     pub(crate) section: Option<String>,
+    /// Repositories installed as /etc/apt/sources.list.d/<name>.sources.
     pub(crate) apt_sources: Option<Vec<AptSources>>,
+    /// Maintainer scripts, or a directory containing them.
+    #[lua_type("pax.MaintainerScripts|string")]
     pub(crate) scripts: Option<MaintainerScripts>,
+    /// Generate /usr/share/doc/<package>/changelog.Debian.gz from git.
+    #[lua_type("pax.ChangelogOpts|string|boolean")]
+    pub(crate) changelog: Option<ChangelogOpts>,
+    /// Generate a machine-readable /usr/share/doc/<package>/copyright file.
+    pub(crate) copyright: Option<Copyright>,
+    /// Systemd units to install into /lib/systemd/system.
+    #[lua_type("(pax.Service|string)[]")]
+    pub(crate) services: Option<Vec<Service>>,
+    /// Accounts created before the package is unpacked.
+    #[lua_type("(pax.User|string)[]")]
+    pub(crate) users: Option<Vec<User>>,
+    /// Split debug info out of ELF binaries into a <package>-dbgsym package.
+    pub(crate) dbgsym: bool,
+    /// Compression of the data archive. Default is gzip.
+    #[lua_default(deb::Compression::default())]
+    pub(crate) compression: deb::Compression,
+    /// Threads used by xz and zstd compression, 0 uses one per CPU. Default is 1.
+    pub(crate) compression_threads: Option<u32>,
     #[ignored]
+    #[serde(skip)]
     pub(crate) buildno: Option<u32>,
 }
 
+fn default_arch() -> String {
+    "all".to_string()
+}
+
+#[derive(Clone, Debug, Default, pax_derive::FromLuaTable)]
+pub(crate) struct BuildAllOpts {
+    /// Number of packages built at the same time. Default is the value of --jobs or one per CPU.
+    pub jobs: Option<usize>,
+}
+
+impl mlua::FromLua<'_> for BuildAllOpts {
+    fn from_lua(value: LuaValue<'_>, lua: &'_ Lua) -> LuaResult<Self> {
+        use mlua::Value;
+        match value {
+            Value::Nil => Ok(Self::default()),
+            Value::Table(t) => Self::from_lua_table(t, lua),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: std::any::type_name::<Self>(),
+                message: None,
+            }),
+        }
+    }
+}
+
+/// Build every spec using a pool of `jobs` threads. All of the packages are attempted and the
+/// errors from every package that failed are reported together.
+///
+/// During a dry run or while `pax test` mocks are active the packages are built one at a time on
+/// the calling thread, since both only cover the thread that turned them on. The sandbox policy
+/// is handed on to every worker.
+pub(crate) fn build_all<'a, P>(
+    specs: impl IntoIterator<Item = &'a mut BuildSpec>,
+    dir: P,
+    jobs: usize,
+) -> io::Result<()>
+where
+    P: AsRef<Path> + Sync,
+{
+    let queue: Vec<_> = specs.into_iter().map(Mutex::new).collect();
+    let total = queue.len();
+    let next = AtomicUsize::new(0);
+    let errors = Mutex::new(Vec::new());
+    let policy = sandbox::policy();
+    thread::scope(|s| {
+        let work = || {
+            sandbox::enable(policy.clone());
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(spec) = queue.get(i) else {
+                    break;
+                };
+                let mut spec = spec.lock().unwrap();
+                let start = Instant::now();
+                log(format!("[{}] building", spec.package));
+                match spec.build(dir.as_ref()) {
+                    Ok(_) => log(format!(
+                        "[{}] finished in {:.1?}",
+                        spec.package,
+                        start.elapsed()
+                    )),
+                    Err(e) => {
+                        log(format!("[{}] failed: {}", spec.package, e));
+                        errors.lock().unwrap().push((i, spec.package.clone(), e));
+                    }
+                }
+            }
+        };
+        if plan::dry_run() || testing::active() {
+            work();
+            return;
+        }
+        for _ in 0..jobs.clamp(1, total.max(1)) {
+            s.spawn(work);
+        }
+    });
+    let mut errors = errors.into_inner().unwrap();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    errors.sort_by_key(|(i, _, _)| *i);
+    let mut msg = format!("{} of {} packages failed to build:", errors.len(), total);
+    for (_, package, e) in errors {
+        msg.push_str(&format!("\n  {}: {}", package, e));
+    }
+    Err(to_io_err(msg))
+}
+
 macro_rules! tar_header {
     ($path:expr, $mtime:expr, $size:expr) => {
         tar_header!($path, $mtime, 0o644, $size)
@@ -99,17 +241,9 @@ impl BuildSpec {
         }
         writeln!(w, "Priority: {}", priority)?;
         writeln!(w, "Architecture: {}", self.arch)?;
-        if let Some(maintainer) = &self.maintainer {
-            writeln!(w, "Maintainer: {}", maintainer)?;
-        } else {
-            match (&self.author, &self.email) {
-                (Some(author), Some(email)) => writeln!(w, "Maintainer: {} <{}>", author, email)?,
-                (Some(author), None) => writeln!(w, "Maintainer: {}", author)?,
-                (None, Some(email)) => writeln!(w, "Maintainer: {}", email)?,
-                (None, None) => {
-                    return Err(to_io_err("could not build maintainer"));
-                }
-            };
+        match self.maintainer() {
+            Some(maintainer) => writeln!(w, "Maintainer: {}", maintainer)?,
+            None => return Err(to_io_err("could not build maintainer")),
         }
 
         if let Some(urgency) = self.urgency {
@@ -126,11 +260,16 @@ impl BuildSpec {
         if self.essential {
             writeln!(w, "Essential: yes")?;
         }
+        if let Some(pre_depends) = &self.pre_depends {
+            if !pre_depends.is_empty() {
+                writeln!(w, "Pre-Depends: {}", pre_depends.join(", "))?;
+            }
+        }
         if !self.dependencies.is_empty() {
             writeln!(w, "Depends: {}", self.dependencies.join(", "))?;
         }
         if let Some(desc) = &self.description {
-            writeln!(w, "Description: {}", desc)?;
+            control::write_multiline(w, "Description", desc)?;
         }
 
         if let Some(recommends) = &self.recommends {
@@ -166,6 +305,19 @@ impl BuildSpec {
         Ok(())
     }
 
+    /// The Maintainer field, either given explicitly or built from the author and email.
+    pub(crate) fn maintainer(&self) -> Option<String> {
+        if let Some(maintainer) = &self.maintainer {
+            return Some(maintainer.clone());
+        }
+        match (&self.author, &self.email) {
+            (Some(author), Some(email)) => Some(format!("{} <{}>", author, email)),
+            (Some(author), None) => Some(author.clone()),
+            (None, Some(email)) => Some(email.clone()),
+            (None, None) => None,
+        }
+    }
+
     pub(crate) fn pre_process(&mut self, base: Option<String>) -> io::Result<()> {
         if let Some(base) = base {
             let dst = Path::new(&base);
@@ -188,55 +340,167 @@ impl BuildSpec {
         Ok(())
     }
 
-    pub(crate) fn build<P>(&mut self, dir: P) -> io::Result<()>
+    /// Build the package into `dir`, unless the manifest recorded by the last build shows that
+    /// none of the inputs changed. Returns false when the package was already up to date.
+    pub(crate) fn build<P>(&mut self, dir: P) -> io::Result<bool>
     where
         P: AsRef<std::path::Path>,
     {
+        self.validate()?;
+        self.files.sort_by_key(|f| f.dst.clone());
+        let path = dir.as_ref().join(self.filename());
+        sandbox::check_write("package", &path)?;
+        if plan::dry_run() {
+            plan::add_package(self, &path)?;
+            return Ok(true);
+        }
+        let manifest_path = Manifest::path(dir.as_ref(), self);
+        let mut manifest = Manifest::new(self)?;
+        self.track_inputs();
+        if !FORCE.load(Ordering::Relaxed) {
+            if let Some(previous) = Manifest::load(&manifest_path).filter(|m| manifest.is_fresh(m))
+            {
+                for output in previous.outputs.keys() {
+                    log(format!("{} is up to date", output));
+                }
+                return Ok(false);
+            }
+        }
+        let outputs = self.write_package(dir.as_ref())?;
+        manifest.set_outputs(&outputs)?;
+        manifest.save(&manifest_path)?;
+        Ok(true)
+    }
+
+    /// Let watch mode know about every file the package is built from.
+    fn track_inputs(&self) {
+        for file in self.files.iter().filter(|f| !f.src.is_empty()) {
+            watch::track(&file.src);
+        }
+        for service in self.services.iter().flatten() {
+            watch::track(&service.unit);
+        }
+        for key in self.apt_sources.iter().flatten().flat_map(|s| &s.key_file) {
+            watch::track(key);
+        }
+    }
+
+    /// Always rebuild packages, even when their manifest says they are up to date.
+    pub(crate) fn force_rebuild(force: bool) {
+        FORCE.store(force, Ordering::Relaxed);
+    }
+
+    /// Write the package, and its dbgsym package when enabled, returning the paths written.
+    pub(crate) fn write_package(&mut self, dir: &Path) -> io::Result<Vec<PathBuf>> {
         use flate2::write::GzEncoder;
         use flate2::Compression;
         use std::os::unix::fs::OpenOptionsExt; // adds .mode() to File::options
 
-        self.validate()?;
-        let path = dir.as_ref().join(self.filename());
+        let path = dir.join(self.filename());
         let package_file = fs::File::options()
             .create(true)
             .truncate(true)
             .write(true)
             .mode(0o666)
-            .open(path)?;
+            .open(&path)?;
         let now = mtime_now();
         let mut archive = deb::DebArchive::new(BufWriter::new(package_file), now);
         archive.init()?;
 
+        // The control tarball needs the size and hashes of the data, so the data is compressed
+        // into a temporary file first to keep memory use independent of the package size.
         let mut ctrl_buf = vec![];
-        let mut data_buf = vec![];
         let ctrl_enc = GzEncoder::new(&mut ctrl_buf, Compression::default());
-        let data_enc = GzEncoder::new(&mut data_buf, Compression::default());
+        let data_file = tempfile::tempfile_in(dir)?;
+        let data_enc = self.compression.encoder(
+            BufWriter::new(data_file),
+            self.compression_threads.unwrap_or(1),
+        )?;
 
         let mut hashes = Vec::with_capacity(self.files.len());
-        let size = {
+        let mut splits = Vec::new();
+        let dbgsym_dir = Path::new(DBGSYM_DIR).join(&self.package);
+        let (size, mut data_file) = {
             let mut b = deb::DataBuilder::new(data_enc, &mut hashes);
-            let files = &mut self.files;
-            files.sort_by_key(|f| f.dst.clone());
-            for file in files {
-                if let Some(ref dir) = file.dir {
+            for file in &self.files {
+                b.set_owner(file.owner.as_deref(), file.group.as_deref());
+                if let Some(ref target) = file.link {
+                    b.add_symlink(&file.dst, target)?;
+                } else if let Some(ref dir) = file.dir {
                     b.add_dir(dir, file.mode.unwrap_or(0o755))?;
                 } else if file.src.len() == 0 {
                     b.add_dir(&file.dst, file.mode.unwrap_or(0o755))?;
                 } else {
+                    if self.dbgsym {
+                        let split =
+                            dbgsym::split_all(Path::new(&file.src), &dbgsym_dir).map_err(|e| {
+                                to_io_err(format!(
+                                    "{}: failed to split debug info from {:?}",
+                                    e, file.src
+                                ))
+                            })?;
+                        for (path, split) in split {
+                            b.read_from(path, split.stripped.clone());
+                            splits.push(split);
+                        }
+                    }
                     b.add_path(&file.src, &file.dst, file.mode).map_err(|e| {
                         io::Error::new(e.kind(), format!("{}: failed to add file to archive", e))
                     })?;
                 }
             }
-            b.size()
+            b.set_owner(None, None);
+            for service in self.services.iter().flatten() {
+                b.add_path(&service.unit, service.dst()?, Some(0o644))
+                    .map_err(|e| {
+                        io::Error::new(e.kind(), format!("{}: failed to add unit file", e))
+                    })?;
+            }
+            for source in self.apt_sources.iter().flatten() {
+                source.add_files(&mut b).map_err(|e| {
+                    to_io_err(format!("{}: failed to add apt source {:?}", e, source.name))
+                })?;
+            }
+            if let Some(ref opts) = self.changelog {
+                let log = changelog::generate(self, opts)
+                    .map_err(|e| to_io_err(format!("{}: failed to generate changelog", e)))?;
+                b.add_reader(
+                    format!("/usr/share/doc/{}/changelog.Debian.gz", self.package),
+                    log.as_slice(),
+                    log.len() as u64,
+                    0o644,
+                )?;
+            }
+            if let Some(ref copyright) = self.copyright {
+                let text = copyright
+                    .generate(self)
+                    .map_err(|e| to_io_err(format!("{}: failed to generate copyright file", e)))?;
+                b.add_reader(
+                    format!("/usr/share/doc/{}/copyright", self.package),
+                    text.as_slice(),
+                    text.len() as u64,
+                    0o644,
+                )?;
+            }
+            let size = b.size();
+            let data_file = b
+                .into_inner()?
+                .finish()?
+                .into_inner()
+                .map_err(|e| e.into_error())?;
+            (size, data_file)
         };
         self.control_tarball(ctrl_enc, DataMetadata { size, hashes })?;
         // The order that these are inserted into the archive matters. The wrong order will break
         // the installation.
         archive.append_vec("control.tar.gz", ctrl_buf)?;
-        archive.append_vec("data.tar.gz", data_buf)?;
-        Ok(())
+        archive.append_file(self.compression.data_member(), &mut data_file)?;
+        watch::built(path.to_string_lossy());
+        let mut outputs = vec![path];
+        if !splits.is_empty() {
+            outputs.extend(dbgsym::package(self, &splits).write_package(dir)?);
+        }
+        Ok(outputs)
     }
 
     fn control_tarball<W: io::Write>(&self, w: W, data: DataMetadata) -> io::Result<()> {
@@ -248,6 +512,23 @@ impl BuildSpec {
             &tar_header!("control", now, control_buf.len()),
             control_buf.as_slice(),
         )?;
+        // dpkg keeps the admin's changes to conffiles on upgrade and only removes them on purge.
+        // Like debhelper, every file installed under /etc is one, along with the files listed in
+        // the spec.
+        let extra = self.conffiles.as_deref().unwrap_or_default();
+        let conffiles: String = data
+            .hashes
+            .iter()
+            .map(|(_, path)| format!("/{}", path.to_string_lossy()))
+            .filter(|path| path.starts_with("/etc/") || extra.contains(path))
+            .map(|path| path + "\n")
+            .collect();
+        if !conffiles.is_empty() {
+            ball.append(
+                &tar_header!("conffiles", now, conffiles.len()),
+                conffiles.as_bytes(),
+            )?;
+        }
         let mut md5sum_buf: Vec<u8> =
             Vec::with_capacity(self.files.len() * (Md5::output_size() + 2));
         let mut hex_buf: [u8; MD5_LEN * 2] = [0; MD5_LEN * 2];
@@ -264,83 +545,57 @@ impl BuildSpec {
             md5sum_buf.as_slice(),
         )?;
 
-        if let Some(ref sources) = self.apt_sources {
-            let mut preinst = Vec::new();
-            let mut postrm = Vec::new();
-            preinst.write("#!/bin/sh\nset -eu\nmkdir -p /usr/share/keyrings/\n".as_bytes())?;
-            postrm.write("#!/bin/sh\nset -eu\n".as_bytes())?;
-            for s in sources {
-                preinst.write(
-                    format!(
-                        "sudo wget -q -O '/usr/share/keyrings/{name}.gpg' '{}'\n\
-                        sudo chmod a+r /usr/share/keyrings/{name}.gpg\n",
-                        s.gpg_key_url,
-                        name = s.name,
-                    )
-                    .as_bytes(),
-                )?;
-                preinst.write(
-                    format!(
-                        "echo \"deb [signed-by=/usr/share/keyrings/{name}.gpg arch=$(dpkg --print-architecture)] \
-                        {} {}\" | sudo tee /etc/apt/sources.list.d/{name}.list\n", s.url, s.components, name=s.name)
-                    .as_bytes(),
-                )?;
-                postrm.write(format!("rm -f /usr/share/keyrings/{name}.gpg /etc/apt/sources.list.d/{name}.list\n", name=s.name).as_bytes())?;
-            }
-            ball.append(
-                &tar_header!("preinst", now, 0o755, preinst.len()),
-                preinst.as_slice(),
-            )?;
-            ball.append(
-                &tar_header!("postrm", now, 0o755, postrm.len()),
-                postrm.as_slice(),
-            )?;
-        } else if let Some(ref scripts) = self.scripts {
-            if let Some(ref preinst) = scripts.preinst {
+        let composer = self.script_composer()?;
+        let user = self.scripts.clone().unwrap_or_default();
+        for script in Script::ALL {
+            if let Some(body) = composer.compose(script, user.get(script)) {
                 ball.append(
-                    &tar_header!("preinst", now, 0o755, preinst.len()),
-                    preinst.trim().as_bytes(),
-                )?;
-            }
-            if let Some(ref postinst) = scripts.postinst {
-                ball.append(
-                    &tar_header!("postinst", now, 0o755, postinst.len()),
-                    postinst.trim().as_bytes(),
-                )?;
-            }
-            if let Some(ref prerm) = scripts.prerm {
-                ball.append(
-                    &tar_header!("prerm", now, 0o755, prerm.len()),
-                    prerm.trim().as_bytes(),
-                )?;
-            }
-            if let Some(ref postrm) = scripts.postrm {
-                ball.append(
-                    &tar_header!("postrm", now, 0o755, postrm.len()),
-                    postrm.trim().as_bytes(),
+                    &tar_header!(script.name(), now, 0o755, body.len()),
+                    body.as_bytes(),
                 )?;
             }
         }
         Ok(())
     }
 
-    fn filename(&self) -> String {
+    /// Collect the maintainer script snippets needed by each feature used in the spec.
+    pub(crate) fn script_composer(&self) -> io::Result<ScriptComposer> {
+        let mut composer = ScriptComposer::default();
+        for user in self.users.iter().flatten() {
+            user.add_scripts(&mut composer);
+        }
+        for service in self.services.iter().flatten() {
+            service.add_scripts(&mut composer)?;
+        }
+        Ok(composer)
+    }
+
+    pub(crate) fn filename(&self) -> String {
         format!("{}-v{}_{}.deb", self.package, self.version(), self.arch)
     }
 
-    fn version(&self) -> String {
+    pub(crate) fn version(&self) -> String {
         match self.buildno {
             Some(n) if n > 0 => format!("{}-{}", self.version, n),
             _ => self.version.clone(),
         }
     }
 
-    fn validate(&self) -> io::Result<()> {
+    pub(crate) fn validate(&self) -> io::Result<()> {
         if self.maintainer.is_none() && self.author.is_none() && self.email.is_none() {
             return Err(to_io_err(
                 "need author and email to infer Maintainer attribute",
             ));
         }
+        for service in self.services.iter().flatten() {
+            service.validate()?;
+        }
+        for user in self.users.iter().flatten() {
+            user.validate()?;
+        }
+        for source in self.apt_sources.iter().flatten() {
+            source.validate().map_err(to_io_err)?;
+        }
         Ok(())
     }
 
@@ -402,6 +657,8 @@ impl BuildSpec {
             author: overrides.get("author")?,
             email: overrides.get("email")?,
             essential: overrides.get("essential")?,
+            conffiles: fill_from!(overrides, "conffiles", None),
+            pre_depends: fill_from!(overrides, "pre_depends", None),
             dependencies: fill_from!(overrides, "dependencies", Vec::new()),
             recommends: fill_from!(overrides, "recommends", None),
             suggests: fill_from!(overrides, "suggests", None),
@@ -414,27 +671,90 @@ impl BuildSpec {
             section: overrides.get("section")?,
             apt_sources: overrides.get("apt_sources")?,
             scripts: overrides.get("scripts").ok(),
+            changelog: overrides.get("changelog")?,
+            copyright: overrides.get("copyright")?,
+            services: overrides.get("services")?,
+            users: overrides.get("users")?,
+            dbgsym: overrides.get("dbgsym")?,
+            compression: overrides.get("compression")?,
+            compression_threads: overrides.get("compression_threads")?,
             buildno: None,
         })
     }
 
     pub(crate) fn parse<R: io::Read>(r: R) -> anyhow::Result<Self> {
-        let mut s = Self::default();
-        let buf = io::BufReader::new(r);
-        for line in buf.lines().map_while(Result::ok) {
-            if let Some(ix) = line.find(':') {
-                let (key, val) = line.split_at(ix);
-                match key.trim().to_lowercase().as_ref() {
-                    "package" => s.package = String::from(val.trim()),
-                    "version" => s.version = String::from(val.trim()),
-                    "maintainer" => s.maintainer = Some(String::from(val.trim())),
-                    // "urgency" => s.urgency = Some(val.trim().into()),
-                    "homepage" => s.homepage = Some(String::from(val.trim())),
-                    _ => {}
-                };
-            }
-        }
-        todo!()
+        let mut text = String::new();
+        io::BufReader::new(r).read_to_string(&mut text)?;
+        let paragraph = control::parse(&text)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("no control paragraph found"))?;
+        Self::from_paragraph(&paragraph)
+    }
+
+    pub(crate) fn from_paragraph(p: &control::Paragraph) -> anyhow::Result<Self> {
+        let get = |key: &str| p.get(key).map(String::from);
+        let package = get("Package").ok_or_else(|| anyhow::anyhow!("missing Package field"))?;
+        let version = get("Version").ok_or_else(|| anyhow::anyhow!("missing Version field"))?;
+        let maintainer = get("Maintainer");
+        let (author, email) = match maintainer.as_deref().map(split_maintainer) {
+            Some((author, email)) => (author, email),
+            None => (None, None),
+        };
+        let non_empty = |l: Option<Vec<String>>| l.filter(|l| !l.is_empty());
+        Ok(Self {
+            name: Some(package.clone()),
+            package,
+            version,
+            description: p.get_multiline("Description"),
+            essential: p
+                .get("Essential")
+                .is_some_and(|e| e.eq_ignore_ascii_case("yes")),
+            author,
+            email,
+            maintainer,
+            homepage: get("Homepage"),
+            files: Vec::new(),
+            conffiles: None,
+            pre_depends: non_empty(p.get_list("Pre-Depends")),
+            dependencies: p.get_list("Depends").unwrap_or_default(),
+            recommends: non_empty(p.get_list("Recommends")),
+            suggests: non_empty(p.get_list("Suggests")),
+            conflicts: non_empty(p.get_list("Conflicts")),
+            breaks: non_empty(p.get_list("Breaks")),
+            provides: non_empty(p.get_list("Provides")),
+            replaces: non_empty(p.get_list("Replaces")),
+            priority: match p.get("Priority") {
+                Some(priority) => priority.parse()?,
+                None => deb::Priority::default(),
+            },
+            arch: get("Architecture").unwrap_or("all".to_string()),
+            urgency: p.get("Urgency").map(str::parse).transpose()?,
+            section: get("Section"),
+            apt_sources: None,
+            scripts: None,
+            changelog: None,
+            copyright: None,
+            services: None,
+            users: None,
+            dbgsym: false,
+            compression: deb::Compression::default(),
+            compression_threads: None,
+            buildno: None,
+        })
+    }
+}
+
+/// Split a "Name <email>" maintainer string into its name and email parts.
+fn split_maintainer(maintainer: &str) -> (Option<String>, Option<String>) {
+    let non_empty = |s: &str| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+    match maintainer.split_once('<') {
+        Some((name, rest)) => (
+            non_empty(name),
+            non_empty(rest.split_once('>').map_or(rest, |(email, _)| email)),
+        ),
+        None if maintainer.contains('@') => (None, non_empty(maintainer)),
+        None => (non_empty(maintainer), None),
     }
 }
 
@@ -472,12 +792,19 @@ impl mlua::FromLua<'_> for RefCellBuildSpec {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PaxIntoLua)]
+#[derive(Clone, Debug, PartialEq, Eq, PaxIntoLua, serde::Deserialize)]
+#[serde(from = "FileRepr")]
 pub(crate) struct File {
     pub src: String,
     pub dst: String,
     pub mode: Option<u32>,
     pub dir: Option<String>,
+    /// Owner of the installed file, e.g. an account from `BuildSpec::users`. Default is root.
+    pub owner: Option<String>,
+    /// Group of the installed file. Default is root.
+    pub group: Option<String>,
+    /// Install a symlink to this target at `dst` instead of copying `src`.
+    pub link: Option<String>,
 }
 
 impl File {
@@ -487,6 +814,17 @@ impl File {
             dst: String::from(dst.as_ref()),
             mode: None,
             dir: None,
+            owner: None,
+            group: None,
+            link: None,
+        }
+    }
+
+    /// A symlink at `dst` pointing to `target`.
+    pub fn symlink<S: AsRef<str>>(dst: S, target: S) -> Self {
+        Self {
+            link: Some(String::from(target.as_ref())),
+            ..Self::new("", dst.as_ref())
         }
     }
 
@@ -507,6 +845,9 @@ impl File {
             dst,
             mode: Some(mode),
             dir: None,
+            owner: None,
+            group: None,
+            link: None,
         })
     }
 }
@@ -515,12 +856,22 @@ impl mlua::FromLua<'_> for File {
     fn from_lua(value: LuaValue<'_>, _lua: &'_ Lua) -> LuaResult<Self> {
         use mlua::Value as V;
         match value {
-            V::Table(tbl) => Ok(Self {
-                src: tbl.get("src")?,
-                dst: tbl.get("dst")?,
-                mode: tbl.get("mode").ok(),
-                dir: None,
-            }),
+            V::Table(tbl) => {
+                let link: Option<String> = tbl.get("link")?;
+                Ok(Self {
+                    // A link has nothing to copy.
+                    src: match link {
+                        Some(_) => tbl.get::<_, Option<String>>("src")?.unwrap_or_default(),
+                        None => tbl.get("src")?,
+                    },
+                    dst: tbl.get("dst")?,
+                    mode: tbl.get("mode").ok(),
+                    dir: None,
+                    owner: tbl.get("owner")?,
+                    group: tbl.get("group")?,
+                    link,
+                })
+            }
             V::String(src) => {
                 let s = src.to_str()?;
                 if let Some((src, dst)) = s.split_once(':') {
@@ -534,6 +885,74 @@ impl mlua::FromLua<'_> for File {
     }
 }
 
+/// A file in a spec file, either "src:dst" or a table like in Lua.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum FileRepr {
+    Short(String),
+    Table(FileTable),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileTable {
+    #[serde(default)]
+    src: String,
+    #[serde(default)]
+    dst: String,
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    mode: Option<u32>,
+    owner: Option<String>,
+    group: Option<String>,
+    link: Option<String>,
+}
+
+impl From<FileRepr> for File {
+    fn from(value: FileRepr) -> Self {
+        match value {
+            FileRepr::Short(s) => match s.split_once(':') {
+                Some((src, dst)) => Self::new(src, dst),
+                None => Self::new(s.as_str(), ""),
+            },
+            FileRepr::Table(FileTable {
+                src,
+                dst,
+                mode,
+                owner,
+                group,
+                link,
+            }) => Self {
+                src,
+                dst,
+                mode,
+                dir: None,
+                owner,
+                group,
+                link,
+            },
+        }
+    }
+}
+
+/// JSON has no octal numbers so modes can also be written as strings like "0755".
+fn deserialize_mode<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<u32>, D::Error> {
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Mode {
+        Number(u32),
+        Octal(String),
+    }
+    match Option::<Mode>::deserialize(d)? {
+        None => Ok(None),
+        Some(Mode::Number(n)) => Ok(Some(n)),
+        Some(Mode::Octal(s)) => u32::from_str_radix(s.trim_start_matches("0o"), 8)
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("invalid mode {:?}: {}", s, e))),
+    }
+}
+
 impl<T: AsRef<Path>> TryFrom<(T, T)> for File {
     type Error = io::Error;
     fn try_from(value: (T, T)) -> Result<Self, Self::Error> {
@@ -557,6 +976,9 @@ impl<T: AsRef<Path>> TryFrom<(T, T, u32)> for File {
             dst,
             mode: Some(value.2),
             dir: None,
+            owner: None,
+            group: None,
+            link: None,
         })
     }
 }
@@ -651,14 +1073,6 @@ impl mlua::IntoLua<'_> for Arch {
     }
 }
 
-#[derive(Clone, Debug, pax_derive::IntoLua, pax_derive::FromLua)]
-pub(crate) struct AptSources {
-    name: String,
-    url: String,
-    components: String,
-    gpg_key_url: String,
-}
-
 fn toml_get_str(t: &toml::Value, key: &str) -> io::Result<String> {
     match t.get(key) {
         None => Err(io::Error::new(
@@ -676,14 +1090,102 @@ fn toml_get_str(t: &toml::Value, key: &str) -> io::Result<String> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use std::{
         fs,
-        io::{self, Read},
-        path::PathBuf,
+        io::Read,
+        path::{Path, PathBuf},
     };
 
-    use super::Arch;
+    use super::{build_all, Arch, BuildSpec, File};
+    use crate::deb::{Priority, Urgency};
+    use crate::debfile::DebFile;
+
+    /// A spec for an amd64 test package with the fields every package needs.
+    pub(crate) fn test_spec(package: &str, version: &str, files: Vec<File>) -> BuildSpec {
+        BuildSpec {
+            package: package.to_string(),
+            version: version.to_string(),
+            arch: "amd64".to_string(),
+            maintainer: Some("Jo <jo@example.com>".to_string()),
+            description: Some("A test package".to_string()),
+            files,
+            ..Default::default()
+        }
+    }
+
+    /// Build `spec` into `dir` and read the package back.
+    pub(crate) fn write_test_deb(mut spec: BuildSpec, dir: &Path) -> DebFile {
+        spec.pre_process(None).unwrap();
+        fs::create_dir_all(dir).unwrap();
+        spec.write_package(dir).unwrap();
+        DebFile::open(dir.join(spec.filename())).unwrap()
+    }
+
+    #[test]
+    fn build_all_errors() {
+        // Without an author or maintainer every package fails validation.
+        let spec = |package: &str| BuildSpec {
+            package: package.to_string(),
+            version: "1.0".to_string(),
+            ..Default::default()
+        };
+        let mut specs = vec![spec("one"), spec("two"), spec("three")];
+        let dir = tempfile::tempdir().unwrap();
+        let err = build_all(&mut specs, dir.path(), 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "3 of 3 packages failed to build:\n  \
+            one: need author and email to infer Maintainer attribute\n  \
+            two: need author and email to infer Maintainer attribute\n  \
+            three: need author and email to infer Maintainer attribute"
+        );
+    }
+
+    #[test]
+    fn control_round_trip() {
+        let spec = BuildSpec {
+            package: "pax".to_string(),
+            version: "1.2.3-1".to_string(),
+            description: Some("short\nlonger description\n\nsecond paragraph".to_string()),
+            essential: true,
+            author: Some("Jerry".to_string()),
+            email: Some("jerry@jerry.se".to_string()),
+            homepage: Some("https://example.com".to_string()),
+            pre_depends: Some(vec!["init-system-helpers (>= 1.54~)".to_string()]),
+            dependencies: vec!["libc6 (>= 2.34)".to_string(), "git".to_string()],
+            recommends: Some(vec!["scdoc".to_string()]),
+            conflicts: Some(vec!["old-pax".to_string()]),
+            priority: Priority::Important,
+            arch: "amd64".to_string(),
+            urgency: Some(Urgency::High),
+            section: Some("devel".to_string()),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        spec.generate_control(&mut buf, 2048).unwrap();
+        let parsed = BuildSpec::parse(buf.as_slice()).unwrap();
+        assert_eq!(parsed.package, spec.package);
+        assert_eq!(parsed.version, spec.version);
+        assert_eq!(parsed.description, spec.description);
+        assert_eq!(parsed.essential, spec.essential);
+        assert_eq!(
+            parsed.maintainer,
+            Some("Jerry <jerry@jerry.se>".to_string())
+        );
+        assert_eq!(parsed.author, spec.author);
+        assert_eq!(parsed.email, spec.email);
+        assert_eq!(parsed.homepage, spec.homepage);
+        assert_eq!(parsed.pre_depends, spec.pre_depends);
+        assert_eq!(parsed.dependencies, spec.dependencies);
+        assert_eq!(parsed.recommends, spec.recommends);
+        assert_eq!(parsed.conflicts, spec.conflicts);
+        assert_eq!(parsed.suggests, None);
+        assert_eq!(parsed.priority, spec.priority);
+        assert_eq!(parsed.arch, spec.arch);
+        assert_eq!(parsed.urgency, spec.urgency);
+        assert_eq!(parsed.section, spec.section);
+    }
 
     #[test]
     fn arch() {
@@ -0,0 +1,349 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, TimeZone};
+use flate2::{Compression, GzBuilder};
+
+use crate::build::BuildSpec;
+use crate::deb::{Urgency, Version};
+
+static DEFAULT_DISTRIBUTION: &str = "unstable";
+
+#[derive(
+    Clone, Debug, Default, pax_derive::FromLuaTable, pax_derive::IntoLua, serde::Deserialize,
+)]
+#[serde(try_from = "OptsRepr")]
+pub(crate) struct ChangelogOpts {
+    /// Path to the git repository. Default is the current directory.
+    pub repo: Option<String>,
+    /// Distribution written into each entry header. Default is "unstable".
+    pub distribution: Option<String>,
+    /// Only include the most recent entries.
+    pub max_entries: Option<usize>,
+    /// Include merge commits in the list of changes.
+    pub merges: bool,
+}
+
+/// `true`, a repository path or a table, the same as in Lua.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum OptsRepr {
+    Enabled(bool),
+    Repo(String),
+    Table(OptsTable),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OptsTable {
+    repo: Option<String>,
+    distribution: Option<String>,
+    max_entries: Option<usize>,
+    #[serde(default)]
+    merges: bool,
+}
+
+impl TryFrom<OptsRepr> for ChangelogOpts {
+    type Error = String;
+    fn try_from(value: OptsRepr) -> Result<Self, Self::Error> {
+        match value {
+            OptsRepr::Enabled(true) => Ok(Self::default()),
+            OptsRepr::Enabled(false) => {
+                Err("changelog can't be false, leave it out to skip the changelog".to_string())
+            }
+            OptsRepr::Repo(repo) => Ok(Self {
+                repo: Some(repo),
+                ..Default::default()
+            }),
+            OptsRepr::Table(OptsTable {
+                repo,
+                distribution,
+                max_entries,
+                merges,
+            }) => Ok(Self {
+                repo,
+                distribution,
+                max_entries,
+                merges,
+            }),
+        }
+    }
+}
+
+impl mlua::FromLua<'_> for ChangelogOpts {
+    fn from_lua(
+        value: mlua::prelude::LuaValue<'_>,
+        lua: &'_ mlua::prelude::Lua,
+    ) -> mlua::prelude::LuaResult<Self> {
+        use mlua::Value;
+        match value {
+            Value::Nil => Ok(Self::default()),
+            Value::Boolean(true) => Ok(Self::default()),
+            Value::String(s) => Ok(Self {
+                repo: Some(s.to_str()?.to_string()),
+                ..Default::default()
+            }),
+            Value::Table(t) => Self::from_lua_table(t, lua),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: std::any::type_name::<Self>(),
+                message: None,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Entry {
+    pub package: String,
+    pub version: String,
+    pub distribution: String,
+    pub urgency: Urgency,
+    pub changes: Vec<String>,
+    pub maintainer: String,
+    pub date: DateTime<FixedOffset>,
+}
+
+impl Entry {
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let urgency: &str = self.urgency.into();
+        writeln!(
+            w,
+            "{} ({}) {}; urgency={}",
+            self.package, self.version, self.distribution, urgency
+        )?;
+        writeln!(w)?;
+        for change in &self.changes {
+            writeln!(w, "  * {}", change)?;
+        }
+        writeln!(w)?;
+        writeln!(
+            w,
+            " -- {}  {}",
+            self.maintainer,
+            self.date.format("%a, %d %b %Y %H:%M:%S %z")
+        )
+    }
+}
+
+/// Write all entries, newest first, separated by a blank line.
+pub(crate) fn write_entries<W: Write>(w: &mut W, entries: &[Entry]) -> io::Result<()> {
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            writeln!(w)?;
+        }
+        entry.write(w)?;
+    }
+    Ok(())
+}
+
+/// Build changelog entries from the git history. Every tag that parses as a version starts a new
+/// entry and commits made after the latest tag are listed under the spec's own version.
+pub(crate) fn from_git(spec: &BuildSpec, opts: &ChangelogOpts) -> Result<Vec<Entry>> {
+    let repo = git2::Repository::discover(opts.repo.as_deref().unwrap_or("."))?;
+    let maintainer = spec
+        .maintainer()
+        .ok_or_else(|| anyhow!("need a maintainer to generate a changelog"))?;
+    let distribution = opts
+        .distribution
+        .clone()
+        .unwrap_or(DEFAULT_DISTRIBUTION.to_string());
+
+    let mut tags = HashMap::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        let version = name.strip_prefix('v').unwrap_or(name);
+        if Version::try_from(version).is_err() {
+            continue;
+        }
+        let commit = repo
+            .revparse_single(&format!("refs/tags/{}", name))?
+            .peel_to_commit()?;
+        tags.insert(commit.id(), version.to_string());
+    }
+
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+    let mut entries: Vec<Entry> = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let tagged = tags.get(&commit.id());
+        if entries.is_empty() || tagged.is_some() {
+            if let Some(max) = opts.max_entries {
+                if entries.len() >= max {
+                    break;
+                }
+            }
+            entries.push(Entry {
+                package: spec.package.clone(),
+                // The top entry is the package being built, even when HEAD is tagged.
+                version: match tagged {
+                    Some(version) if !entries.is_empty() => version.clone(),
+                    _ => spec.version(),
+                },
+                distribution: distribution.clone(),
+                urgency: if entries.is_empty() {
+                    spec.urgency.unwrap_or(Urgency::Medium)
+                } else {
+                    Urgency::Medium
+                },
+                changes: Vec::new(),
+                maintainer: maintainer.clone(),
+                date: commit_time(&commit)?,
+            });
+        }
+        if commit.parent_count() > 1 && !opts.merges {
+            continue;
+        }
+        if let Some(summary) = commit.summary() {
+            entries
+                .last_mut()
+                .unwrap()
+                .changes
+                .push(summary.to_string());
+        }
+    }
+    entries.retain(|e| !e.changes.is_empty());
+    Ok(entries)
+}
+
+fn commit_time(commit: &git2::Commit) -> Result<DateTime<FixedOffset>> {
+    let time = commit.time();
+    FixedOffset::east_opt(time.offset_minutes() * 60)
+        .and_then(|tz| tz.timestamp_opt(time.seconds(), 0).single())
+        .ok_or_else(|| anyhow!("invalid commit time for {}", commit.id()))
+}
+
+/// Compress with gzip the same way 'gzip -9n' does, leaving out the file name and timestamp so
+/// that the output is reproducible.
+pub(crate) fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut enc = GzBuilder::new()
+        .mtime(0)
+        .write(Vec::new(), Compression::best());
+    enc.write_all(data)?;
+    enc.finish()
+}
+
+/// Generate the gzipped changelog for a spec.
+pub(crate) fn generate(spec: &BuildSpec, opts: &ChangelogOpts) -> Result<Vec<u8>> {
+    let entries = from_git(spec, opts)?;
+    let mut buf = Vec::new();
+    write_entries(&mut buf, &entries)?;
+    Ok(gzip(&buf)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::{FixedOffset, TimeZone};
+
+    use super::{from_git, gzip, write_entries, ChangelogOpts, Entry};
+    use crate::build::BuildSpec;
+    use crate::deb::Urgency;
+
+    #[test]
+    fn format() {
+        let entry = |version: &str, changes: &[&str]| Entry {
+            package: "pax".to_string(),
+            version: version.to_string(),
+            distribution: "unstable".to_string(),
+            urgency: Urgency::Low,
+            changes: changes.iter().map(|s| s.to_string()).collect(),
+            maintainer: "Jerry <jerry@jerry.se>".to_string(),
+            date: FixedOffset::east_opt(3600)
+                .unwrap()
+                .with_ymd_and_hms(2024, 3, 7, 9, 5, 0)
+                .unwrap(),
+        };
+        let mut buf = Vec::new();
+        write_entries(
+            &mut buf,
+            &[entry("1.1.0", &["two", "three"]), entry("1.0.0", &["one"])],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "pax (1.1.0) unstable; urgency=low\n\
+             \n  * two\n  * three\n\n\
+             \x20-- Jerry <jerry@jerry.se>  Thu, 07 Mar 2024 09:05:00 +0100\n\
+             \n\
+             pax (1.0.0) unstable; urgency=low\n\
+             \n  * one\n\n\
+             \x20-- Jerry <jerry@jerry.se>  Thu, 07 Mar 2024 09:05:00 +0100\n"
+        );
+    }
+
+    #[test]
+    fn reproducible_gzip() {
+        let a = gzip(b"hello").unwrap();
+        let b = gzip(b"hello").unwrap();
+        assert_eq!(a, b);
+        // mtime field of the gzip header
+        assert_eq!(&a[4..8], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn git_history() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let repo = git2::Repository::init(dir).unwrap();
+        let sig = git2::Signature::new(
+            "Jerry",
+            "jerry@jerry.se",
+            &git2::Time::new(1_700_000_000, 0),
+        )
+        .unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let mut parents = Vec::new();
+        for msg in ["first", "second", "third\n\nbody is ignored"] {
+            let parent_refs: Vec<_> = parents.iter().collect();
+            let oid = repo
+                .commit(Some("HEAD"), &sig, &sig, msg, &tree, &parent_refs)
+                .unwrap();
+            let commit = repo.find_commit(oid).unwrap();
+            if msg == "second" {
+                repo.tag_lightweight("v1.0.0", commit.as_object(), false)
+                    .unwrap();
+            }
+            parents = vec![commit];
+        }
+        let spec = BuildSpec {
+            package: "pax".to_string(),
+            version: "1.1.0".to_string(),
+            maintainer: Some("Jerry <jerry@jerry.se>".to_string()),
+            urgency: Some(Urgency::High),
+            ..Default::default()
+        };
+        let opts = ChangelogOpts {
+            repo: Some(dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let entries = from_git(&spec, &opts).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].version, "1.1.0");
+        assert_eq!(entries[0].urgency, Urgency::High);
+        assert_eq!(entries[0].changes, vec!["third"]);
+        assert_eq!(entries[1].version, "1.0.0");
+        assert_eq!(entries[1].urgency, Urgency::Medium);
+        assert_eq!(entries[1].changes, vec!["second", "first"]);
+
+        // a tagged HEAD still gets the version being built
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.tag_lightweight("v1.1.0", head.as_object(), false)
+            .unwrap();
+        let spec = BuildSpec {
+            buildno: Some(2),
+            ..spec
+        };
+        let entries = from_git(&spec, &opts).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].version, "1.1.0-2");
+        assert_eq!(entries[0].changes, vec!["third"]);
+        assert_eq!(entries[1].version, "1.0.0");
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use crate::build::BuildSpec;
+use crate::deb::{self, MaintainerScripts};
+use crate::util::to_io_err;
+
+/// A single deb822 paragraph. Field order is preserved and field names are matched without
+/// regard to case.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Paragraph {
+    fields: Vec<(String, String)>,
+}
+
+impl Paragraph {
+    /// Every field in the order they were written.
+    pub(crate) fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// Get the raw value of a field. Continuation lines are joined with '\n' and have their
+    /// leading space removed.
+    pub(crate) fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get a folded field (e.g. Depends) as a comma separated list.
+    pub(crate) fn get_list(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key).map(|v| {
+            v.replace('\n', " ")
+                .split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    }
+
+    /// Get a multiline field (e.g. Description) with the '.' placeholder lines turned back into
+    /// empty lines.
+    pub(crate) fn get_multiline(&self, key: &str) -> Option<String> {
+        self.get(key).map(|v| {
+            v.lines()
+                .map(|l| if l.trim() == "." { "" } else { l })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Parse deb822 formatted text into a list of paragraphs.
+pub(crate) fn parse(text: &str) -> io::Result<Vec<Paragraph>> {
+    let mut paragraphs = Vec::new();
+    let mut current = Paragraph::default();
+    for (i, line) in text.lines().enumerate() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                paragraphs.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            let (_, value) = current.fields.last_mut().ok_or_else(|| {
+                to_io_err(format!(
+                    "line {}: continuation line found before any field",
+                    i + 1
+                ))
+            })?;
+            value.push('\n');
+            value.push_str(&line[1..]);
+            continue;
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| to_io_err(format!("line {}: expected \"Field: value\"", i + 1)))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(to_io_err(format!("line {}: empty field name", i + 1)));
+        }
+        if current.get(key).is_some() {
+            return Err(to_io_err(format!(
+                "line {}: duplicate field {:?}",
+                i + 1,
+                key
+            )));
+        }
+        current
+            .fields
+            .push((key.to_string(), value.trim().to_string()));
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+    Ok(paragraphs)
+}
+
+/// Write a multiline field value using deb822 continuation lines. Empty lines are replaced with
+/// a single '.' as required by the Description field.
+pub(crate) fn write_multiline<W: Write>(w: &mut W, key: &str, value: &str) -> io::Result<()> {
+    let mut lines = value.lines();
+    writeln!(w, "{}: {}", key, lines.next().unwrap_or_default())?;
+    for line in lines {
+        if line.trim().is_empty() {
+            writeln!(w, " .")?;
+        } else {
+            writeln!(w, " {}", line)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the control metadata and maintainer scripts out of a .deb file.
+pub(crate) fn read_deb<P: AsRef<Path>>(path: P) -> anyhow::Result<BuildSpec> {
+    let path = path.as_ref();
+    let mut pkg = ar::Archive::new(
+        fs::File::open(path).map_err(|e| anyhow::anyhow!("{}: could not open {:?}", e, path))?,
+    );
+    while let Some(ar_entry) = pkg.next_entry() {
+        let entry = ar_entry?;
+        let name = String::from_utf8(entry.header().identifier().to_vec())?;
+        if !name.starts_with("control.tar") {
+            continue;
+        }
+        return read_control_tarball(deb::decoder(&name, entry)?);
+    }
+    Err(anyhow::anyhow!("{:?} has no control archive", path))
+}
+
+fn read_control_tarball<R: Read>(r: R) -> anyhow::Result<BuildSpec> {
+    let mut control = None;
+    let mut scripts = MaintainerScripts::default();
+    let mut ball = tar::Archive::new(r);
+    for entry in ball.entries()? {
+        let mut entry = entry?;
+        let name = entry
+            .path()?
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let slot = match name.as_str() {
+            "control" => &mut control,
+            "preinst" => &mut scripts.preinst,
+            "postinst" => &mut scripts.postinst,
+            "prerm" => &mut scripts.prerm,
+            "postrm" => &mut scripts.postrm,
+            _ => continue,
+        };
+        let mut s = String::new();
+        entry.read_to_string(&mut s)?;
+        *slot = Some(s);
+    }
+    let control = control.ok_or_else(|| anyhow::anyhow!("control archive has no control file"))?;
+    let mut spec = BuildSpec::parse(control.as_bytes())?;
+    if scripts.preinst.is_some()
+        || scripts.postinst.is_some()
+        || scripts.prerm.is_some()
+        || scripts.postrm.is_some()
+    {
+        spec.scripts = Some(scripts);
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn paragraphs() {
+        let text = "# leading comment\n\
+            Package: one\n\
+            Depends: a,\n  b (>= 1.0),\n  c\n\
+            Description: short\n Long line one.\n .\n Long line two.\n\
+            \n\
+            \n\
+            Package: two\n\
+            # comment inside a paragraph\n\
+            Version: 1.0\n";
+        let p = parse(text).unwrap();
+        assert_eq!(p.len(), 2);
+        assert_eq!(p[0].get("package"), Some("one"));
+        assert_eq!(
+            p[0].get_list("Depends").unwrap(),
+            vec!["a", "b (>= 1.0)", "c"]
+        );
+        assert_eq!(
+            p[0].get_multiline("Description").unwrap(),
+            "short\nLong line one.\n\nLong line two."
+        );
+        assert_eq!(p[1].get("Package"), Some("two"));
+        assert_eq!(p[1].get("Version"), Some("1.0"));
+        assert_eq!(p[1].get("Depends"), None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse(" starts with continuation").is_err());
+        assert!(parse("Package: a\nno colon").is_err());
+        assert!(parse("Package: a\npackage: b").is_err());
+        assert!(parse(": value").is_err());
+    }
+}
@@ -0,0 +1,971 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs,
+    io::{self, Write},
+    path::Path,
+    str,
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::build::BuildSpec;
+use crate::control::write_multiline;
+use crate::util;
+
+static FORMAT: &str = "https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/";
+
+/// License name used when a dependency's license could not be found.
+static UNKNOWN: &str = "UNKNOWN";
+
+/// Structured data used to write a machine-readable (DEP-5) copyright file.
+#[derive(Clone, Debug, Default, pax_derive::FromLua, pax_derive::IntoLua, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Copyright {
+    /// Upstream-Name header. Defaults to the package name.
+    pub upstream_name: Option<String>,
+    pub upstream_contact: Option<String>,
+    pub source: Option<String>,
+    #[lua_default(vec![])]
+    #[lua_type("pax.CopyrightFiles[]")]
+    pub files: Vec<FilesParagraph>,
+    /// Stand-alone license paragraphs holding the full license text.
+    #[lua_default(vec![])]
+    #[lua_type("pax.CopyrightLicense[]")]
+    pub licenses: Vec<LicenseParagraph>,
+    /// Crate directory whose dependencies, found with 'cargo metadata', are added to the
+    /// "Files: *" paragraph.
+    pub cargo: Option<String>,
+    /// Target triple used to filter crate dependencies. Defaults to the host.
+    pub cargo_target: Option<String>,
+    /// Go module directory whose dependencies, found with 'go list -m', are added to the
+    /// "Files: *" paragraph.
+    pub go: Option<String>,
+}
+
+#[derive(
+    Clone, Debug, Default, PartialEq, pax_derive::FromLua, pax_derive::IntoLua, Deserialize,
+)]
+#[serde(deny_unknown_fields)]
+#[lua_class("pax.CopyrightFiles")]
+pub(crate) struct FilesParagraph {
+    /// File globs.
+    pub files: Vec<String>,
+    /// Copyright holders.
+    pub copyright: Vec<String>,
+    pub license: String,
+    pub comment: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, pax_derive::FromLua, pax_derive::IntoLua, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[lua_class("pax.CopyrightLicense")]
+pub(crate) struct LicenseParagraph {
+    pub name: String,
+    pub text: Option<String>,
+    /// Read the license text from a file instead.
+    pub file: Option<String>,
+}
+
+impl Copyright {
+    pub(crate) fn write<W: Write>(&self, w: &mut W, spec: &BuildSpec) -> Result<()> {
+        writeln!(w, "Format: {}", FORMAT)?;
+        writeln!(
+            w,
+            "Upstream-Name: {}",
+            self.upstream_name.as_ref().unwrap_or(&spec.package)
+        )?;
+        if let Some(ref contact) = self.upstream_contact {
+            writeln!(w, "Upstream-Contact: {}", contact)?;
+        }
+        if let Some(ref source) = self.source {
+            writeln!(w, "Source: {}", source)?;
+        }
+        let mut deps = Vec::new();
+        if let Some(ref dir) = self.cargo {
+            deps.extend(cargo_dependencies(dir, self.cargo_target.as_deref())?);
+        }
+        if let Some(ref dir) = self.go {
+            deps.extend(go_dependencies(dir)?);
+        }
+        self.write_paragraphs(w, &deps)
+    }
+
+    fn write_paragraphs<W: Write>(&self, w: &mut W, deps: &[Dependency]) -> Result<()> {
+        let mut paragraphs = self.files.clone();
+        add_dependencies(&mut paragraphs, deps);
+        for p in &paragraphs {
+            writeln!(w)?;
+            p.write(w)?;
+        }
+        let mut licenses = Vec::new();
+        for license in &self.licenses {
+            let text = match (&license.text, &license.file) {
+                (Some(text), _) => text.clone(),
+                (None, Some(file)) => fs::read_to_string(file)
+                    .map_err(|e| anyhow!("{}: could not read license file {:?}", e, file))?,
+                (None, None) => String::new(),
+            };
+            licenses.push((license.name.clone(), text));
+        }
+        // Every license named without its text needs a stand-alone paragraph.
+        for name in paragraphs
+            .iter()
+            .filter(|p| !p.license.contains('\n'))
+            .flat_map(|p| license_names(&p.license))
+        {
+            if !licenses.iter().any(|(n, _)| *n == name) {
+                let text = missing_license_text(&name, deps);
+                licenses.push((name, text));
+            }
+        }
+        for (name, text) in &licenses {
+            writeln!(w)?;
+            write_multiline(w, "License", &format!("{}\n{}", name, text.trim_end()))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn generate(&self, spec: &BuildSpec) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write(&mut buf, spec)?;
+        Ok(buf)
+    }
+}
+
+impl FilesParagraph {
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "Files: {}", self.files.join(" "))?;
+        write_multiline(w, "Copyright", &self.copyright.join("\n"))?;
+        writeln!(w, "License: {}", self.license)?;
+        if let Some(ref comment) = self.comment {
+            write_multiline(w, "Comment", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// A crate or Go module compiled into the package.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Dependency {
+    /// Name and version, e.g. "serde 1.0.0".
+    name: String,
+    copyright: Vec<String>,
+    /// DEP-5 license expression.
+    license: String,
+    /// The SPDX expression, kept when DEP-5 syntax can't group it the same way.
+    spdx: Option<String>,
+    /// Contents of the license file, if one was found.
+    text: Option<String>,
+}
+
+/// Add the dependencies to the "Files: *" paragraph, adding one at the start if there is none.
+/// Dependencies are built into the package's files rather than shipped as files of their own,
+/// and only the last paragraph matching a file applies, so they cannot have paragraphs of their
+/// own.
+fn add_dependencies(paragraphs: &mut Vec<FilesParagraph>, deps: &[Dependency]) {
+    if deps.is_empty() {
+        return;
+    }
+    let i = match paragraphs.iter().position(|p| p.files == ["*"]) {
+        Some(i) => i,
+        None => {
+            paragraphs.insert(
+                0,
+                FilesParagraph {
+                    files: vec!["*".to_string()],
+                    ..Default::default()
+                },
+            );
+            0
+        }
+    };
+    let p = &mut paragraphs[i];
+    let mut licenses: Vec<String> = Some(p.license.clone())
+        .filter(|l| !l.is_empty())
+        .into_iter()
+        .collect();
+    let mut comment: Vec<String> = p.comment.iter().cloned().collect();
+    for dep in deps {
+        for holder in &dep.copyright {
+            if !p.copyright.contains(holder) {
+                p.copyright.push(holder.clone());
+            }
+        }
+        if !licenses.contains(&dep.license) {
+            licenses.push(dep.license.clone());
+        }
+        match &dep.spdx {
+            Some(spdx) => comment.push(format!("Includes {} under SPDX {}.", dep.name, spdx)),
+            None => comment.push(format!("Includes {} under {}.", dep.name, dep.license)),
+        }
+    }
+    // A comma groups the "or" choices of each license before they are joined with "and".
+    let sep = if licenses.iter().any(|l| l.contains(" or ")) {
+        ", and "
+    } else {
+        " and "
+    };
+    p.license = licenses.join(sep);
+    p.comment = Some(comment.join("\n"));
+}
+
+#[derive(Deserialize)]
+struct CargoMetadata {
+    packages: Vec<CargoPackage>,
+    workspace_members: Vec<String>,
+    resolve: Option<CargoResolve>,
+}
+
+#[derive(Deserialize)]
+struct CargoPackage {
+    id: String,
+    name: String,
+    version: String,
+    license: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    manifest_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CargoResolve {
+    root: Option<String>,
+    nodes: Vec<CargoNode>,
+}
+
+#[derive(Deserialize)]
+struct CargoNode {
+    id: String,
+    #[serde(default)]
+    deps: Vec<CargoDep>,
+}
+
+#[derive(Deserialize)]
+struct CargoDep {
+    pkg: String,
+    #[serde(default)]
+    dep_kinds: Vec<CargoDepKind>,
+}
+
+#[derive(Deserialize)]
+struct CargoDepKind {
+    kind: Option<String>,
+}
+
+fn cargo_dependencies(dir: &str, target: Option<&str>) -> Result<Vec<Dependency>> {
+    let manifest = Path::new(dir).join("Cargo.toml");
+    let target = match target {
+        Some(t) => t.to_string(),
+        None => host_triple()?,
+    };
+    let out = util::command("cargo")?
+        .args(["metadata", "--format-version", "1", "--filter-platform"])
+        .arg(&target)
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .output()?;
+    if !out.status.success() {
+        let s = str::from_utf8(&out.stderr).map(|s| s.strip_suffix('\n').unwrap_or(s))?;
+        return Err(anyhow!("cargo metadata failed: {}", s));
+    }
+    Ok(crate_dependencies(serde_json::from_slice(&out.stdout)?))
+}
+
+fn host_triple() -> Result<String> {
+    let out = util::command("rustc")?.arg("-vV").output()?;
+    str::from_utf8(&out.stdout)?
+        .lines()
+        .find_map(|l| l.strip_prefix("host: "))
+        .map(String::from)
+        .ok_or_else(|| anyhow!("could not find host target triple"))
+}
+
+/// Find every package that the root crates depend on when built, skipping dev-dependencies.
+fn crate_dependencies(meta: CargoMetadata) -> Vec<Dependency> {
+    let Some(resolve) = meta.resolve else {
+        return Vec::new();
+    };
+    let nodes: HashMap<_, _> = resolve.nodes.iter().map(|n| (&n.id, n)).collect();
+    let roots = match resolve.root {
+        Some(ref root) => vec![root.clone()],
+        None => meta.workspace_members.clone(),
+    };
+    let mut seen: HashSet<String> = roots.iter().cloned().collect();
+    let mut queue: VecDeque<String> = roots.into();
+    while let Some(id) = queue.pop_front() {
+        let Some(node) = nodes.get(&id) else {
+            continue;
+        };
+        for dep in &node.deps {
+            let dev_only = !dep.dep_kinds.is_empty()
+                && dep
+                    .dep_kinds
+                    .iter()
+                    .all(|k| k.kind.as_deref() == Some("dev"));
+            if !dev_only && seen.insert(dep.pkg.clone()) {
+                queue.push_back(dep.pkg.clone());
+            }
+        }
+    }
+    let members: HashSet<_> = meta.workspace_members.iter().collect();
+    let mut deps: BTreeMap<(String, String), Dependency> = BTreeMap::new();
+    for pkg in meta.packages {
+        if !seen.contains(&pkg.id) || members.contains(&pkg.id) {
+            continue;
+        }
+        let copyright = if pkg.authors.is_empty() {
+            vec![format!("{} developers", pkg.name)]
+        } else {
+            pkg.authors.clone()
+        };
+        let text = pkg
+            .manifest_path
+            .as_deref()
+            .and_then(|p| Path::new(p).parent())
+            .and_then(|dir| find_license_file(&dir.to_string_lossy()))
+            .and_then(|p| fs::read_to_string(p).ok());
+        let (license, spdx) = pkg
+            .license
+            .as_deref()
+            .map_or((UNKNOWN.to_string(), None), dep5_license);
+        deps.insert(
+            (pkg.name.clone(), pkg.version.clone()),
+            Dependency {
+                name: format!("{} {}", pkg.name, pkg.version),
+                copyright,
+                license,
+                spdx,
+                text,
+            },
+        );
+    }
+    deps.into_values().collect()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GoModule {
+    path: String,
+    version: Option<String>,
+    #[serde(default)]
+    main: bool,
+    dir: Option<String>,
+}
+
+fn go_dependencies(dir: &str) -> Result<Vec<Dependency>> {
+    let out = util::command("go")?
+        .args(["list", "-C", dir, "-m", "-json", "all"])
+        .output()?;
+    if !out.status.success() {
+        let s = str::from_utf8(&out.stderr).map(|s| s.strip_suffix('\n').unwrap_or(s))?;
+        return Err(anyhow!("go list failed: {}", s));
+    }
+    let mut deps = Vec::new();
+    // 'go list -json' writes a stream of objects rather than an array.
+    for module in serde_json::Deserializer::from_slice(&out.stdout).into_iter::<GoModule>() {
+        let module = module?;
+        if module.main {
+            continue;
+        }
+        let text = module
+            .dir
+            .as_deref()
+            .and_then(find_license_file)
+            .and_then(|p| fs::read_to_string(p).ok())
+            .unwrap_or_default();
+        let mut copyright = copyright_lines(&text);
+        if copyright.is_empty() {
+            copyright.push(format!("{} authors", module.path));
+        }
+        let (license, spdx) = dep5_license(detect_license(&text));
+        deps.push(Dependency {
+            name: match module.version {
+                Some(v) => format!("{} {}", module.path, v),
+                None => module.path.clone(),
+            },
+            copyright,
+            license,
+            spdx,
+            text: Some(text).filter(|t| !t.trim().is_empty()),
+        });
+    }
+    Ok(deps)
+}
+
+fn find_license_file(dir: &str) -> Option<std::path::PathBuf> {
+    let mut entries: Vec<_> = fs::read_dir(dir).ok()?.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+    entries.into_iter().map(|e| e.path()).find(|p| {
+        let name = p
+            .file_name()
+            .map(|n| n.to_string_lossy().to_uppercase())
+            .unwrap_or_default();
+        p.is_file()
+            && (name.starts_with("LICENSE")
+                || name.starts_with("LICENCE")
+                || name.starts_with("COPYING"))
+    })
+}
+
+/// The DEP-5 license for the SPDX expression `expr`, along with `expr` itself when DEP-5 can't
+/// group it the same way. Such a license lists every name joined with "and".
+fn dep5_license(expr: &str) -> (String, Option<String>) {
+    if let Some(license) = spdx_to_dep5(expr) {
+        return (license, None);
+    }
+    let mut names: Vec<String> = Vec::new();
+    let spaced = spaced_spdx(expr);
+    let mut words = spaced.split_whitespace().peekable();
+    while let Some(word) = words.next() {
+        let mut name = match word.to_ascii_lowercase().as_str() {
+            "or" | "and" | "with" | "(" | ")" => continue,
+            _ => dep5_name(word).to_string(),
+        };
+        if words.next_if(|w| w.eq_ignore_ascii_case("with")).is_some() {
+            if let Some(exception) = words.next() {
+                name = format!("{} with {}", name, exception);
+            }
+        }
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    match names.is_empty() {
+        true => (UNKNOWN.to_string(), Some(expr.to_string())),
+        false => (names.join(" and "), Some(expr.to_string())),
+    }
+}
+
+/// A parsed SPDX license expression.
+#[derive(Debug)]
+enum Spdx {
+    License(String),
+    And(Vec<Spdx>),
+    Or(Vec<Spdx>),
+}
+
+/// Convert an SPDX license expression into the syntax and short names used by DEP-5. DEP-5
+/// reads "and" as binding tighter than "or" and has no parentheses, but a comma ends a group:
+/// "(MIT OR Apache-2.0) AND Zlib" is written "Expat or Apache-2.0, and Zlib". Returns None for
+/// expressions that need deeper grouping than that, or don't parse.
+fn spdx_to_dep5(expr: &str) -> Option<String> {
+    let spaced = spaced_spdx(expr);
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let mut pos = 0;
+    let parsed = parse_spdx_or(&tokens, &mut pos)?;
+    if pos != tokens.len() {
+        return None;
+    }
+    match parsed {
+        Spdx::And(terms) if terms.iter().any(|t| matches!(t, Spdx::Or(_))) => terms
+            .iter()
+            .map(dep5_or)
+            .collect::<Option<Vec<_>>>()
+            .map(|t| t.join(", and ")),
+        e => dep5_or(&e),
+    }
+}
+
+/// `expr` with the old "A/B" form of "A OR B" replaced and space around parentheses.
+fn spaced_spdx(expr: &str) -> String {
+    expr.replace('/', " OR ")
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+}
+
+fn parse_spdx_or(tokens: &[&str], pos: &mut usize) -> Option<Spdx> {
+    let mut terms = vec![parse_spdx_and(tokens, pos)?];
+    while tokens
+        .get(*pos)
+        .is_some_and(|t| t.eq_ignore_ascii_case("or"))
+    {
+        *pos += 1;
+        terms.push(parse_spdx_and(tokens, pos)?);
+    }
+    Some(match terms.len() {
+        1 => terms.remove(0),
+        _ => Spdx::Or(
+            terms
+                .into_iter()
+                .flat_map(|t| match t {
+                    Spdx::Or(inner) => inner,
+                    t => vec![t],
+                })
+                .collect(),
+        ),
+    })
+}
+
+fn parse_spdx_and(tokens: &[&str], pos: &mut usize) -> Option<Spdx> {
+    let mut terms = vec![parse_spdx_license(tokens, pos)?];
+    while tokens
+        .get(*pos)
+        .is_some_and(|t| t.eq_ignore_ascii_case("and"))
+    {
+        *pos += 1;
+        terms.push(parse_spdx_license(tokens, pos)?);
+    }
+    Some(match terms.len() {
+        1 => terms.remove(0),
+        _ => Spdx::And(
+            terms
+                .into_iter()
+                .flat_map(|t| match t {
+                    Spdx::And(inner) => inner,
+                    t => vec![t],
+                })
+                .collect(),
+        ),
+    })
+}
+
+fn parse_spdx_license(tokens: &[&str], pos: &mut usize) -> Option<Spdx> {
+    let token = *tokens.get(*pos)?;
+    *pos += 1;
+    if token == "(" {
+        let inner = parse_spdx_or(tokens, pos)?;
+        (tokens.get(*pos) == Some(&")")).then_some(())?;
+        *pos += 1;
+        return Some(inner);
+    }
+    if [")", "and", "or", "with"].contains(&token.to_ascii_lowercase().as_str()) {
+        return None;
+    }
+    let mut name = dep5_name(token).to_string();
+    if tokens
+        .get(*pos)
+        .is_some_and(|t| t.eq_ignore_ascii_case("with"))
+    {
+        name = format!("{} with {}", name, tokens.get(*pos + 1)?);
+        *pos += 2;
+    }
+    Some(Spdx::License(name))
+}
+
+/// An "or" of licenses, or of "and" groups of licenses.
+fn dep5_or(e: &Spdx) -> Option<String> {
+    match e {
+        Spdx::Or(terms) => terms
+            .iter()
+            .map(dep5_and)
+            .collect::<Option<Vec<_>>>()
+            .map(|t| t.join(" or ")),
+        e => dep5_and(e),
+    }
+}
+
+/// A license, or an "and" of licenses.
+fn dep5_and(e: &Spdx) -> Option<String> {
+    match e {
+        Spdx::License(name) => Some(name.clone()),
+        Spdx::And(terms) => terms
+            .iter()
+            .map(dep5_and)
+            .collect::<Option<Vec<_>>>()
+            .map(|t| t.join(" and ")),
+        Spdx::Or(_) => None,
+    }
+}
+
+/// The DEP-5 short name of an SPDX license identifier. Names DEP-5 does not define are kept.
+fn dep5_name(id: &str) -> &str {
+    match id {
+        "MIT" => "Expat",
+        "GPL-2.0" | "GPL-2.0-only" => "GPL-2",
+        "GPL-2.0+" | "GPL-2.0-or-later" => "GPL-2+",
+        "GPL-3.0" | "GPL-3.0-only" => "GPL-3",
+        "GPL-3.0+" | "GPL-3.0-or-later" => "GPL-3+",
+        "LGPL-2.1-only" => "LGPL-2.1",
+        "LGPL-2.1+" | "LGPL-2.1-or-later" => "LGPL-2.1+",
+        "LGPL-3.0" | "LGPL-3.0-only" => "LGPL-3",
+        "LGPL-3.0+" | "LGPL-3.0-or-later" => "LGPL-3+",
+        "BSD-2-Clause" => "BSD-2-clause",
+        "BSD-3-Clause" => "BSD-3-clause",
+        "Zlib" => "Zlib",
+        id => id,
+    }
+}
+
+/// The license short names in a DEP-5 License field.
+fn license_names(license: &str) -> Vec<String> {
+    let first = license.lines().next().unwrap_or_default().replace(',', " ");
+    let mut names = Vec::new();
+    let mut words = first.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "or" | "and" => {}
+            // The exception is part of the license it follows.
+            "with" => _ = words.next(),
+            name if !names.iter().any(|n| n == name) => names.push(name.to_string()),
+            _ => {}
+        }
+    }
+    names
+}
+
+/// Licenses whose text ships in /usr/share/common-licenses on Debian systems.
+static COMMON_LICENSES: &[(&str, &str)] = &[
+    ("Apache-2.0", "Apache-2.0"),
+    ("Artistic", "Artistic"),
+    ("CC0-1.0", "CC0-1.0"),
+    ("GPL-2", "GPL-2"),
+    ("GPL-2+", "GPL-2"),
+    ("GPL-3", "GPL-3"),
+    ("GPL-3+", "GPL-3"),
+    ("LGPL-2.1", "LGPL-2.1"),
+    ("LGPL-2.1+", "LGPL-2.1"),
+    ("LGPL-3", "LGPL-3"),
+    ("LGPL-3+", "LGPL-3"),
+    ("MPL-2.0", "MPL-2.0"),
+];
+
+/// Text for a license paragraph that was not given: a pointer to common-licenses, the license
+/// file of a dependency using it, or a note saying which dependencies use it.
+fn missing_license_text(name: &str, deps: &[Dependency]) -> String {
+    if let Some((_, file)) = COMMON_LICENSES.iter().find(|(n, _)| *n == name) {
+        return format!(
+            "On Debian systems, the complete text of the {} license can be found in\n\
+             \"/usr/share/common-licenses/{}\".",
+            name, file
+        );
+    }
+    let users: Vec<_> = deps
+        .iter()
+        .filter(|d| license_names(&d.license).iter().any(|n| n == name))
+        .collect();
+    // Only a dependency under this license alone has a license file holding just its text.
+    if let Some(text) = users
+        .iter()
+        .filter(|d| d.license == name)
+        .find_map(|d| d.text.clone())
+    {
+        return text;
+    }
+    let names: Vec<_> = users.iter().map(|d| d.name.as_str()).collect();
+    if name == UNKNOWN {
+        format!("The license of {} could not be found.", names.join(", "))
+    } else {
+        format!(
+            "The {} license text of {} was not found.",
+            name,
+            names.join(", ")
+        )
+    }
+}
+
+fn copyright_lines(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        let Some(rest) = line.trim().strip_prefix("Copyright") else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        let rest = rest
+            .strip_prefix("(c)")
+            .or_else(|| rest.strip_prefix("(C)"))
+            .or_else(|| rest.strip_prefix('©'))
+            .unwrap_or(rest)
+            .trim();
+        if rest.is_empty() || lines.iter().any(|l| l == rest) {
+            continue;
+        }
+        lines.push(rest.to_string());
+    }
+    lines
+}
+
+/// Guess the SPDX identifier of the license from the text of a license file.
+fn detect_license(text: &str) -> &'static str {
+    let t = text
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if t.contains("apache license") && t.contains("version 2.0") {
+        "Apache-2.0"
+    } else if t.contains("mozilla public license") && t.contains("2.0") {
+        "MPL-2.0"
+    } else if t.contains("gnu lesser general public license") {
+        if t.contains("version 3") {
+            "LGPL-3.0"
+        } else {
+            "LGPL-2.1"
+        }
+    } else if t.contains("gnu general public license") {
+        if t.contains("version 3") {
+            "GPL-3.0"
+        } else {
+            "GPL-2.0"
+        }
+    } else if t.contains("permission is hereby granted, free of charge") {
+        "MIT"
+    } else if t.contains("redistribution and use in source and binary forms") {
+        if t.contains("neither the name") || t.contains("names of its contributors") {
+            "BSD-3-Clause"
+        } else {
+            "BSD-2-Clause"
+        }
+    } else if t.contains("permission to use, copy, modify, and/or distribute")
+        || t.contains("permission to use, copy, modify, and distribute")
+    {
+        "ISC"
+    } else {
+        UNKNOWN
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{
+        copyright_lines, crate_dependencies, dep5_license, detect_license, license_names,
+        spdx_to_dep5, Copyright, Dependency, FilesParagraph, LicenseParagraph,
+    };
+    use crate::build::BuildSpec;
+
+    #[test]
+    fn write() {
+        let spec = BuildSpec {
+            package: "pax".to_string(),
+            ..Default::default()
+        };
+        let c = Copyright {
+            source: Some("https://github.com/harrybrwn/pax".to_string()),
+            files: vec![FilesParagraph {
+                files: vec!["*".to_string()],
+                copyright: vec!["2024 Harry Brown".to_string(), "2024 Jerry".to_string()],
+                license: "MIT".to_string(),
+                comment: None,
+            }],
+            licenses: vec![LicenseParagraph {
+                name: "MIT".to_string(),
+                text: Some(
+                    "Permission is hereby granted.\n\nTHE SOFTWARE IS PROVIDED \"AS IS\""
+                        .to_string(),
+                ),
+                file: None,
+            }],
+            ..Default::default()
+        };
+        let out = String::from_utf8(c.generate(&spec).unwrap()).unwrap();
+        assert_eq!(
+            out,
+            "Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/\n\
+             Upstream-Name: pax\n\
+             Source: https://github.com/harrybrwn/pax\n\
+             \n\
+             Files: *\n\
+             Copyright: 2024 Harry Brown\n\
+             \x202024 Jerry\n\
+             License: MIT\n\
+             \n\
+             License: MIT\n\
+             \x20Permission is hereby granted.\n\
+             \x20.\n\
+             \x20THE SOFTWARE IS PROVIDED \"AS IS\"\n"
+        );
+    }
+
+    #[test]
+    fn cargo_metadata() {
+        let meta = serde_json::from_str(
+            r#"{
+              "packages": [
+                {"id": "app 0.1.0", "name": "app", "version": "0.1.0", "license": "MIT", "authors": []},
+                {"id": "dep 1.0.0", "name": "dep", "version": "1.0.0", "license": "MIT OR Apache-2.0", "authors": ["A <a@a.a>"]},
+                {"id": "sub 2.0.0", "name": "sub", "version": "2.0.0", "license": null, "authors": []},
+                {"id": "devdep 1.0.0", "name": "devdep", "version": "1.0.0", "license": "MIT", "authors": []}
+              ],
+              "workspace_members": ["app 0.1.0"],
+              "resolve": {
+                "root": "app 0.1.0",
+                "nodes": [
+                  {"id": "app 0.1.0", "deps": [
+                    {"pkg": "dep 1.0.0", "dep_kinds": [{"kind": null}]},
+                    {"pkg": "devdep 1.0.0", "dep_kinds": [{"kind": "dev"}]}
+                  ]},
+                  {"id": "dep 1.0.0", "deps": [{"pkg": "sub 2.0.0", "dep_kinds": [{"kind": "build"}]}]},
+                  {"id": "sub 2.0.0", "deps": []},
+                  {"id": "devdep 1.0.0", "deps": []}
+                ]
+              }
+            }"#,
+        )
+        .unwrap();
+        let deps = crate_dependencies(meta);
+        assert_eq!(
+            deps,
+            vec![
+                Dependency {
+                    name: "dep 1.0.0".to_string(),
+                    copyright: vec!["A <a@a.a>".to_string()],
+                    license: "Expat or Apache-2.0".to_string(),
+                    spdx: None,
+                    text: None,
+                },
+                Dependency {
+                    name: "sub 2.0.0".to_string(),
+                    copyright: vec!["sub developers".to_string()],
+                    license: "UNKNOWN".to_string(),
+                    spdx: None,
+                    text: None,
+                },
+            ]
+        );
+    }
+
+    /// Check the rules of the DEP-5 format that lintian enforces on binary packages: the
+    /// paragraphs have their required fields and every license named without its text has a
+    /// stand-alone License paragraph, given once.
+    fn check_dep5(text: &str) {
+        let paragraphs = crate::control::parse(text).unwrap();
+        assert!(paragraphs[0].get("Format").is_some());
+        let mut defined = Vec::new();
+        let mut used = Vec::new();
+        for p in &paragraphs[1..] {
+            let license = p.get("License").expect("paragraph without a License field");
+            if let Some(files) = p.get("Files") {
+                assert!(
+                    p.get("Copyright").is_some_and(|c| !c.is_empty()),
+                    "{}",
+                    files
+                );
+                if !license.contains('\n') {
+                    used.extend(license_names(license));
+                }
+            } else {
+                let (name, body) = license.split_once('\n').expect("License without text");
+                assert!(!body.trim().is_empty(), "License {} without text", name);
+                assert!(!defined.contains(&name.to_string()), "{} given twice", name);
+                defined.push(name.to_string());
+            }
+        }
+        for name in used {
+            assert!(defined.contains(&name), "no License paragraph for {}", name);
+        }
+    }
+
+    #[test]
+    fn dependencies() {
+        let spec = BuildSpec {
+            package: "app".to_string(),
+            ..Default::default()
+        };
+        let c = Copyright {
+            files: vec![
+                FilesParagraph {
+                    files: vec!["*".to_string()],
+                    copyright: vec!["2024 Jerry".to_string()],
+                    license: "GPL-3+".to_string(),
+                    comment: None,
+                },
+                FilesParagraph {
+                    files: vec!["usr/share/doc/*".to_string()],
+                    copyright: vec!["2024 Jerry".to_string()],
+                    license: "CC0-1.0".to_string(),
+                    comment: None,
+                },
+            ],
+            ..Default::default()
+        };
+        let deps = [
+            Dependency {
+                name: "dep 1.0.0".to_string(),
+                copyright: vec!["A <a@a.a>".to_string()],
+                license: spdx_to_dep5("MIT OR Apache-2.0").unwrap(),
+                spdx: None,
+                text: None,
+            },
+            Dependency {
+                name: "bsd 0.1.0".to_string(),
+                copyright: vec!["B".to_string(), "2024 Jerry".to_string()],
+                license: spdx_to_dep5("BSD-3-Clause").unwrap(),
+                spdx: None,
+                text: Some("Redistribution and use in source and binary forms".to_string()),
+            },
+            Dependency {
+                name: "sub 2.0.0".to_string(),
+                copyright: vec!["sub developers".to_string()],
+                license: "UNKNOWN".to_string(),
+                spdx: None,
+                text: None,
+            },
+        ];
+        let mut out = Vec::new();
+        writeln!(out, "Format: {}", super::FORMAT).unwrap();
+        writeln!(out, "Upstream-Name: {}", spec.package).unwrap();
+        c.write_paragraphs(&mut out, &deps).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        check_dep5(&out);
+        let paragraphs = crate::control::parse(&out).unwrap();
+        assert_eq!(paragraphs.len(), 9);
+        assert_eq!(paragraphs[1].get("Files"), Some("*"));
+        assert_eq!(
+            paragraphs[1].get("License"),
+            Some("GPL-3+, and Expat or Apache-2.0, and BSD-3-clause, and UNKNOWN")
+        );
+        assert_eq!(
+            paragraphs[1].get("Copyright"),
+            Some("2024 Jerry\nA <a@a.a>\nB\nsub developers")
+        );
+        assert_eq!(paragraphs[2].get("Files"), Some("usr/share/doc/*"));
+        assert!(paragraphs[3]
+            .get("License")
+            .unwrap()
+            .contains("/usr/share/common-licenses/GPL-3"));
+        assert_eq!(
+            paragraphs[6].get("License"),
+            Some("BSD-3-clause\nRedistribution and use in source and binary forms")
+        );
+    }
+
+    #[test]
+    fn licenses() {
+        let dep5 = |expr| spdx_to_dep5(expr).unwrap();
+        assert_eq!(dep5("MIT/Apache-2.0"), "Expat or Apache-2.0");
+        assert_eq!(
+            dep5("(MIT AND BSD-3-Clause) OR GPL-2.0-or-later"),
+            "Expat and BSD-3-clause or GPL-2+"
+        );
+        assert_eq!(
+            dep5("(MIT OR Apache-2.0) AND Unicode-DFS-2016"),
+            "Expat or Apache-2.0, and Unicode-DFS-2016"
+        );
+        assert_eq!(
+            dep5("MIT AND (Apache-2.0 WITH LLVM-exception OR Zlib)"),
+            "Expat, and Apache-2.0 with LLVM-exception or Zlib"
+        );
+        assert_eq!(dep5("(MIT OR (Zlib))"), "Expat or Zlib");
+        assert_eq!(
+            spdx_to_dep5("(MIT AND (Apache-2.0 OR Zlib)) OR BSL-1.0"),
+            None
+        );
+        assert_eq!(spdx_to_dep5("(MIT OR"), None);
+        assert_eq!(
+            dep5_license("(MIT AND (Apache-2.0 OR Zlib)) OR BSL-1.0"),
+            (
+                "Expat and Apache-2.0 and Zlib and BSL-1.0".to_string(),
+                Some("(MIT AND (Apache-2.0 OR Zlib)) OR BSL-1.0".to_string())
+            )
+        );
+        assert_eq!(
+            license_names("Apache-2.0 with LLVM-exception or Expat, and Expat"),
+            ["Apache-2.0", "Expat"]
+        );
+        let mit = "MIT License\n\nCopyright (c) 2019 Some One\nCopyright (c) 2019 Some One\n\n\
+            Permission is hereby granted, free of charge, to any person obtaining a copy";
+        assert_eq!(detect_license(mit), "MIT");
+        assert_eq!(copyright_lines(mit), vec!["2019 Some One"]);
+        let bsd = "Redistribution and use in source and binary forms, with or without\n\
+            modification, are permitted.\n\
+            Neither the name of Google Inc. nor the names of its contributors";
+        assert_eq!(detect_license(bsd), "BSD-3-Clause");
+        assert_eq!(
+            detect_license("Apache License\n Version 2.0, January 2004"),
+            "Apache-2.0"
+        );
+        assert_eq!(detect_license("all rights reserved"), "UNKNOWN");
+    }
+}
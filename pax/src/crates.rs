@@ -12,6 +12,11 @@ use cargo::util::command_prelude::root_manifest;
 use cargo::util::{homedir, interning::InternedString};
 use cargo::GlobalContext;
 
+use crate::error::Error;
+use crate::plan;
+use crate::sandbox;
+use crate::util;
+
 #[derive(Debug, pax_derive::FromLua)]
 pub(crate) struct Cargo {
     pub root: String,
@@ -40,9 +45,15 @@ pub(crate) struct Cargo {
 
 impl Cargo {
     pub(crate) fn build(&self) -> anyhow::Result<()> {
+        crate::watch::track(self.root());
         if !self.embeded_cargo {
             return self.run_from_shell();
         }
+        if plan::skip(format!("cargo build (embedded) (in {})", self.root)) {
+            return Ok(());
+        }
+        // Build scripts and rustc are run even though cargo itself isn't.
+        sandbox::check_exec("cargo build")?;
         let cwd = self.root();
         let mut config = GlobalContext::new(
             Shell::new(),
@@ -71,7 +82,9 @@ impl Cargo {
         let mut ws = Workspace::new(&manifest, &config)?;
         ws.set_require_optional_deps(true);
         if self.clean {
-            std::fs::remove_dir_all(ws.target_dir().as_path_unlocked())?;
+            let target = ws.target_dir().into_path_unlocked();
+            sandbox::check_write("cargo clean", &target)?;
+            std::fs::remove_dir_all(target)?;
         }
 
         let mut options = CompileOptions::new(&config, CompileMode::Build)?;
@@ -105,10 +118,6 @@ impl Cargo {
             .as_ref()
             .map(PathBuf::from)
             .unwrap_or(cwd.join("target"));
-        if self.clean {
-            std::fs::remove_dir_all(&target)
-                .map_err(|e| anyhow!("{}: could not remove {:?}", e, target))?;
-        }
         if let Some(s) = manifest.to_str() {
             args.push("--manifest-path");
             args.push(s);
@@ -160,15 +169,25 @@ impl Cargo {
                 args.push(&c);
             }
         }
-        println!("cargo {}", args.join(" "));
-        let out = std::process::Command::new("cargo")
+        let line = format!("cargo {}", args.join(" "));
+        if plan::skip(format!("{} (in {})", line, cwd.display())) {
+            return Ok(());
+        }
+        if self.clean {
+            sandbox::check_write("cargo clean", &target)?;
+            std::fs::remove_dir_all(&target)
+                .map_err(|e| anyhow!("{}: could not remove {:?}", e, target))?;
+        }
+        println!("{}", line);
+        let out = util::command("cargo")?
             .args(&args)
             .current_dir(cwd)
             .stdout(std::io::stdout())
             .stderr(std::io::stderr())
-            .output()?;
+            .output()
+            .map_err(|e| Error::spawn(&line, e))?;
         if !out.status.success() {
-            return Err(anyhow!("failed to build crate"));
+            return Err(Error::process(line, out.status, "").into());
         }
         Ok(())
     }
@@ -0,0 +1,225 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use object::{read::ReadCache, Object, ObjectSection};
+
+use crate::build::{BuildSpec, File};
+use crate::deb::Priority;
+use crate::util::{self, walk};
+
+/// Directory that gdb and friends search for separate debug files by build ID.
+static DEBUG_DIR: &str = "/usr/lib/debug/.build-id";
+
+/// The two halves of a binary after its debug info has been split off.
+#[derive(Debug)]
+pub(crate) struct Split {
+    pub stripped: PathBuf,
+    pub debug: PathBuf,
+    pub build_id: String,
+}
+
+impl Split {
+    /// Install path of the debug file, e.g. /usr/lib/debug/.build-id/ab/cdef.debug
+    pub(crate) fn dst(&self) -> String {
+        let (dir, rest) = self.build_id.split_at(2);
+        format!("{}/{}/{}.debug", DEBUG_DIR, dir, rest)
+    }
+}
+
+/// Get the GNU build ID of an ELF file and whether it still has debug info. Returns None for
+/// files that aren't ELF. Only the headers and the sections looked at are read, so large files
+/// cost no more than small ones.
+fn inspect(path: &Path) -> Result<Option<(Option<String>, bool)>> {
+    let mut f = fs::File::open(path).map_err(|e| anyhow!("{}: could not read {:?}", e, path))?;
+    let mut magic = [0; 4];
+    match f.read_exact(&mut magic) {
+        Ok(()) if &magic == b"\x7fELF" => {}
+        Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => {
+            bail!("{}: could not read {:?}", e, path)
+        }
+        _ => return Ok(None),
+    }
+    let data = ReadCache::new(f);
+    let file = object::File::parse(&data)?;
+    let build_id = file.build_id()?.map(hex::encode);
+    let has_debug = file.sections().any(|s| {
+        s.name()
+            .is_ok_and(|n| n.starts_with(".debug_") || n.starts_with(".zdebug_"))
+    });
+    Ok(Some((build_id, has_debug)))
+}
+
+fn objcopy(args: &[&std::ffi::OsStr]) -> Result<()> {
+    let status = util::command("objcopy")?
+        .args(args)
+        .status()
+        .map_err(|e| anyhow!("{}: failed to run objcopy", e))?;
+    if !status.success() {
+        bail!("objcopy {:?} failed: {}", args, status);
+    }
+    Ok(())
+}
+
+/// Split the debug info out of an ELF binary, the same way dh_strip does, writing both halves
+/// into `work`. Files that aren't ELF, have no build ID or are already stripped are skipped.
+pub(crate) fn split<P: AsRef<Path>>(src: P, work: &Path) -> Result<Option<Split>> {
+    let src = src.as_ref();
+    if !src.is_file() {
+        return Ok(None);
+    }
+    let build_id = match inspect(src)? {
+        Some((Some(id), true)) if id.len() > 2 => id,
+        _ => return Ok(None),
+    };
+    let dir = work.join(&build_id);
+    fs::create_dir_all(&dir)?;
+    let name = src
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid file name {:?}", src))?;
+    let stripped = dir.join(name);
+    let debug = dir.join(format!("{}.debug", name.to_string_lossy()));
+    objcopy(&[
+        "--only-keep-debug".as_ref(),
+        "--compress-debug-sections".as_ref(),
+        src.as_os_str(),
+        debug.as_os_str(),
+    ])?;
+    objcopy(&[
+        "--strip-unneeded".as_ref(),
+        "--remove-section=.comment".as_ref(),
+        "--remove-section=.note".as_ref(),
+        "--add-gnu-debuglink".as_ref(),
+        debug.as_os_str(),
+        src.as_os_str(),
+        stripped.as_os_str(),
+    ])?;
+    Ok(Some(Split {
+        stripped,
+        debug,
+        build_id,
+    }))
+}
+
+/// Split every ELF binary in `src`, a file or a directory, returning the path of each binary
+/// with its split. Symlinks inside a directory are packaged as links, so they are skipped.
+pub(crate) fn split_all(src: &Path, work: &Path) -> Result<Vec<(PathBuf, Split)>> {
+    let mut files = Vec::new();
+    if src.is_dir() {
+        walk(src, |entry| {
+            if entry.file_type()?.is_file() {
+                files.push(entry.path());
+            }
+            Ok(())
+        })?;
+    } else {
+        files.push(src.to_path_buf());
+    }
+    let mut splits = Vec::new();
+    for file in files {
+        if let Some(s) = split(&file, work)? {
+            splits.push((file, s));
+        }
+    }
+    Ok(splits)
+}
+
+/// Create the spec for the companion <package>-dbgsym package.
+pub(crate) fn package(spec: &BuildSpec, splits: &[Split]) -> BuildSpec {
+    let mut files: Vec<File> = splits
+        .iter()
+        .map(|s| {
+            let mut f = File::new(s.debug.to_string_lossy().to_string(), s.dst());
+            f.mode = Some(0o644);
+            f
+        })
+        .collect();
+    files.sort_by_key(|f| f.dst.clone());
+    BuildSpec {
+        package: format!("{}-dbgsym", spec.package),
+        version: spec.version.clone(),
+        description: Some(format!("debug symbols for {}", spec.package)),
+        author: spec.author.clone(),
+        email: spec.email.clone(),
+        maintainer: spec.maintainer.clone(),
+        homepage: spec.homepage.clone(),
+        files,
+        dependencies: vec![format!("{} (= {})", spec.package, spec.version())],
+        priority: Priority::Optional,
+        arch: spec.arch.clone(),
+        section: Some("debug".to_string()),
+        buildno: spec.buildno,
+        compression: spec.compression,
+        compression_threads: spec.compression_threads,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::{inspect, package, split, split_all};
+    use crate::build::BuildSpec;
+
+    #[test]
+    fn split_debug_info() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work = tmp.path();
+        let src = work.join("main.c");
+        std::fs::write(&src, "int main(void) { return 0; }\n").unwrap();
+        let bin = work.join("main");
+        let ok = Command::new("cc")
+            .args(["-g", "-Wl,--build-id", "-o"])
+            .arg(&bin)
+            .arg(&src)
+            .status()
+            .is_ok_and(|s| s.success());
+        if !ok {
+            eprintln!("no C compiler, skipping");
+            return;
+        }
+        let s = split(&bin, &work.join("out")).unwrap().unwrap();
+        let (id, has_debug) = inspect(&s.stripped).unwrap().unwrap();
+        assert_eq!(id.as_deref(), Some(s.build_id.as_str()));
+        assert!(!has_debug);
+        assert!(inspect(&s.debug).unwrap().unwrap().1);
+        assert!(s.dst().starts_with("/usr/lib/debug/.build-id/"));
+        assert_eq!(
+            s.dst(),
+            format!(
+                "/usr/lib/debug/.build-id/{}/{}.debug",
+                &s.build_id[..2],
+                &s.build_id[2..]
+            )
+        );
+        // already stripped binaries and non-ELF files are left alone
+        assert!(split(&s.stripped, &work.join("again")).unwrap().is_none());
+        assert!(split(&src, &work.join("again")).unwrap().is_none());
+
+        let spec = BuildSpec {
+            package: "demo".to_string(),
+            version: "1.0".to_string(),
+            arch: "amd64".to_string(),
+            ..Default::default()
+        };
+        let dbg = package(&spec, &[s]);
+        assert_eq!(dbg.package, "demo-dbgsym");
+        assert_eq!(dbg.dependencies, vec!["demo (= 1.0)"]);
+        assert_eq!(dbg.files.len(), 1);
+
+        // binaries inside directory sources are split too, symlinks to them are not
+        let tree = work.join("tree");
+        std::fs::create_dir_all(tree.join("lib")).unwrap();
+        std::fs::copy(&bin, tree.join("lib/main")).unwrap();
+        std::os::unix::fs::symlink("lib/main", tree.join("main")).unwrap();
+        std::fs::write(tree.join("README"), "docs").unwrap();
+        let splits = split_all(&tree, &work.join("tree-out")).unwrap();
+        assert_eq!(splits.len(), 1);
+        assert_eq!(splits[0].0, tree.join("lib/main"));
+        assert!(split_all(&bin, &work.join("file-out")).unwrap()[0].0 == bin);
+    }
+}
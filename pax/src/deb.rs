@@ -1,8 +1,8 @@
 use std::{
     cmp::Ordering,
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs,
-    io::{self, Read, Write},
+    io::{self, Read, Seek, SeekFrom, Write},
     os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
 };
@@ -13,6 +13,7 @@ use mlua::{
     Lua,
 };
 
+use crate::scripts::Script;
 use crate::util::{mtime_now, to_io_err, walk, HashReader};
 use pax_derive::UserData as PaxUserData;
 
@@ -41,6 +42,17 @@ impl<W: Write> DebArchive<W> {
         self.builder.append(&head, data.as_slice())?;
         Ok(())
     }
+
+    /// Copy the whole of a file into the archive without loading it into memory.
+    pub(crate) fn append_file(&mut self, name: &str, file: &mut fs::File) -> io::Result<()> {
+        let size = file.seek(SeekFrom::End(0))?;
+        file.rewind()?;
+        let mut head = ar::Header::new(name.into(), size);
+        head.set_mode(0o644);
+        head.set_mtime(self.time);
+        self.builder.append(&head, file)?;
+        Ok(())
+    }
 }
 
 type HashPair = (md5::digest::Output<md5::Md5>, PathBuf);
@@ -52,6 +64,11 @@ pub(crate) struct DataBuilder<'a, W: Write> {
     dirs: HashSet<PathBuf>,
     hasher: md5::Md5,
     hashes: &'a mut Vec<HashPair>,
+    owner: Option<String>,
+    group: Option<String>,
+    /// Files whose content is read from another path, like binaries with their debug info split
+    /// off.
+    contents: HashMap<PathBuf, PathBuf>,
 }
 
 impl<'a, W: Write> DataBuilder<'a, W> {
@@ -63,13 +80,55 @@ impl<'a, W: Write> DataBuilder<'a, W> {
             hasher: md5::Md5::new(),
             hashes,
             size: 0,
+            owner: None,
+            group: None,
+            contents: HashMap::new(),
         }
     }
 
+    /// Package the content of `from` wherever `path` is added by `add_path`, keeping the mode of
+    /// `path`.
+    pub(crate) fn read_from(&mut self, path: PathBuf, from: PathBuf) {
+        self.contents.insert(path, from);
+    }
+
+    /// Open the file holding the content of `path` and get its size.
+    fn open_contents(&self, path: &Path) -> io::Result<(fs::File, u64)> {
+        let from = self.contents.get(path).map_or(path, PathBuf::as_path);
+        let file = fs::File::open(from).map_err(|e| {
+            io::Error::new(e.kind(), format!("{}: could not open file {:?}", e, from))
+        })?;
+        let size = file.metadata()?.size();
+        Ok((file, size))
+    }
+
+    /// Set the owner and group names of the entries added after this. dpkg looks the names up
+    /// on the target system so the account can be created by preinst. Parent directories are
+    /// always owned by root.
+    pub(crate) fn set_owner(&mut self, owner: Option<&str>, group: Option<&str>) {
+        self.owner = owner.map(String::from);
+        self.group = group.map(String::from);
+    }
+
+    fn set_header_owner(&self, header: &mut tar::Header) -> io::Result<()> {
+        header.set_uid(0);
+        header.set_gid(0);
+        if self.owner.is_some() || self.group.is_some() {
+            header.set_username(self.owner.as_deref().unwrap_or("root"))?;
+            header.set_groupname(self.group.as_deref().unwrap_or("root"))?;
+        }
+        Ok(())
+    }
+
     pub fn size(&self) -> u64 {
         self.size
     }
 
+    /// Write the end of the tarball and return the inner writer.
+    pub(crate) fn into_inner(self) -> io::Result<W> {
+        self.tar.into_inner()
+    }
+
     pub fn add_path<S, D>(&mut self, source: S, dest: D, mode: Option<u32>) -> io::Result<()>
     where
         S: AsRef<Path>,
@@ -91,17 +150,8 @@ impl<'a, W: Write> DataBuilder<'a, W> {
         if ft.is_symlink() {
             Err(to_io_err("symlinks are not supported file types"))
         } else if ft.is_file() {
-            self.add_reader(
-                &dst,
-                fs::File::open(&source).map_err(|e| {
-                    io::Error::new(
-                        e.kind(),
-                        format!("{}: could not open file {:?}", e, source.as_ref()),
-                    )
-                })?,
-                stat.size(),
-                mode,
-            )
+            let (file, size) = self.open_contents(source.as_ref())?;
+            self.add_reader(&dst, file, size, mode)
         } else if ft.is_dir() {
             walk(&source, |entry| {
                 let path = entry.path();
@@ -115,17 +165,8 @@ impl<'a, W: Write> DataBuilder<'a, W> {
                     self.tar
                         .append_link(&mut header, d, fs::read_link(&path)?)?;
                 } else if meta.is_file() {
-                    self.add_reader_metadata(
-                        d,
-                        fs::File::open(&path).map_err(|e| {
-                            io::Error::new(
-                                e.kind(),
-                                format!("{}: could not open file {:?}", e, &path),
-                            )
-                        })?,
-                        meta,
-                    )
-                    .map_err(|e| {
+                    let (file, size) = self.open_contents(&path)?;
+                    self.add_reader(d, file, size, meta.mode()).map_err(|e| {
                         io::Error::new(
                             e.kind(),
                             format!("{}: failed to walk directory {:?}", e, source.as_ref()),
@@ -148,11 +189,36 @@ impl<'a, W: Write> DataBuilder<'a, W> {
         let dst = strip_leading_slash(path);
         let p = dst.as_path();
         self.add_parent_directories(p)?;
-        self.directory_with_mode(p, mode)?;
+        // Children added later must not write the directory again with the default mode.
+        self.dirs.insert(p.to_path_buf());
+        self.directory_with_mode(p, mode, true)?;
         Ok(())
     }
 
-    fn add_reader<P, R>(&mut self, dest: P, reader: R, size: u64, mode: u32) -> io::Result<()>
+    /// Add a symlink at `dest` pointing to `target`, which is written as given.
+    pub(crate) fn add_symlink<P, T>(&mut self, dest: P, target: T) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        let dst = strip_leading_slash(dest);
+        self.add_parent_directories(&dst)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_mtime(self.time);
+        self.set_header_owner(&mut header)?;
+        self.tar.append_link(&mut header, dst, target)
+    }
+
+    pub(crate) fn add_reader<P, R>(
+        &mut self,
+        dest: P,
+        reader: R,
+        size: u64,
+        mode: u32,
+    ) -> io::Result<()>
     where
         P: AsRef<Path>,
         R: Read,
@@ -161,8 +227,7 @@ impl<'a, W: Write> DataBuilder<'a, W> {
         self.add_parent_directories(&dst)?;
         let mut head = tar::Header::new_gnu();
         head.set_mtime(self.time);
-        head.set_uid(0);
-        head.set_gid(0);
+        self.set_header_owner(&mut head)?;
         head.set_mode(mode);
         head.set_size(size);
         let r = HashReader {
@@ -175,25 +240,14 @@ impl<'a, W: Write> DataBuilder<'a, W> {
         Ok(())
     }
 
-    #[inline]
-    fn add_reader_metadata<P, R>(
-        &mut self,
-        dest: P,
-        reader: R,
-        meta: fs::Metadata,
-    ) -> io::Result<()>
-    where
-        P: AsRef<Path>,
-        R: Read,
-    {
-        self.add_reader(dest, reader, meta.size(), meta.mode())
-    }
-
-    fn directory_with_mode(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+    fn directory_with_mode(&mut self, path: &Path, mode: u32, owned: bool) -> io::Result<()> {
         let mut header = tar::Header::new_gnu();
         header.set_mtime(self.time);
         header.set_size(0);
         header.set_mode(mode);
+        if owned {
+            self.set_header_owner(&mut header)?;
+        }
         let mut path_str = path.to_string_lossy().to_string();
         if !path_str.ends_with('/') {
             path_str += "/";
@@ -222,7 +276,7 @@ impl<'a, W: Write> DataBuilder<'a, W> {
             }
             if !self.dirs.contains(&directory) {
                 self.dirs.insert(directory.clone());
-                self.directory_with_mode(&directory, mode)?;
+                self.directory_with_mode(&directory, mode, false)?;
             }
         }
         Ok(())
@@ -238,6 +292,22 @@ fn strip_leading_slash<P: AsRef<Path>>(path: P) -> PathBuf {
     }
 }
 
+/// Spec files name the variants of these enums the same way Lua strings do.
+macro_rules! deserialize_from_str {
+    ($($t:ty),*) => {
+        $(
+            impl<'de> serde::Deserialize<'de> for $t {
+                fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                    let s = String::deserialize(d)?;
+                    s.parse().map_err(serde::de::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+deserialize_from_str!(Urgency, Priority, Compression);
+
 #[derive(Copy, Clone, Debug, PartialEq, PaxUserData)]
 pub(crate) enum Urgency {
     Low,
@@ -258,6 +328,106 @@ pub(crate) enum Priority {
     Invalid,
 }
 
+/// Compression used for the data archive.
+#[derive(Copy, Clone, Debug, Default, PartialEq, pax_derive::UserDataWithDefault)]
+pub(crate) enum Compression {
+    #[default]
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Name of the data archive member.
+    pub(crate) fn data_member(&self) -> &'static str {
+        match self {
+            Self::Gzip => "data.tar.gz",
+            Self::Xz => "data.tar.xz",
+            Self::Zstd => "data.tar.zst",
+        }
+    }
+
+    /// Wrap a writer in an encoder. Threads are only used by xz and zstd, where 0 means one
+    /// per CPU.
+    pub(crate) fn encoder<'a, W: Write>(&self, w: W, threads: u32) -> io::Result<Encoder<'a, W>> {
+        let threads = if threads == 0 {
+            std::thread::available_parallelism().map_or(1, |n| n.get() as u32)
+        } else {
+            threads
+        };
+        Ok(match self {
+            Self::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                w,
+                flate2::Compression::default(),
+            )),
+            Self::Xz if threads > 1 => {
+                let stream = xz2::stream::MtStreamBuilder::new()
+                    .preset(6)
+                    .threads(threads)
+                    .encoder()
+                    .map_err(to_io_err)?;
+                Encoder::Xz(xz2::write::XzEncoder::new_stream(w, stream))
+            }
+            Self::Xz => Encoder::Xz(xz2::write::XzEncoder::new(w, 6)),
+            Self::Zstd => {
+                let mut enc = zstd::Encoder::new(w, 0)?;
+                if threads > 1 {
+                    enc.multithread(threads)?;
+                }
+                Encoder::Zstd(enc)
+            }
+        })
+    }
+}
+
+/// Wrap an archive member such as data.tar.xz in a decoder for the compression its name ends
+/// with.
+pub(crate) fn decoder<'a, R: Read + 'a>(name: &str, r: R) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(match name.rsplit_once(".tar") {
+        Some((_, "")) => Box::new(r),
+        Some((_, ".gz")) => Box::new(flate2::read::GzDecoder::new(r)),
+        Some((_, ".xz")) => Box::new(xz2::read::XzDecoder::new(r)),
+        Some((_, ".zst")) => Box::new(zstd::Decoder::new(r)?),
+        _ => return Err(to_io_err(format!("unsupported archive member {:?}", name))),
+    })
+}
+
+/// A compressing writer. [`Encoder::finish`] has to be called to write the end of the stream.
+pub(crate) enum Encoder<'a, W: Write> {
+    Gzip(flate2::write::GzEncoder<W>),
+    Xz(xz2::write::XzEncoder<W>),
+    Zstd(zstd::Encoder<'a, W>),
+}
+
+impl<W: Write> Encoder<'_, W> {
+    /// Finish the compressed stream and return the inner writer.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Gzip(e) => e.finish(),
+            Self::Xz(e) => e.finish(),
+            Self::Zstd(e) => e.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(e) => e.write(buf),
+            Self::Xz(e) => e.write(buf),
+            Self::Zstd(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Gzip(e) => e.flush(),
+            Self::Xz(e) => e.flush(),
+            Self::Zstd(e) => e.flush(),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub(crate) enum Architecture {
     #[default]
@@ -481,7 +651,8 @@ where
     }
 }
 
-#[derive(Default, Debug, Clone, pax_derive::IntoLua, pax_derive::FromLua)]
+#[derive(Default, Debug, Clone, pax_derive::IntoLua, serde::Deserialize)]
+#[serde(try_from = "ScriptsRepr")]
 pub(crate) struct MaintainerScripts {
     pub preinst: Option<String>,
     pub postinst: Option<String>,
@@ -489,10 +660,170 @@ pub(crate) struct MaintainerScripts {
     pub postrm: Option<String>,
 }
 
+impl MaintainerScripts {
+    pub(crate) fn get(&self, script: Script) -> Option<&str> {
+        match script {
+            Script::Preinst => self.preinst.as_deref(),
+            Script::Postinst => self.postinst.as_deref(),
+            Script::Prerm => self.prerm.as_deref(),
+            Script::Postrm => self.postrm.as_deref(),
+        }
+    }
+
+    /// Read any maintainer scripts found in a directory.
+    fn from_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut res = Self::default();
+        for script in Script::ALL {
+            let path = dir.as_ref().join(script.name());
+            if path.exists() {
+                *res.get_mut(script) = Some(read_script(&path)?);
+            }
+        }
+        Ok(res)
+    }
+
+    fn get_mut(&mut self, script: Script) -> &mut Option<String> {
+        match script {
+            Script::Preinst => &mut self.preinst,
+            Script::Postinst => &mut self.postinst,
+            Script::Prerm => &mut self.prerm,
+            Script::Postrm => &mut self.postrm,
+        }
+    }
+}
+
+fn read_script<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    crate::watch::track(&path);
+    fs::read_to_string(&path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("{}: could not read script {:?}", e, path.as_ref()),
+        )
+    })
+}
+
+/// The forms maintainer scripts can take in a spec file, which are the same as in Lua.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ScriptsRepr {
+    Dir(String),
+    Table(ScriptsTable),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptsTable {
+    dir: Option<String>,
+    preinst: Option<ScriptRepr>,
+    postinst: Option<ScriptRepr>,
+    prerm: Option<ScriptRepr>,
+    postrm: Option<ScriptRepr>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ScriptRepr {
+    Inline(String),
+    File { file: String },
+}
+
+impl TryFrom<ScriptsRepr> for MaintainerScripts {
+    type Error = io::Error;
+    fn try_from(value: ScriptsRepr) -> Result<Self, Self::Error> {
+        let (dir, scripts) = match value {
+            ScriptsRepr::Dir(dir) => return Self::from_dir(dir),
+            ScriptsRepr::Table(t) => (t.dir, [t.preinst, t.postinst, t.prerm, t.postrm]),
+        };
+        let mut res = match dir {
+            Some(dir) => Self::from_dir(dir)?,
+            None => Self::default(),
+        };
+        for (script, repr) in Script::ALL.into_iter().zip(scripts) {
+            *res.get_mut(script) = match repr {
+                None => continue,
+                Some(ScriptRepr::Inline(text)) => Some(text),
+                Some(ScriptRepr::File { file }) => Some(read_script(file)?),
+            };
+        }
+        Ok(res)
+    }
+}
+
+impl mlua::FromLua<'_> for MaintainerScripts {
+    /// Scripts can be given inline as strings, as a table with a "file" key, or all loaded from
+    /// the directory given by "dir". A plain string is treated as that directory.
+    fn from_lua(value: LuaValue<'_>, _lua: &'_ Lua) -> LuaResult<Self> {
+        use mlua::Value;
+        let table = match value {
+            Value::String(dir) => return Ok(Self::from_dir(dir.to_str()?)?),
+            Value::Table(t) => t,
+            _ => {
+                return Err(mlua::Error::FromLuaConversionError {
+                    from: value.type_name(),
+                    to: "MaintainerScripts",
+                    message: Some("\"MaintainerScripts\" must be a table".into()),
+                })
+            }
+        };
+        let mut res = match table.get::<_, Option<String>>("dir")? {
+            Some(dir) => Self::from_dir(dir)?,
+            None => Self::default(),
+        };
+        for script in Script::ALL {
+            let name = script.name();
+            let text = match table.get::<_, Value>(name)? {
+                Value::Nil => continue,
+                Value::String(s) => s.to_str()?.to_string(),
+                Value::Table(t) => read_script(t.get::<_, String>("file")?)?,
+                v => {
+                    return Err(mlua::Error::FromLuaConversionError {
+                        from: v.type_name(),
+                        to: "String",
+                        message: Some(format!("error at field {:?}", name)),
+                    })
+                }
+            };
+            *res.get_mut(script) = Some(text);
+        }
+        Ok(res)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{DataBuilder, Version};
-    use std::io::Write;
+    use super::{decoder, Compression, DataBuilder, DebArchive, MaintainerScripts, Version};
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn compression() {
+        let data = "pax ".repeat(4096);
+        for (c, threads) in [
+            (Compression::Gzip, 1),
+            (Compression::Xz, 1),
+            (Compression::Xz, 2),
+            (Compression::Zstd, 1),
+            (Compression::Zstd, 0),
+        ] {
+            let mut enc = c.encoder(Vec::new(), threads).unwrap();
+            enc.write_all(data.as_bytes()).unwrap();
+            let buf = enc.finish().unwrap();
+            let mut out = String::new();
+            match c {
+                Compression::Gzip => flate2::read::GzDecoder::new(buf.as_slice())
+                    .read_to_string(&mut out)
+                    .unwrap(),
+                Compression::Xz => xz2::read::XzDecoder::new(buf.as_slice())
+                    .read_to_string(&mut out)
+                    .unwrap(),
+                Compression::Zstd => zstd::Decoder::new(buf.as_slice())
+                    .unwrap()
+                    .read_to_string(&mut out)
+                    .unwrap(),
+            };
+            assert_eq!(out, data, "{:?} with {} threads", c, threads);
+        }
+    }
 
     #[test]
     fn data_builder() {
@@ -576,6 +907,69 @@ mod tests {
         }
     }
 
+    #[test]
+    fn archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("hello");
+        // odd sizes make ar pad the members
+        fs::write(&src, "hello").unwrap();
+        let control = b"not a tarball".to_vec();
+        for c in [Compression::Gzip, Compression::Xz, Compression::Zstd] {
+            let mut hashes = Vec::new();
+            let mut b = DataBuilder::new(
+                c.encoder(tempfile::tempfile().unwrap(), 1).unwrap(),
+                &mut hashes,
+            );
+            b.add_path(&src, "/usr/share/hello", None).unwrap();
+            let mut data = b.into_inner().unwrap().finish().unwrap();
+            let data_size = data.seek(SeekFrom::End(0)).unwrap();
+            let mut deb = DebArchive::new(Vec::new(), 0);
+            deb.init().unwrap();
+            deb.append_vec("control.tar.gz", control.clone()).unwrap();
+            deb.append_file(c.data_member(), &mut data).unwrap();
+            let buf = deb.builder.into_inner().unwrap();
+
+            // Walk the members by hand to check the sizes, padding and order.
+            assert_eq!(&buf[..8], b"!<arch>\n");
+            let mut members = Vec::new();
+            let mut pos = 8;
+            while pos < buf.len() {
+                let header = std::str::from_utf8(&buf[pos..pos + 60]).unwrap();
+                let name = header[..16].trim_end().to_string();
+                let size: usize = header[48..58].trim_end().parse().unwrap();
+                pos += 60 + size;
+                if size % 2 == 1 {
+                    assert_eq!(buf[pos], b'\n', "{} is padded", name);
+                    pos += 1;
+                }
+                members.push((name, size));
+            }
+            assert_eq!(pos, buf.len());
+            assert_eq!(
+                members,
+                [
+                    ("debian-binary".to_string(), 4),
+                    ("control.tar.gz".to_string(), control.len()),
+                    (c.data_member().to_string(), data_size as usize),
+                ]
+            );
+
+            let mut ar = ar::Archive::new(buf.as_slice());
+            ar.next_entry().unwrap().unwrap();
+            ar.next_entry().unwrap().unwrap();
+            let entry = ar.next_entry().unwrap().unwrap();
+            let mut tar = tar::Archive::new(decoder(c.data_member(), entry).unwrap());
+            let mut found = String::new();
+            for entry in tar.entries().unwrap() {
+                let mut entry = entry.unwrap();
+                if entry.path().unwrap().ends_with("usr/share/hello") {
+                    entry.read_to_string(&mut found).unwrap();
+                }
+            }
+            assert_eq!(found, "hello", "{:?}", c);
+        }
+    }
+
     #[test]
     fn get_debian_data() {
         let mut s = String::new();
@@ -694,4 +1088,33 @@ mod tests {
         println!();
         res
     }
+
+    #[test]
+    fn scripts_from_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::write(dir.join("postinst"), "echo postinst\n").unwrap();
+        std::fs::write(dir.join("prerm"), "echo prerm\n").unwrap();
+        std::fs::write(dir.join("other.sh"), "echo other\n").unwrap();
+        let lua = mlua::Lua::new();
+        lua.globals().set("dir", dir.to_str()).unwrap();
+        let scripts: MaintainerScripts = lua
+            .load(
+                r#"{
+                    dir = dir,
+                    prerm = "echo inline",
+                    postrm = { file = dir .. "/other.sh" },
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(scripts.preinst, None);
+        assert_eq!(scripts.postinst.as_deref(), Some("echo postinst\n"));
+        assert_eq!(scripts.prerm.as_deref(), Some("echo inline"));
+        assert_eq!(scripts.postrm.as_deref(), Some("echo other\n"));
+        let res: mlua::Result<MaintainerScripts> = lua
+            .load(r#"{ preinst = { file = dir .. "/missing" } }"#)
+            .eval();
+        assert!(res.is_err());
+    }
 }
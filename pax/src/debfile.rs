@@ -0,0 +1,331 @@
+//! Reading .deb files back: the control fields, the other control archive files and every
+//! entry in the data archive.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use md5::Digest;
+use mlua::{AnyUserData, Lua, UserData, Value};
+
+use crate::control::{self, Paragraph};
+use crate::deb;
+use crate::error::Error;
+use crate::project::Project;
+use crate::util::{to_io_err, HashReader};
+
+/// A file, directory or link in the data archive.
+#[derive(Clone, Debug, Default, PartialEq, pax_derive::IntoLua)]
+pub(crate) struct Entry {
+    /// Where the entry is installed, e.g. "/usr/bin/foo".
+    pub path: String,
+    /// "file", "dir", "symlink" or "hardlink".
+    pub kind: String,
+    /// Permission bits, including the setuid, setgid and sticky bits.
+    pub mode: u32,
+    pub owner: String,
+    pub group: String,
+    pub uid: u64,
+    pub gid: u64,
+    pub size: u64,
+    /// Target of a symlink or hard link.
+    pub target: Option<String>,
+    /// md5 of a regular file's content.
+    pub md5: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DebFile {
+    pub path: PathBuf,
+    pub control: Paragraph,
+    /// The rest of the control archive by name, such as the maintainer scripts, conffiles and
+    /// md5sums.
+    pub control_files: BTreeMap<String, String>,
+    /// Name of the data archive member, which tells the compression used.
+    pub data_member: String,
+    /// Entries of the data archive sorted by path.
+    pub entries: Vec<Entry>,
+}
+
+impl DebFile {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = fs::File::open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: could not open {:?}", e, path)))?;
+        let mut deb = Self {
+            path: path.to_path_buf(),
+            ..Default::default()
+        };
+        let mut archive = ar::Archive::new(file);
+        while let Some(member) = archive.next_entry() {
+            let member = member?;
+            let name = String::from_utf8_lossy(member.header().identifier()).to_string();
+            if name.starts_with("control.tar") {
+                deb.read_control(deb::decoder(&name, member)?)?;
+            } else if name.starts_with("data.tar") {
+                deb.read_data(deb::decoder(&name, member)?)?;
+                deb.data_member = name;
+            }
+        }
+        if deb.control.is_empty() {
+            return Err(to_io_err(format!("{:?} has no control file", path)));
+        }
+        if deb.data_member.is_empty() {
+            return Err(to_io_err(format!("{:?} has no data archive", path)));
+        }
+        deb.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(deb)
+    }
+
+    fn read_control<R: Read>(&mut self, r: R) -> io::Result<()> {
+        let mut ball = tar::Archive::new(r);
+        for entry in ball.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = install_path(&entry.path()?);
+            let name = name.trim_start_matches('/').to_string();
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            if name == "control" {
+                self.control = control::parse(&text)?
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default();
+            } else {
+                self.control_files.insert(name, text);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_data<R: Read>(&mut self, r: R) -> io::Result<()> {
+        let mut ball = tar::Archive::new(r);
+        for entry in ball.entries()? {
+            let mut entry = entry?;
+            let path = install_path(&entry.path()?);
+            if path == "/" {
+                continue;
+            }
+            let header = entry.header();
+            // Parent directories are written without ids, which dpkg reads as root.
+            let uid = header.uid().unwrap_or(0);
+            let gid = header.gid().unwrap_or(0);
+            let name = |name: Option<&str>, id: u64| match name {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ if id == 0 => "root".to_string(),
+                _ => id.to_string(),
+            };
+            let kind = header.entry_type();
+            let mut e = Entry {
+                path,
+                kind: match kind {
+                    tar::EntryType::Directory => "dir",
+                    tar::EntryType::Symlink => "symlink",
+                    tar::EntryType::Link => "hardlink",
+                    _ => "file",
+                }
+                .to_string(),
+                mode: header.mode()? & 0o7777,
+                owner: name(header.username().ok().flatten(), uid),
+                group: name(header.groupname().ok().flatten(), gid),
+                uid,
+                gid,
+                size: header.size()?,
+                target: entry.link_name()?.map(|target| match kind {
+                    tar::EntryType::Link => install_path(&target),
+                    _ => target.to_string_lossy().to_string(),
+                }),
+                md5: None,
+            };
+            if kind.is_file() {
+                let mut h = md5::Md5::new();
+                io::copy(
+                    &mut HashReader {
+                        r: &mut entry,
+                        h: &mut h,
+                    },
+                    &mut io::sink(),
+                )?;
+                e.md5 = Some(hex::encode(h.finalize()));
+            }
+            self.entries.push(e);
+        }
+        Ok(())
+    }
+
+    /// Extract the data archive into `root`, keeping the modes of the entries but not their
+    /// owners.
+    pub(crate) fn unpack<P: AsRef<Path>>(&self, root: P) -> io::Result<()> {
+        let mut archive = ar::Archive::new(fs::File::open(&self.path)?);
+        while let Some(member) = archive.next_entry() {
+            let member = member?;
+            let name = String::from_utf8_lossy(member.header().identifier()).to_string();
+            if name != self.data_member {
+                continue;
+            }
+            let mut ball = tar::Archive::new(deb::decoder(&name, member)?);
+            ball.set_preserve_permissions(true);
+            ball.set_overwrite(true);
+            for entry in ball.entries()? {
+                entry?.unpack_in(root.as_ref())?;
+            }
+            return Ok(());
+        }
+        Err(to_io_err(format!("{:?} has no data archive", self.path)))
+    }
+
+    /// Paths listed in the conffiles control file.
+    pub(crate) fn conffiles(&self) -> Vec<String> {
+        self.control_files
+            .get("conffiles")
+            .map(|text| {
+                // Lines can start with flags like "remove-on-upgrade".
+                text.lines()
+                    .filter_map(|l| l.split_whitespace().last())
+                    .map(|l| install_path(Path::new(l)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn entry(&self, path: &str) -> Option<&Entry> {
+        let path = install_path(Path::new(path));
+        self.entries
+            .binary_search_by(|e| e.path.cmp(&path))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    pub(crate) fn field(&self, name: &str) -> Option<&str> {
+        self.control.get(name)
+    }
+}
+
+/// An archive path like "./usr/bin/" as the absolute path "/usr/bin".
+pub(crate) fn install_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    format!("/{}", path.trim_end_matches('/'))
+}
+
+/// A .deb file opened from Lua.
+pub(crate) struct Deb(pub DebFile);
+
+impl UserData for Deb {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("path", |_, this| {
+            Ok(this.0.path.to_string_lossy().to_string())
+        });
+        for (name, field) in [
+            ("package", "Package"),
+            ("version", "Version"),
+            ("arch", "Architecture"),
+        ] {
+            fields.add_field_method_get(name, move |_, this| {
+                Ok(this.0.field(field).map(String::from))
+            });
+        }
+        fields.add_field_method_get("control", |lua, this| {
+            lua.create_table_from(
+                this.0
+                    .control
+                    .fields()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone())),
+            )
+        });
+        fields.add_field_method_get("scripts", |_, this| Ok(this.0.control_files.clone()));
+        fields.add_field_method_get("files", |_, this| Ok(this.0.entries.clone()));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("file", |_, this, path: String| {
+            Ok(this.0.entry(&path).cloned())
+        });
+        methods.add_method("field", |_, this, name: String| {
+            Ok(this.0.field(&name).map(String::from))
+        });
+        methods.add_method(
+            "copy",
+            |_, this, (project, patterns): (AnyUserData, Option<Value>)| {
+                let patterns = match patterns {
+                    None | Some(Value::Nil) => Vec::new(),
+                    Some(Value::String(s)) => vec![s.to_str()?.to_string()],
+                    Some(Value::Table(t)) => t.sequence_values().collect::<mlua::Result<_>>()?,
+                    Some(v) => {
+                        return Err(mlua::Error::runtime(format!(
+                            "patterns must be a string or a list of strings, got {}",
+                            v.type_name()
+                        )))
+                    }
+                };
+                let mut project = project.borrow_mut::<Project>()?;
+                Ok(project.import(&this.0, &patterns)?)
+            },
+        );
+        methods.add_method("inherit_deps", |_, this, project: AnyUserData| {
+            Ok(project.borrow_mut::<Project>()?.inherit_deps(&this.0))
+        });
+    }
+}
+
+/// Open a .deb file for Lua.
+pub(crate) fn open(_: &Lua, path: String) -> mlua::Result<Deb> {
+    match DebFile::open(&path) {
+        Ok(deb) => Ok(Deb(deb)),
+        Err(e) => Err(Error::archive(path, e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use md5::Digest;
+
+    use super::install_path;
+    use crate::build::tests::{test_spec, write_test_deb};
+    use crate::build::{BuildSpec, File};
+    use crate::deb::{Compression, MaintainerScripts};
+
+    #[test]
+    fn read() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("tool");
+        std::fs::write(&bin, "#!/bin/sh\necho hi\n").unwrap();
+        for compression in [Compression::Gzip, Compression::Xz, Compression::Zstd] {
+            let files = vec![File {
+                mode: Some(0o4755),
+                ..File::new(bin.to_str().unwrap(), "/usr/bin/tool")
+            }];
+            let spec = BuildSpec {
+                compression,
+                scripts: Some(MaintainerScripts {
+                    postinst: Some("echo installed".to_string()),
+                    ..Default::default()
+                }),
+                ..test_spec("tool", "1.0.0", files)
+            };
+            let dist = dir.path().join(format!("dist-{:?}", compression));
+            let deb = write_test_deb(spec, &dist);
+            assert_eq!(deb.data_member, compression.data_member());
+            assert_eq!(deb.field("package"), Some("tool"));
+            assert!(deb.control_files["postinst"].contains("echo installed"));
+            let entry = deb.entry("usr/bin/tool/").unwrap();
+            assert_eq!(entry.kind, "file");
+            assert_eq!(entry.mode, 0o4755);
+            assert_eq!(entry.owner, "root");
+            assert_eq!(entry.size, 18);
+            let md5 = hex::encode(md5::Md5::digest("#!/bin/sh\necho hi\n"));
+            assert_eq!(entry.md5, Some(md5));
+            assert_eq!(deb.entry("/usr/bin").unwrap().kind, "dir");
+        }
+        assert_eq!(install_path(Path::new("./usr/share/")), "/usr/share");
+        assert_eq!(install_path(Path::new("./")), "/");
+    }
+}
@@ -0,0 +1,377 @@
+//! `pax diff`: what changed between two versions of a package.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::debfile::{DebFile, Entry};
+
+/// Lines of context around each change in a script diff.
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Diff {
+    pub control: Vec<FieldChange>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<FileChange>,
+    pub scripts: Vec<ScriptDiff>,
+}
+
+/// A control field that was added, removed or changed.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    fn of(old: T, new: T) -> Option<Self> {
+        (old != new).then_some(Self { old, new })
+    }
+}
+
+/// A path in both packages whose content or attributes differ.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct FileChange {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<Change<String>>,
+    /// The md5 of the content.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<Change<Option<String>>>,
+    /// Written in octal, like "0755".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Change<String>>,
+    /// Written as "owner:group".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<Change<Option<String>>>,
+}
+
+/// A maintainer script or other control file that was added, removed or changed.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ScriptDiff {
+    pub name: String,
+    /// The change as a unified diff.
+    pub diff: String,
+}
+
+/// Compare two packages.
+pub(crate) fn diff(old: &DebFile, new: &DebFile) -> Diff {
+    let mut d = Diff::default();
+    for (field, value) in old.control.fields() {
+        let change = Change::of(Some(value.as_str()), new.field(field));
+        if let Some(change) = change {
+            d.control.push(FieldChange {
+                field: field.clone(),
+                old: change.old.map(String::from),
+                new: change.new.map(String::from),
+            });
+        }
+    }
+    for (field, value) in new.control.fields() {
+        if old.field(field).is_none() {
+            d.control.push(FieldChange {
+                field: field.clone(),
+                old: None,
+                new: Some(value.clone()),
+            });
+        }
+    }
+
+    for entry in &old.entries {
+        match new.entry(&entry.path) {
+            None => d.removed.push(entry.path.clone()),
+            Some(other) => d.changed.extend(file_change(entry, other)),
+        }
+    }
+    for entry in &new.entries {
+        if old.entry(&entry.path).is_none() {
+            d.added.push(entry.path.clone());
+        }
+    }
+
+    // md5sums changes with every file, which the file changes already cover.
+    let names = old
+        .control_files
+        .keys()
+        .chain(
+            new.control_files
+                .keys()
+                .filter(|k| !old.control_files.contains_key(*k)),
+        )
+        .filter(|name| *name != "md5sums");
+    for name in names {
+        let a = old.control_files.get(name).map_or("", String::as_str);
+        let b = new.control_files.get(name).map_or("", String::as_str);
+        if a != b {
+            d.scripts.push(ScriptDiff {
+                name: name.clone(),
+                diff: unified(a, b),
+            });
+        }
+    }
+    d
+}
+
+fn file_change(old: &Entry, new: &Entry) -> Option<FileChange> {
+    let owner = |e: &Entry| format!("{}:{}", e.owner, e.group);
+    let change = FileChange {
+        path: old.path.clone(),
+        kind: Change::of(old.kind.clone(), new.kind.clone()),
+        content: Change::of(old.md5.clone(), new.md5.clone()),
+        mode: Change::of(format!("{:04o}", old.mode), format!("{:04o}", new.mode)),
+        owner: Change::of(owner(old), owner(new)),
+        target: Change::of(old.target.clone(), new.target.clone()),
+    };
+    let same = change.kind.is_none()
+        && change.content.is_none()
+        && change.mode.is_none()
+        && change.owner.is_none()
+        && change.target.is_none();
+    (!same).then_some(change)
+}
+
+/// A unified diff of two texts, without the file header lines.
+fn unified(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    // Length of the longest common subsequence of a[i..] and b[j..].
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+    // Each line of the edit script with its line number in the old and new text.
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((' ', a[i], i, j));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', a[i], i, j));
+            i += 1;
+        } else {
+            ops.push(('+', b[j], i, j));
+            j += 1;
+        }
+    }
+
+    let mut out = String::new();
+    let changes: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    let mut k = 0;
+    while k < changes.len() {
+        let start = changes[k].saturating_sub(CONTEXT);
+        let mut end = changes[k];
+        while k < changes.len() && changes[k] <= end + 2 * CONTEXT {
+            end = changes[k];
+            k += 1;
+        }
+        let end = (end + CONTEXT + 1).min(ops.len());
+        let hunk = &ops[start..end];
+        let old_len = hunk.iter().filter(|op| op.0 != '+').count();
+        let new_len = hunk.iter().filter(|op| op.0 != '-').count();
+        let (_, _, old_start, new_start) = hunk[0];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        ));
+        for (op, line, _, _) in hunk {
+            out.push(*op);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// A hunk range like "3,4", counting lines from 1.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+impl Diff {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.control.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.scripts.is_empty()
+    }
+
+    pub(crate) fn write_text<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        if self.is_empty() {
+            return writeln!(w, "no differences");
+        }
+        if !self.control.is_empty() {
+            writeln!(w, "control:")?;
+            for c in &self.control {
+                match (&c.old, &c.new) {
+                    (Some(old), Some(new)) => writeln!(w, "  ~ {}: {} -> {}", c.field, old, new)?,
+                    (None, Some(new)) => writeln!(w, "  + {}: {}", c.field, new)?,
+                    (Some(old), None) => writeln!(w, "  - {}: {}", c.field, old)?,
+                    (None, None) => {}
+                }
+            }
+        }
+        if !self.added.is_empty() || !self.removed.is_empty() || !self.changed.is_empty() {
+            writeln!(w, "files:")?;
+            for path in &self.added {
+                writeln!(w, "  + {}", path)?;
+            }
+            for path in &self.removed {
+                writeln!(w, "  - {}", path)?;
+            }
+            for c in &self.changed {
+                let mut what = Vec::new();
+                if let Some(kind) = &c.kind {
+                    what.push(format!("{} -> {}", kind.old, kind.new));
+                }
+                if c.content.is_some() {
+                    what.push("content".to_string());
+                }
+                if let Some(mode) = &c.mode {
+                    what.push(format!("mode {} -> {}", mode.old, mode.new));
+                }
+                if let Some(owner) = &c.owner {
+                    what.push(format!("owner {} -> {}", owner.old, owner.new));
+                }
+                if let Some(target) = &c.target {
+                    let show = |t: &Option<String>| t.clone().unwrap_or_else(|| "-".to_string());
+                    what.push(format!(
+                        "target {} -> {}",
+                        show(&target.old),
+                        show(&target.new)
+                    ));
+                }
+                writeln!(w, "  ~ {} ({})", c.path, what.join(", "))?;
+            }
+        }
+        if !self.scripts.is_empty() {
+            writeln!(w, "scripts:")?;
+            for s in &self.scripts {
+                writeln!(w, "  {}:", s.name)?;
+                for line in s.diff.lines() {
+                    writeln!(w, "    {}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{diff, unified, Change, FieldChange};
+    use crate::build::tests::{test_spec, write_test_deb};
+    use crate::build::{BuildSpec, File};
+    use crate::deb::MaintainerScripts;
+
+    #[test]
+    fn packages() {
+        assert_eq!(
+            unified(
+                "a\nb\nc\nd\ne\nf\ng\nh\ni\n",
+                "a\nb\nc\nd\nE\nf\ng\nh\ni\nj\n"
+            ),
+            "@@ -2,8 +2,9 @@\n b\n c\n d\n-e\n+E\n f\n g\n h\n i\n+j\n"
+        );
+        assert_eq!(unified("", "x\n"), "@@ -0,0 +1 @@\n+x\n");
+
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path.to_string_lossy().to_string()
+        };
+        let (a, b, c) = (write("a", "a"), write("b", "b"), write("c", "c"));
+        let build = |version: &str, files: Vec<File>, postinst: &str| {
+            let spec = BuildSpec {
+                scripts: Some(MaintainerScripts {
+                    postinst: Some(postinst.to_string()),
+                    ..Default::default()
+                }),
+                ..test_spec("app", version, files)
+            };
+            write_test_deb(spec, &dir.path().join(version))
+        };
+        let old = build(
+            "1.0",
+            vec![
+                File::new(a.as_str(), "/usr/bin/a"),
+                File::new(b.as_str(), "/usr/bin/b"),
+                File::new(c.as_str(), "/usr/share/c"),
+            ],
+            "echo one\necho two",
+        );
+        let new = build(
+            "1.1",
+            vec![
+                File {
+                    mode: Some(0o755),
+                    ..File::new(b.as_str(), "/usr/bin/a")
+                },
+                File::new(b.as_str(), "/usr/bin/b"),
+                File::new(c.as_str(), "/usr/lib/c"),
+            ],
+            "echo one\necho three",
+        );
+        let d = diff(&old, &new);
+        assert_eq!(
+            d.control[0],
+            FieldChange {
+                field: "Version".to_string(),
+                old: Some("1.0".to_string()),
+                new: Some("1.1".to_string()),
+            }
+        );
+        assert_eq!(d.added, ["/usr/lib", "/usr/lib/c"]);
+        assert_eq!(d.removed, ["/usr/share", "/usr/share/c"]);
+        assert_eq!(d.changed.len(), 1, "{:?}", d.changed);
+        assert_eq!(d.changed[0].path, "/usr/bin/a");
+        assert_eq!(
+            d.changed[0].mode,
+            Change::of("0644".to_string(), "0755".to_string())
+        );
+        assert!(d.changed[0].content.is_some());
+        assert_eq!(d.scripts.len(), 1);
+        assert_eq!(d.scripts[0].name, "postinst");
+        assert!(d.scripts[0].diff.contains("-echo two\n+echo three\n"));
+
+        let mut text = Vec::new();
+        d.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("  ~ Version: 1.0 -> 1.1\n"), "{}", text);
+        assert!(
+            text.contains("  ~ /usr/bin/a (content, mode 0644 -> 0755)\n"),
+            "{}",
+            text
+        );
+        let json = serde_json::to_value(&d).unwrap();
+        assert_eq!(json["changed"][0]["mode"]["new"], "0755");
+        assert!(json["changed"][0].get("owner").is_none());
+        assert!(diff(&old, &old).is_empty());
+    }
+}
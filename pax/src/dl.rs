@@ -4,7 +4,11 @@ use std::path::Path;
 use std::{fs, str};
 
 use anyhow::Result;
-use hyper::body::Buf;
+
+use crate::error::Error;
+use crate::plan;
+use crate::sandbox;
+use crate::testing;
 
 #[derive(Clone, Default, pax_derive::FromLuaTable)]
 pub struct DownloadOpts {
@@ -12,8 +16,7 @@ pub struct DownloadOpts {
     pub release: Option<String>,
     pub arch: Option<String>,
     pub out: Option<String>,
-    // 1 - gzip
-    // 2 - xz
+    /// 1 for gzip, 2 for xz
     pub compression: Option<i32>,
 }
 
@@ -58,7 +61,7 @@ pub(crate) fn fetch(url: String, opts: DownloadOpts) -> Result<()> {
 pub(crate) fn kubectl(opts: DownloadOpts) -> Result<String> {
     let runtime = runtime()?;
     let mut release = opt!(opts, release, "stable").to_string();
-    if release == "stable" {
+    if release == "stable" && !plan::dry_run() && !testing::is_mocked(testing::Kind::Dl) {
         release = runtime.block_on(get_string("https://dl.k8s.io/release/stable.txt"))?;
     }
     let u = format!(
@@ -169,10 +172,17 @@ fn client() -> Client {
 static REDIRECT_LIMIT: u8 = 10;
 
 async fn download(u: &str, out: &str, mode: u32, compression: Option<i32>) -> Result<()> {
-    let client = client();
-    let res = get(u, client).await?;
-    let body = res.into_body();
-    let mut body_bytes = hyper::body::to_bytes(body).await?.reader();
+    sandbox::check_write("download", out)?;
+    let body = if plan::skip(format!("download {} -> {}", u, out)) {
+        // Mocked downloads in `pax test` can still write a fixture.
+        match testing::fixture(u) {
+            Some(path) => fs::read(path)?,
+            None => return Ok(()),
+        }
+    } else {
+        get_bytes(u).await?
+    };
+    let mut body_bytes = io::Cursor::new(body);
     if let Some(p) = Path::new(&out).parent() {
         _ = fs::create_dir_all(p);
     }
@@ -198,6 +208,25 @@ async fn download(u: &str, out: &str, mode: u32, compression: Option<i32>) -> Re
     Ok(())
 }
 
+/// Download a file into memory.
+pub(crate) fn fetch_bytes(url: &str) -> Result<Vec<u8>> {
+    if testing::mocked(&format!("download {}", url)) {
+        return Ok(testing::fixture(url)
+            .map(fs::read)
+            .transpose()?
+            .unwrap_or_default());
+    }
+    runtime()?.block_on(get_bytes(url))
+}
+
+async fn get_bytes(u: &str) -> Result<Vec<u8>> {
+    let body = async {
+        let res = get(u, client()).await?;
+        Ok::<_, anyhow::Error>(hyper::body::to_bytes(res.into_body()).await?.to_vec())
+    };
+    Ok(body.await.map_err(|e| Error::download(u, e))?)
+}
+
 async fn get_string(u: &str) -> Result<String> {
     let client = client();
     let res = get(u, client).await?;
@@ -212,6 +241,7 @@ async fn get(u: &str, client: Client) -> Result<hyper::Response<hyper::Body>> {
         if i > REDIRECT_LIMIT {
             anyhow::bail!("too many redirects");
         }
+        sandbox::check_url("download", &url)?;
         let req = hyper::Request::builder()
             .method("GET")
             .uri(url)
@@ -1,5 +1,14 @@
 use mlua;
-use std::{error, fmt, io};
+use std::{
+    error, fmt, fs,
+    io::{self, IsTerminal},
+    path::Path,
+    process::ExitStatus,
+};
+
+use crate::sandbox;
+
+type BoxError = Box<dyn error::Error + Send + Sync>;
 
 #[derive(Debug)]
 pub(crate) enum Error {
@@ -7,6 +16,29 @@ pub(crate) enum Error {
     Fmt(fmt::Error),
     Lua(mlua::Error),
     Str(String),
+    /// A call blocked by --sandbox.
+    Denied(sandbox::Denied),
+    /// Building a package failed.
+    Build {
+        package: String,
+        source: BoxError,
+    },
+    /// Downloading a file failed.
+    Download {
+        url: String,
+        source: BoxError,
+    },
+    /// Reading or writing a package archive failed.
+    Archive {
+        path: String,
+        source: BoxError,
+    },
+    /// An external command could not be started, or exited with an error.
+    Process {
+        command: String,
+        status: Option<ExitStatus>,
+        source: Option<BoxError>,
+    },
 }
 
 impl fmt::Display for Error {
@@ -16,13 +48,32 @@ impl fmt::Display for Error {
             Self::Fmt(e) => e.fmt(f),
             Self::Lua(e) => e.fmt(f),
             Self::Str(s) => f.write_str(s),
+            Self::Denied(d) => d.fmt(f),
+            Self::Build { package, .. } => write!(f, "failed to build package {}", package),
+            Self::Download { url, .. } => write!(f, "failed to download {}", url),
+            Self::Archive { path, .. } => write!(f, "failed to read or write archive {}", path),
+            Self::Process {
+                command,
+                status: Some(status),
+                ..
+            } => write!(f, "command `{}` failed with {}", command, status),
+            Self::Process { command, .. } => write!(f, "could not run `{}`", command),
         }
     }
 }
 
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        self.inner().source()
+        match self {
+            Self::Io(e) => e.source(),
+            Self::Fmt(e) => e.source(),
+            Self::Lua(e) => e.source(),
+            Self::Str(_) | Self::Denied(_) => None,
+            Self::Build { source, .. }
+            | Self::Download { source, .. }
+            | Self::Archive { source, .. } => Some(source.as_ref()),
+            Self::Process { source, .. } => source.as_deref().map(|e| e as _),
+        }
     }
 }
 
@@ -44,24 +95,23 @@ impl From<fmt::Error> for Error {
     }
 }
 
-impl Into<mlua::prelude::LuaError> for Error {
-    fn into(self) -> mlua::prelude::LuaError {
-        match self {
-            Self::Lua(e) => e,
-            Self::Io(e) => mlua::Error::external(e.to_string()),
-            Self::Str(s) => mlua::Error::external(s),
-            Self::Fmt(e) => mlua::Error::external(e),
+impl From<Error> for mlua::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Lua(e) => e,
+            Error::Io(e) => mlua::Error::external(e),
+            Error::Str(s) => mlua::Error::external(s),
+            Error::Fmt(e) => mlua::Error::external(e),
+            e => mlua::Error::external(e),
         }
     }
 }
 
-impl Into<io::Error> for Error {
-    fn into(self) -> io::Error {
-        match self {
-            Self::Io(e) => e,
-            Self::Fmt(e) => io::Error::new(io::ErrorKind::Other, e),
-            Self::Lua(e) => io::Error::new(io::ErrorKind::Other, e),
-            Self::Str(e) => io::Error::new(io::ErrorKind::Other, e),
+impl From<Error> for io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::Io(e) => e,
+            e => io::Error::other(e),
         }
     }
 }
@@ -71,12 +121,44 @@ impl Error {
         Self::Str(msg.to_string())
     }
 
-    fn inner(&self) -> &(dyn error::Error + 'static) {
-        match self {
-            Self::Io(e) => e,
-            Self::Fmt(e) => e,
-            Self::Lua(e) => e,
-            Self::Str(_) => self,
+    pub(crate) fn build<S: Into<String>, E: Into<BoxError>>(package: S, source: E) -> Self {
+        Self::Build {
+            package: package.into(),
+            source: source.into(),
+        }
+    }
+
+    pub(crate) fn download<S: Into<String>, E: Into<BoxError>>(url: S, source: E) -> Self {
+        Self::Download {
+            url: url.into(),
+            source: source.into(),
+        }
+    }
+
+    pub(crate) fn archive<P: AsRef<Path>, E: Into<BoxError>>(path: P, source: E) -> Self {
+        Self::Archive {
+            path: path.as_ref().display().to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// A command that exited with a failing status. `output` is what it wrote to stderr, if it
+    /// was captured.
+    pub(crate) fn process<S: Into<String>>(command: S, status: ExitStatus, output: &str) -> Self {
+        let output = output.trim();
+        Self::Process {
+            command: command.into(),
+            status: Some(status),
+            source: (!output.is_empty()).then(|| output.into()),
+        }
+    }
+
+    /// A command that could not be started.
+    pub(crate) fn spawn<S: Into<String>>(command: S, source: io::Error) -> Self {
+        Self::Process {
+            command: command.into(),
+            status: None,
+            source: Some(source.into()),
         }
     }
 
@@ -86,5 +168,240 @@ impl Error {
     }
 }
 
+/// What an error report is made of, taken apart from the layers of Lua and Rust errors.
+#[derive(Debug, Default, PartialEq)]
+struct Report {
+    /// Messages from the outermost error to its root cause.
+    causes: Vec<String>,
+    /// The Lua stack when the error was raised.
+    traceback: Option<String>,
+}
+
+impl Report {
+    fn new(err: &(dyn error::Error + 'static)) -> Self {
+        let mut report = Self::default();
+        report.add(err);
+        report
+    }
+
+    fn add(&mut self, err: &(dyn error::Error + 'static)) {
+        if let Some(e) = err.downcast_ref::<mlua::Error>() {
+            return self.add_lua(e);
+        }
+        if let Some(Error::Lua(e)) = err.downcast_ref::<Error>() {
+            return self.add_lua(e);
+        }
+        self.push(err.to_string());
+        if let Some(source) = err.source() {
+            self.add(source);
+        }
+    }
+
+    fn add_lua(&mut self, err: &mlua::Error) {
+        match err {
+            mlua::Error::CallbackError { traceback, cause } => {
+                // The innermost callback has the most complete traceback.
+                self.traceback = Some(traceback.clone());
+                self.add_lua(cause)
+            }
+            mlua::Error::WithContext { context, cause } => {
+                self.push(context.clone());
+                self.add_lua(cause)
+            }
+            mlua::Error::BadArgument {
+                to,
+                pos,
+                name,
+                cause,
+                ..
+            } => {
+                let arg = name.clone().unwrap_or_else(|| format!("#{}", pos));
+                match to {
+                    Some(to) => self.push(format!("bad argument {} to `{}`", arg, to)),
+                    None => self.push(format!("bad argument {}", arg)),
+                }
+                self.add_lua(cause)
+            }
+            mlua::Error::ExternalError(e) => self.add(e.as_ref()),
+            mlua::Error::RuntimeError(msg) | mlua::Error::SyntaxError { message: msg, .. } => {
+                // Errors raised from Lua carry their traceback in the message.
+                match msg.split_once("\nstack traceback:") {
+                    Some((msg, traceback)) => {
+                        self.push(msg.to_string());
+                        if self.traceback.is_none() {
+                            self.traceback = Some(format!("stack traceback:{}", traceback));
+                        }
+                    }
+                    None => self.push(msg.clone()),
+                }
+            }
+            e => self.push(e.to_string()),
+        }
+    }
+
+    /// Add a message, skipping ones that repeat the previous message.
+    fn push(&mut self, msg: String) {
+        let msg = msg.trim().to_string();
+        if msg.is_empty() || self.causes.last().is_some_and(|last| last.ends_with(&msg)) {
+            return;
+        }
+        self.causes.push(msg);
+    }
+
+    /// The place in a Lua script where the error happened, as the chunk name and line, along with
+    /// the Rust function that was being called.
+    fn location(&self) -> Option<(String, usize, Option<String>)> {
+        let mut function = None;
+        let frames = self
+            .traceback
+            .iter()
+            .flat_map(|t| t.lines().skip(1))
+            .map(str::trim);
+        // The message of errors raised from Lua starts with the location.
+        for frame in self.causes.iter().map(String::as_str).chain(frames) {
+            if let Some(rest) = frame.strip_prefix("[C]: in ") {
+                function.get_or_insert(rest.to_string());
+                continue;
+            }
+            if let Some((chunk, line)) = parse_location(frame) {
+                return Some((chunk, line, function));
+            }
+        }
+        None
+    }
+
+    fn write<W: io::Write>(&self, w: &mut W, verbose: bool, color: bool) -> io::Result<()> {
+        let style = |code: &str, s: &str| match color {
+            true => format!("\x1b[{}m{}\x1b[0m", code, s),
+            false => s.to_string(),
+        };
+        let mut causes = self.causes.iter();
+        let first = causes.next().map_or("unknown error", String::as_str);
+        writeln!(w, "{} {}", style("1;31", "error:"), first)?;
+        for cause in causes {
+            writeln!(w, "{} {}", style("1", "caused by:"), cause)?;
+        }
+        if let Some((chunk, line, function)) = self.location() {
+            write!(w, "  {} {}:{}", style("1;34", "-->"), chunk, line)?;
+            match function {
+                Some(function) => writeln!(w, " ({})", function)?,
+                None => writeln!(w)?,
+            }
+            if let Ok(text) = fs::read_to_string(&chunk) {
+                let width = (line + 1).to_string().len();
+                let gutter = style("1;34", &format!("{:width$} |", "", width = width));
+                writeln!(w, "  {}", gutter)?;
+                for (n, src) in text.lines().enumerate().map(|(i, l)| (i + 1, l)) {
+                    if n + 1 < line || n > line + 1 {
+                        continue;
+                    }
+                    let num = style("1;34", &format!("{:width$} |", n, width = width));
+                    match n == line {
+                        true => writeln!(w, "  {} {}", num, style("1", src))?,
+                        false => writeln!(w, "  {} {}", num, src)?,
+                    }
+                }
+                writeln!(w, "  {}", gutter)?;
+            }
+        }
+        if let Some(traceback) = self.traceback.as_ref().filter(|_| verbose) {
+            writeln!(w, "{}", traceback.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse "file.lua:12:" or `[string "file.lua"]:12:` at the start of a message or stack frame.
+fn parse_location(s: &str) -> Option<(String, usize)> {
+    let (chunk, rest) = match s.strip_prefix("[string \"") {
+        Some(rest) => {
+            let (chunk, rest) = rest.split_once("\"]:")?;
+            (chunk, rest)
+        }
+        None => s.split_once(':')?,
+    };
+    let (line, _) = rest.split_once(':')?;
+    let line = line.parse().ok()?;
+    if chunk.is_empty() || chunk.contains(' ') {
+        return None;
+    }
+    Some((chunk.to_string(), line))
+}
+
+/// Print an error to stderr with the chain of causes, and where in the Lua script it happened.
+/// The Lua traceback is only printed when `verbose` is set.
+pub(crate) fn report<E: error::Error + 'static>(err: &E, verbose: bool) {
+    let stderr = io::stderr();
+    let color = stderr.is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    _ = Report::new(err).write(&mut stderr.lock(), verbose, color);
+}
+
+/// The error report as plain text, for results that are written somewhere other than the
+/// terminal.
+pub(crate) fn render<E: error::Error + 'static>(err: &E, verbose: bool) -> String {
+    let mut out = Vec::new();
+    _ = Report::new(err).write(&mut out, verbose, false);
+    String::from_utf8_lossy(&out).to_string()
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::{Error, Report};
+    use mlua::ErrorContext;
+
+    #[test]
+    fn report() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("pax.lua");
+        let chunk = script.to_str().unwrap().to_string();
+        std::fs::write(&script, "local x = 1\nfail()\nlocal y = 2\n").unwrap();
+        let lua = mlua::Lua::new();
+        let fail = lua
+            .create_function(|_, ()| -> mlua::Result<()> {
+                let io = std::io::Error::other("disk full");
+                let archive = Error::archive("dist/demo.deb", io);
+                Err(Error::build("demo", archive).into())
+            })
+            .unwrap();
+        lua.globals().set("fail", fail).unwrap();
+        let err = lua
+            .load(script.as_path())
+            .exec()
+            .context("task package")
+            .unwrap_err();
+
+        let report = Report::new(&err);
+        assert_eq!(
+            report.causes,
+            vec![
+                "task package",
+                "failed to build package demo",
+                "failed to read or write archive dist/demo.deb",
+                "disk full",
+            ]
+        );
+        let (file, line, function) = report.location().unwrap();
+        assert_eq!((file.as_str(), line), (chunk.as_str(), 2));
+        assert_eq!(function.as_deref(), Some("function 'fail'"));
+
+        let mut out = Vec::new();
+        report.write(&mut out, false, false).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("error: task package\ncaused by: failed to build package demo\n"));
+        assert!(out.contains(&format!("  --> {}:2 (function 'fail')\n", chunk)));
+        assert!(out.contains("  2 | fail()\n"), "{}", out);
+        assert!(!out.contains("stack traceback"));
+
+        let err = lua
+            .load("error('boom')")
+            .set_name("@x.lua")
+            .exec()
+            .unwrap_err();
+        let report = Report::new(&err);
+        assert_eq!(report.causes, vec!["x.lua:1: boom"]);
+        assert_eq!(report.location().unwrap().1, 1);
+        let mut out = Vec::new();
+        report.write(&mut out, true, false).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("stack traceback:"));
+    }
+}
@@ -1,11 +1,14 @@
 use std::io;
 use std::path::{Path, PathBuf};
-use std::process;
 
 use anyhow::anyhow;
 use anyhow::Result;
 use git_url_parse::GitUrl;
 
+use crate::plan;
+use crate::sandbox;
+use crate::util;
+
 #[derive(Debug, Default, pax_derive::FromLua)]
 pub(crate) struct GitCloneOpts {
     pub repo: String,
@@ -28,6 +31,8 @@ impl GitCloneOpts {
 }
 
 pub(crate) fn git_clone(opts: GitCloneOpts) -> anyhow::Result<()> {
+    sandbox::check_url("pax.git.clone", &opts.repo)?;
+    sandbox::check_write("pax.git.clone", opts.dest.as_deref().unwrap_or("."))?;
     let mut args = vec!["clone"];
     args.push(&opts.repo);
     if let Some(ref d) = opts.dest {
@@ -48,7 +53,10 @@ pub(crate) fn git_clone(opts: GitCloneOpts) -> anyhow::Result<()> {
     } else {
         _ = depth_str;
     }
-    let _code = process::Command::new("git")
+    if plan::skip(format!("git {}", args.join(" "))) {
+        return Ok(());
+    }
+    let _code = util::command("git")?
         .args(args)
         .stderr(io::stderr())
         .stdout(io::stdout())
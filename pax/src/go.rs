@@ -1,36 +1,51 @@
 use std::env::current_dir;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Output};
 use std::str;
 
 use anyhow::{anyhow, Result};
 
+use crate::error::Error;
 use crate::git;
+use crate::plan;
+use crate::util;
 
 #[derive(Clone, pax_derive::FromLuaTable, pax_derive::IntoLua)]
 pub(crate) struct Go<'lua> {
+    /// Root directory to run the go command in.
     pub(crate) root: String,
+    /// Command directory to build. Default is '.'
     pub(crate) cmd: Option<String>,
+    /// Path of the built binary.
     pub(crate) out: Option<String>,
+    /// Passed to '-mod'.
     mode: Option<String>,
     #[lua_default(true)]
     trimpath: bool,
+    /// Passed to '-ldflags'. Default is "-s -w".
     #[lua_default(Some(["-s", "-w"].map(String::from).to_vec()))]
     ldflags: Option<Vec<String>>,
+    /// Passed to '-asmflags'.
     asmflags: Option<Vec<String>>,
+    /// Passed to '-tags'.
     tags: Option<Vec<String>>,
     compiler: Option<String>,
     /// Run 'go generate ./...' before running build commands
     generate: bool,
-    // build_ldflags: Option<mlua::Value<'lua>>,
+    /// Called with the git sha and build date, returns extra flags for '-ldflags'.
+    #[lua_type("fun(data: pax.GoBuildData): string[]")]
     build_ldflags: Option<mlua::Function<'lua>>,
-
+    /// Mode of the installed binary. Default is 0755.
     pub(crate) bin_access_mode: Option<u32>,
 }
 
 impl<'lua> Go<'lua> {
     pub(crate) fn list(&self) -> Result<String> {
-        run_cmd(Command::new("go").args(["list", "-C", &self.dir()?]))
+        let dir = self.dir()?;
+        if plan::skip(format!("go list -C {}", dir)) {
+            return Ok(String::new());
+        }
+        run_cmd(util::command("go")?.args(["list", "-C", &dir]))
     }
 
     pub(crate) fn build(&self) -> Result<()> {
@@ -38,6 +53,7 @@ impl<'lua> Go<'lua> {
             self.generate(&Some("./...".to_string()))?;
         }
         let dir = self.dir()?;
+        crate::watch::track(&dir);
         let mut args = vec!["-C", &dir, "build"];
         let out = self.out();
         if let Some(ref o) = out {
@@ -82,12 +98,12 @@ impl<'lua> Go<'lua> {
         if let Some(cmd) = &self.cmd {
             args.push(cmd);
         }
-        println!("go {}", args.join(" "));
-        let out = Command::new("go").args(args).output()?;
-        if !out.status.success() {
-            let s = str::from_utf8(&out.stderr).map(|s| s.strip_suffix('\n').unwrap_or(s))?;
-            return Err(anyhow!("{}", s));
+        let line = format!("go {}", args.join(" "));
+        if plan::skip(&line) {
+            return Ok(());
         }
+        println!("{}", line);
+        check(&line, util::command("go")?.args(args).output())?;
         Ok(())
     }
 
@@ -123,12 +139,12 @@ impl<'lua> Go<'lua> {
         if let Some(cmd) = &self.cmd {
             args.push(cmd);
         }
-        println!("go {}", args.join(" "));
-        let out = Command::new("go").args(args).output()?;
-        if !out.status.success() {
-            let s = str::from_utf8(&out.stderr).map(|s| s.strip_suffix('\n').unwrap_or(s))?;
-            return Err(anyhow!("{}", s));
+        let line = format!("go {}", args.join(" "));
+        if plan::skip(&line) {
+            return Ok(());
         }
+        println!("{}", line);
+        check(&line, util::command("go")?.args(args).output())?;
         Ok(())
     }
 
@@ -143,15 +159,18 @@ impl<'lua> Go<'lua> {
         if let Some(cmd) = &cmd {
             args.push(cmd);
         }
-        println!("go {}", args.join(" "));
-        let out = Command::new("go")
-            .args(args)
-            .stdout(std::io::stdout())
-            .output()?;
-        if !out.status.success() {
-            let s = str::from_utf8(&out.stderr).map(|s| s.strip_suffix('\n').unwrap_or(s))?;
-            return Err(anyhow!("{}", s));
+        let line = format!("go {}", args.join(" "));
+        if plan::skip(&line) {
+            return Ok(());
         }
+        println!("{}", line);
+        check(
+            &line,
+            util::command("go")?
+                .args(args)
+                .stdout(std::io::stdout())
+                .output(),
+        )?;
         Ok(())
     }
 
@@ -227,12 +246,28 @@ impl<'lua> mlua::FromLua<'lua> for Go<'lua> {
     }
 }
 
-fn run_cmd(cmd: &mut Command) -> Result<String> {
-    let out = cmd.output()?;
+/// Turn a failure to start `line`, or a failing exit status, into an error.
+fn check(line: &str, out: std::io::Result<Output>) -> Result<Output> {
+    let out = out.map_err(|e| Error::spawn(line, e))?;
     if !out.status.success() {
-        let s = str::from_utf8(&out.stderr).map(|s| s.strip_suffix('\n').unwrap_or(s))?;
-        return Err(anyhow!("{}", s));
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(Error::process(line, out.status, &stderr).into());
+    }
+    Ok(out)
+}
+
+fn command_line(cmd: &Command) -> String {
+    let mut line = cmd.get_program().to_string_lossy().to_string();
+    for arg in cmd.get_args() {
+        line.push(' ');
+        line.push_str(&arg.to_string_lossy());
     }
+    line
+}
+
+fn run_cmd(cmd: &mut Command) -> Result<String> {
+    let line = command_line(cmd);
+    let out = check(&line, cmd.output())?;
     match str::from_utf8(&out.stdout)?.strip_suffix('\n') {
         None => Err(anyhow!("no output from command")),
         Some(s) => Ok(String::from(s)),
@@ -240,8 +275,10 @@ fn run_cmd(cmd: &mut Command) -> Result<String> {
 }
 
 #[derive(pax_derive::FromLua, pax_derive::IntoLua)]
-struct GoBuildData {
+pub(crate) struct GoBuildData {
+    /// Commit of HEAD in the module's repository.
     git_sha: String,
+    /// Time of the build in RFC 3339 format.
     date: String,
 }
 
@@ -0,0 +1,150 @@
+use std::{env, path::Path};
+
+use mlua::{Function, Lua, Table, Value};
+
+use crate::watch;
+
+/// Modules in src/lua/std compiled into the binary, each loaded with `require("pax.std.<name>")`.
+macro_rules! std_modules {
+    ($($name:literal),* $(,)?) => {
+        &[$((
+            concat!("pax.std.", $name),
+            include_str!(concat!("lua/std/", $name, ".lua")),
+        )),*]
+    };
+}
+
+static STD: &[(&str, &str)] = std_modules!("files", "strings", "tables");
+
+/// Directories searched for shared modules after the project's own pax/ directory and
+/// $PAX_LUA_PATH.
+static SHARED_DIRS: &[&str] = &["~/.config/pax/lua", "/usr/share/pax/lua"];
+
+/// The package.path used for require(). `base` is the directory holding pax.lua and `extra` is
+/// the value of $PAX_LUA_PATH, a ';' separated list of directories or Lua path templates.
+pub(crate) fn search_path(base: &Path, extra: Option<&str>) -> String {
+    let mut dirs = vec![base.join("pax").to_string_lossy().to_string()];
+    dirs.extend(
+        extra
+            .into_iter()
+            .flat_map(|s| s.split(';'))
+            .filter(|s| !s.is_empty())
+            .map(String::from),
+    );
+    let home = env::var("HOME").ok();
+    for dir in SHARED_DIRS {
+        match (dir.strip_prefix("~/"), &home) {
+            (Some(rest), Some(home)) => {
+                dirs.push(Path::new(home).join(rest).to_string_lossy().to_string())
+            }
+            (Some(_), None) => {}
+            (None, _) => dirs.push(dir.to_string()),
+        }
+    }
+    let mut templates = Vec::new();
+    for dir in dirs {
+        if dir.contains('?') {
+            templates.push(dir);
+        } else {
+            let dir = dir.trim_end_matches('/');
+            templates.push(format!("{}/?.lua", dir));
+            templates.push(format!("{}/?/init.lua", dir));
+        }
+    }
+    templates.join(";")
+}
+
+/// Set up require() for pax.lua: the search path, the embedded standard library, and a file
+/// searcher that tracks loaded modules for --watch.
+pub(crate) fn setup(lua: &Lua, base: &Path, extra: Option<&str>) -> mlua::Result<()> {
+    let package: Table = lua.globals().get("package")?;
+    package.set("path", search_path(base, extra))?;
+
+    let preload: Table = package.get("preload")?;
+    for (name, src) in STD {
+        let loader = lua.create_function(move |lua, args: mlua::MultiValue| {
+            lua.load(*src)
+                .set_name(format!("{}.lua", name.replace('.', "/")))
+                .call::<_, Value>(args)
+        })?;
+        preload.set(*name, loader)?;
+    }
+
+    // Searches package.path like the default Lua searcher, but records the file so that
+    // editing a module re-runs pax.lua in watch mode.
+    let searcher = lua.create_function(|lua, name: String| {
+        let package: Table = lua.globals().get("package")?;
+        let path: String = package.get("path")?;
+        let searchpath: Function = package.get("searchpath")?;
+        let (file, err): (Option<String>, Option<String>) = searchpath.call((name, path))?;
+        match file {
+            Some(file) => {
+                watch::track(&file);
+                let chunk = lua.load(Path::new(&file)).into_function()?;
+                Ok((
+                    Value::Function(chunk),
+                    Value::String(lua.create_string(&file)?),
+                ))
+            }
+            None => Ok((
+                Value::String(lua.create_string(err.unwrap_or_default())?),
+                Value::Nil,
+            )),
+        }
+    })?;
+    let searchers: Table = package.get("searchers")?;
+    // Index 1 is the preload searcher, the default file searcher at 2 is replaced.
+    searchers.raw_set(2, searcher)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{search_path, setup};
+
+    #[test]
+    fn require() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("pax")).unwrap();
+        fs::write(
+            dir.path().join("pax/local.lua"),
+            "return { name = 'local' }",
+        )
+        .unwrap();
+        let shared = dir.path().join("shared");
+        fs::create_dir_all(shared.join("lib")).unwrap();
+        fs::write(shared.join("lib/init.lua"), "return { name = 'shared' }").unwrap();
+
+        let path = search_path(Path::new("/src"), Some("/a;/b/?.lua"));
+        assert!(
+            path.starts_with("/src/pax/?.lua;/src/pax/?/init.lua;/a/?.lua;/a/?/init.lua;/b/?.lua;")
+        );
+        assert!(path.ends_with("/usr/share/pax/lua/?.lua;/usr/share/pax/lua/?/init.lua"));
+
+        let lua = mlua::Lua::new();
+        lua.load("package.loaded.pax = { octal = function(s) return tonumber(s, 8) end, path = { basename = function(p) return p:match('[^/]*$') end } }")
+            .exec()
+            .unwrap();
+        setup(&lua, dir.path(), shared.to_str()).unwrap();
+        lua.load(
+            r#"
+            assert(require("local").name == "local")
+            assert(require("lib").name == "shared")
+            local strings = require("pax.std.strings")
+            local parts = strings.split("a,b,,c", ",")
+            assert(#parts == 4 and parts[4] == "c")
+            assert(strings.trim("  x ") == "x")
+            local tables = require("pax.std.tables")
+            assert(tables.contains(tables.map({ 1, 2 }, function(v) return v * 2 end), 4))
+            local files = require("pax.std.files")
+            local f = files.bin("target/release/demo")
+            assert(f.dst == "/usr/bin/demo" and f.mode == 493)
+            assert(not pcall(require, "missing"))
+            "#,
+        )
+        .exec()
+        .unwrap();
+    }
+}
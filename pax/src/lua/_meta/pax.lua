@@ -1 +0,0 @@
-../../../../lua/_meta/pax.lua
\ No newline at end of file
@@ -0,0 +1,43 @@
+--- Helpers for building the files list of a package.
+local pax = require("pax")
+
+local M = {}
+
+--- A file entry copied from `src` to `dst` with an octal mode such as "0644".
+--- @param src string
+--- @param dst string
+--- @param mode? string
+--- @return pax.File
+function M.file(src, dst, mode)
+	return {
+		src = src,
+		dst = dst,
+		mode = mode and pax.octal(mode) or nil,
+	}
+end
+
+--- An executable installed into /usr/bin, named after `src` unless `name` is given.
+--- @param src string
+--- @param name? string
+--- @return pax.File
+function M.bin(src, name)
+	return M.file(src, "/usr/bin/" .. (name or pax.path.basename(src)), "0755")
+end
+
+--- A documentation file installed into /usr/share/doc/<package>.
+--- @param src string
+--- @param package string
+--- @return pax.File
+function M.doc(src, package)
+	return M.file(src, "/usr/share/doc/" .. package .. "/" .. pax.path.basename(src), "0644")
+end
+
+--- A configuration file installed into /etc.
+--- @param src string
+--- @param dst string path relative to /etc
+--- @return pax.File
+function M.conf(src, dst)
+	return M.file(src, "/etc/" .. dst, "0644")
+end
+
+return M
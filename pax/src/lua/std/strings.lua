@@ -0,0 +1,43 @@
+--- String helpers missing from the Lua standard library.
+local M = {}
+
+--- Split `s` on every occurrence of `sep`, which is matched literally.
+--- @param s string
+--- @param sep string
+--- @return string[]
+function M.split(s, sep)
+	local parts = {}
+	local start = 1
+	while true do
+		local i, j = string.find(s, sep, start, true)
+		if not i then
+			table.insert(parts, string.sub(s, start))
+			return parts
+		end
+		table.insert(parts, string.sub(s, start, i - 1))
+		start = j + 1
+	end
+end
+
+--- Remove leading and trailing whitespace.
+--- @param s string
+--- @return string
+function M.trim(s)
+	return (string.gsub(s, "^%s*(.-)%s*$", "%1"))
+end
+
+--- @param s string
+--- @param prefix string
+--- @return boolean
+function M.starts_with(s, prefix)
+	return string.sub(s, 1, #prefix) == prefix
+end
+
+--- @param s string
+--- @param suffix string
+--- @return boolean
+function M.ends_with(s, suffix)
+	return suffix == "" or string.sub(s, -#suffix) == suffix
+end
+
+return M
@@ -0,0 +1,59 @@
+--- Table helpers.
+local M = {}
+
+--- Call `fn` on every value of a list and collect the results.
+--- @generic T, U
+--- @param list T[]
+--- @param fn fun(v: T): U
+--- @return U[]
+function M.map(list, fn)
+	local out = {}
+	for i, v in ipairs(list) do
+		out[i] = fn(v)
+	end
+	return out
+end
+
+--- Keep the values of a list for which `fn` returns true.
+--- @generic T
+--- @param list T[]
+--- @param fn fun(v: T): boolean
+--- @return T[]
+function M.filter(list, fn)
+	local out = {}
+	for _, v in ipairs(list) do
+		if fn(v) then
+			table.insert(out, v)
+		end
+	end
+	return out
+end
+
+--- Shallow copy of `base` with the keys of `overrides` set on top.
+--- @param base table
+--- @param overrides? table
+--- @return table
+function M.merge(base, overrides)
+	local out = {}
+	for k, v in pairs(base) do
+		out[k] = v
+	end
+	for k, v in pairs(overrides or {}) do
+		out[k] = v
+	end
+	return out
+end
+
+--- @param list any[]
+--- @param value any
+--- @return boolean
+function M.contains(list, value)
+	for _, v in ipairs(list) do
+		if v == value then
+			return true
+		end
+	end
+	return false
+end
+
+return M
@@ -0,0 +1,219 @@
+--- Test cases for `pax test`, loaded with `require("pax.testing")`.
+---
+--- While tests run, downloads, git commands and other external commands are mocked: they are
+--- recorded instead of run. Use `unmock` to let them run for real.
+local native = ...
+local M = {}
+
+--- Add a test case. Cases run after the whole file has loaded, in the order they were added.
+--- @type fun(name: string, fn: fun())
+M.test = native.test
+
+--- Build a package into a temporary directory and open the result.
+--- @type fun(spec: pax.BuildSpec): pax.Deb
+M.build = native.build
+
+--- Open a .deb file.
+--- @type fun(path: string): pax.Deb
+M.open = native.open
+
+--- Record calls of a kind instead of running them. All kinds are mocked by default.
+--- @type fun(kind: "dl"|"git"|"exec")
+M.mock = native.mock
+
+--- Run calls of a kind for real.
+--- @type fun(kind: "dl"|"git"|"exec")
+M.unmock = native.unmock
+
+--- The calls recorded by the mocks since the test case started, as command lines like
+--- "git clone https://..." or "download https://... -> bin/jq".
+--- @type fun(kind?: "dl"|"git"|"exec"): string[]
+M.calls = native.calls
+
+--- Write the file at `path` when `url` is downloaded by a mocked pax.dl call.
+--- @type fun(url: string, path: string)
+M.fixture = native.fixture
+
+local function is_deb(v)
+	return type(v) == "userdata" and native.is_deb(v)
+end
+
+local function show(v, depth)
+	depth = depth or 0
+	if type(v) == "string" then
+		return string.format("%q", v)
+	elseif type(v) ~= "table" then
+		return tostring(v)
+	elseif depth > 2 then
+		return "{...}"
+	end
+	local keys = {}
+	for k in pairs(v) do
+		table.insert(keys, k)
+	end
+	table.sort(keys, function(a, b)
+		return tostring(a) < tostring(b)
+	end)
+	local parts = {}
+	for i, k in ipairs(keys) do
+		if k == i then
+			table.insert(parts, show(v[k], depth + 1))
+		else
+			table.insert(parts, tostring(k) .. " = " .. show(v[k], depth + 1))
+		end
+	end
+	return "{ " .. table.concat(parts, ", ") .. " }"
+end
+
+local function equal(a, b)
+	if type(a) ~= "table" or type(b) ~= "table" then
+		return a == b
+	end
+	for k, v in pairs(a) do
+		if not equal(v, b[k]) then
+			return false
+		end
+	end
+	for k in pairs(b) do
+		if a[k] == nil then
+			return false
+		end
+	end
+	return true
+end
+
+--- Modes given as strings are octal. Numbers are compared as they are: Lua has no octal literals,
+--- and 0755 can't be told apart from a mode such as pax.octal("01363").
+local function mode_matches(expected, actual)
+	if type(expected) == "string" then
+		return tonumber(expected, 8) == actual
+	end
+	return expected == actual
+end
+
+--- Differences between a package entry and the expected attributes.
+local function entry_problems(entry, want)
+	local problems = {}
+	for key, value in pairs(want) do
+		local ok
+		if key == "mode" then
+			ok = mode_matches(value, entry.mode)
+		else
+			ok = entry[key] == value
+		end
+		if not ok and key == "mode" then
+			local hint = ""
+			if type(value) == "number" then
+				if tonumber(tostring(value), 8) == entry.mode then
+					hint = string.format(' (write octal modes as "%04d")', value)
+				end
+				value = string.format("%04o", value)
+			end
+			table.insert(problems, string.format("mode is %04o, not %s%s", entry.mode, value, hint))
+		elseif not ok then
+			table.insert(problems, string.format("%s is %s, not %s", key, show(entry[key]), show(value)))
+		end
+	end
+	table.sort(problems)
+	return problems
+end
+
+local function contains(value, item, opts)
+	if is_deb(value) then
+		local entry = value:file(item)
+		if not entry then
+			return false, "no " .. item
+		end
+		local problems = entry_problems(entry, opts or {})
+		if #problems > 0 then
+			return false, item .. ": " .. table.concat(problems, ", ")
+		end
+		return true, item
+	elseif type(value) == "string" then
+		return value:find(item, 1, true) ~= nil, show(item)
+	elseif type(value) == "table" then
+		for _, v in pairs(value) do
+			if equal(v, item) then
+				return true, show(item)
+			end
+		end
+		return false, show(item)
+	end
+	error("cannot look for values in a " .. type(value), 3)
+end
+
+--- Assertions on a value. Each one raises an error when it does not hold.
+--- @class pax.Expectation
+--- @field to_be fun(expected: any) Compare with ==.
+--- @field to_equal fun(expected: any) Compare tables by their contents.
+--- @field to_match fun(pattern: string) Match a string against a Lua pattern.
+--- @field to_contain fun(item: any, opts?: pax.ExpectEntry) A file in a package, a substring or a value in a table.
+--- @field not_to_contain fun(item: any)
+--- @field to_have_field fun(name: string, value?: string) A control field of a package.
+--- @field to_have_script fun(name: string, text?: string) A maintainer script of a package containing `text`.
+
+--- @class pax.ExpectEntry
+--- @field kind? "file"|"dir"|"symlink"|"hardlink"
+--- @field mode? integer|string Such as "0755" or pax.octal("0755").
+--- @field owner? string
+--- @field group? string
+--- @field target? string Target of a link.
+
+--- @param value any
+--- @return pax.Expectation
+function M.expect(value)
+	local e = {}
+	function e.to_be(expected)
+		if value ~= expected then
+			error(string.format("expected %s, got %s", show(expected), show(value)), 2)
+		end
+	end
+	function e.to_equal(expected)
+		if not equal(value, expected) then
+			error(string.format("expected %s, got %s", show(expected), show(value)), 2)
+		end
+	end
+	function e.to_match(pattern)
+		if type(value) ~= "string" or not value:match(pattern) then
+			error(string.format("expected %s to match %q", show(value), pattern), 2)
+		end
+	end
+	function e.to_contain(item, opts)
+		local ok, what = contains(value, item, opts)
+		if not ok then
+			local name = is_deb(value) and value.path or show(value)
+			error(string.format("expected %s to contain %s", name, what), 2)
+		end
+	end
+	function e.not_to_contain(item)
+		if contains(value, item) then
+			local name = is_deb(value) and value.path or show(value)
+			error(string.format("expected %s not to contain %s", name, show(item)), 2)
+		end
+	end
+	function e.to_have_field(name, expected)
+		if not is_deb(value) then
+			error("to_have_field needs a package, got " .. show(value), 2)
+		end
+		local actual = value:field(name)
+		if actual == nil then
+			error(string.format("expected %s to have a %s field", value.path, name), 2)
+		elseif expected ~= nil and actual ~= expected then
+			error(string.format("expected %s to be %s, got %s", name, show(expected), show(actual)), 2)
+		end
+	end
+	function e.to_have_script(name, text)
+		if not is_deb(value) then
+			error("to_have_script needs a package, got " .. show(value), 2)
+		end
+		local script = value.scripts[name]
+		if script == nil then
+			error(string.format("expected %s to have a %s script", value.path, name), 2)
+		elseif text ~= nil and not script:find(text, 1, true) then
+			error(string.format("expected the %s script to contain %s, got:\n%s", name, show(text), script), 2)
+		end
+	end
+	return e
+end
+
+return M
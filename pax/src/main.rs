@@ -1,22 +1,51 @@
+mod apt;
 mod build;
+mod changelog;
+mod control;
+mod copyright;
 mod crates;
+mod dbgsym;
 mod deb;
+mod debfile;
+mod diff;
 mod dl;
 mod error;
 mod git;
 mod go;
+mod loader;
+mod manifest;
+mod meta;
 mod modules;
 mod os;
+mod plan;
 mod project;
+mod repl;
+mod sandbox;
+mod scripts;
+mod specfile;
+mod systemd;
+mod tasks;
+mod testing;
+mod users;
 mod util;
+mod vars;
+mod verify;
+mod watch;
 
-use std::{cell::RefCell, fs, io::Read, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fs,
+    io::{Read, Write},
+    rc::Rc,
+};
 
 use clap::{Parser, Subcommand};
 use mlua::Lua;
 use util::{scdoc, SCDocOpts};
 
-use crate::build::{BuildSpec, RefCellBuildSpec, DEFAULT_DIST};
+use crate::build::{BuildAllOpts, BuildSpec, RefCellBuildSpec, DEFAULT_DIST};
+use crate::error::Error;
 use crate::modules::GitSubModule;
 use crate::util::{lua_octal, print_function};
 
@@ -24,27 +53,125 @@ use crate::util::{lua_octal, print_function};
 struct Cli {
     #[arg(long, short, default_value = "pax.lua")]
     config: String,
+    /// Number of packages built at the same time by pax:package_all().
+    #[arg(long, short, global = true)]
+    jobs: Option<usize>,
+    /// Rebuild packages even when nothing changed since the last build.
+    #[arg(long, short, global = true)]
+    force: bool,
+    /// Print the Lua stack traceback along with errors.
+    #[arg(long, short, global = true)]
+    verbose: bool,
+    /// Set a variable read with pax.var(). Can be given more than once.
+    #[arg(short = 'D', long = "define", value_name = "KEY=VALUE", value_parser = vars::parse_define, global = true)]
+    defines: Vec<(String, String)>,
+    /// Load environment variables from this file. Default is .env when it exists.
+    #[arg(long, global = true)]
+    env_file: Option<String>,
+    /// Run pax.lua without access to commands, files outside the project or the network.
+    #[arg(long, global = true)]
+    sandbox: bool,
+    /// Let a sandboxed pax.lua run commands.
+    #[arg(long, global = true, requires = "sandbox")]
+    allow_exec: bool,
+    /// Let a sandboxed pax.lua write files in this directory. Can be given more than once.
+    #[arg(long, value_name = "DIR", global = true, requires = "sandbox")]
+    allow_write: Vec<String>,
+    /// Let a sandboxed pax.lua download from this host, "*.example.com" allows all subdomains.
+    /// Can be given more than once.
+    #[arg(long, value_name = "HOST", global = true, requires = "sandbox")]
+    allow_host: Vec<String>,
     #[command(subcommand)]
     command: Option<Command>,
 }
 
-#[derive(Debug, Default, Subcommand)]
+#[derive(Debug, Subcommand)]
 enum Command {
     /// Manage configuration
     Config,
-    /// Tests
-    #[clap(hide = true)]
-    Test,
+    /// Start an interactive Lua prompt with require("pax") set up.
+    Repl,
     /// Run the cli
-    #[default]
-    Run,
+    Run {
+        /// Print what would be built and run without building packages, running commands or
+        /// downloading anything.
+        #[arg(long)]
+        dry_run: bool,
+        /// Print the dry-run plan as JSON.
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+        /// Re-run the config whenever it or the files it uses change.
+        #[arg(long, conflicts_with = "dry_run")]
+        watch: bool,
+        /// Tasks to run, along with the tasks they depend on. Default is the "default" task.
+        tasks: Vec<String>,
+        /// Arguments after '--' are passed to the script as pax.args.
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// List the tasks defined in pax.lua.
+    List,
+    /// Run the *_test.lua files, with downloads, git and other commands mocked.
+    Test {
+        /// Test files, or directories to search for them.
+        #[arg(default_value = ".")]
+        paths: Vec<String>,
+        /// Also write the results to this file as JUnit XML.
+        #[arg(long, value_name = "FILE")]
+        junit: Option<String>,
+    },
+    /// Build the packages described in a TOML or JSON spec file.
+    Build {
+        /// Path to the spec file.
+        spec: String,
+        /// Directory the packages are written to.
+        #[arg(long, default_value = DEFAULT_DIST)]
+        dist: String,
+    },
+    /// Install a package into a directory and remove it again, running its maintainer scripts,
+    /// and report scripts that fail and files that are left behind.
+    Verify {
+        /// Path to the .deb file.
+        deb: String,
+        /// Directory to install into. Default is a temporary directory.
+        #[arg(long)]
+        root: Option<String>,
+        /// Shell the maintainer scripts are run with.
+        #[arg(long, default_value = "sh")]
+        shell: String,
+    },
+    /// Show what changed between two versions of a package.
+    Diff {
+        /// Path to the older .deb file.
+        old: String,
+        /// Path to the newer .deb file.
+        new: String,
+        /// Print the changes as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the pax.lua equivalent of a TOML or JSON spec file.
+    Convert {
+        /// Path to the spec file.
+        spec: String,
+    },
     /// Generate lua annotations.
     #[clap(hide = true)]
     Generate,
 }
 
 impl Cli {
+    /// Run the config and then the tasks selected on the command line.
     fn run(&self, lua: &Lua) -> mlua::Result<()> {
+        self.load(lua)?;
+        let names = match self.command {
+            Some(Command::Run { ref tasks, .. }) => tasks.as_slice(),
+            _ => &[],
+        };
+        tasks::run(lua, names)
+    }
+
+    fn load(&self, lua: &Lua) -> mlua::Result<()> {
         let mut file = std::fs::File::options()
             .write(false)
             .read(true)
@@ -60,23 +187,94 @@ impl Cli {
         Ok(())
     }
 
+    fn watch(&self) -> mlua::Result<()> {
+        loop {
+            watch::reset();
+            watch::track(&self.config);
+            let start = std::time::Instant::now();
+            match self.run(&Lua::new()) {
+                Err(e) => error::report(&e, self.verbose),
+                Ok(()) => {
+                    let built = watch::built_packages();
+                    util::log(format!(
+                        "rebuilt {} package{} in {:.1?}",
+                        built.len(),
+                        if built.len() == 1 { "" } else { "s" },
+                        start.elapsed()
+                    ));
+                    for pkg in built {
+                        println!("  {}", pkg);
+                    }
+                }
+            }
+            let paths = watch::tracked();
+            util::log(format!(
+                "watching {} path{} for changes",
+                paths.len(),
+                if paths.len() == 1 { "" } else { "s" }
+            ));
+            for path in watch::wait(&paths)? {
+                println!("  changed: {}", path.display());
+            }
+        }
+    }
+
+    /// Variables and arguments for the script, after loading the .env file.
+    fn vars(&self) -> std::io::Result<vars::Vars> {
+        match self.env_file {
+            Some(ref path) => vars::load_dotenv(path)?,
+            None if fs::metadata(".env").is_ok() => vars::load_dotenv(".env")?,
+            None => {}
+        }
+        let args = match self.command {
+            Some(Command::Run { ref args, .. }) => args.clone(),
+            _ => Vec::new(),
+        };
+        Ok(vars::Vars {
+            defines: self.defines.iter().cloned().collect(),
+            args,
+        })
+    }
+
     fn process<R>(&self, lua: &Lua, configbody: &mut R) -> mlua::Result<PaxConfig>
     where
         R: Read,
     {
         let mut body = String::new();
         configbody.read_to_string(&mut body)?;
+        let rt_conf = self.setup(lua)?;
+        lua.load(body)
+            .set_name(format!("@{}", self.config))
+            .set_mode(mlua::ChunkMode::Text)
+            .exec()?;
+        Ok(rt_conf.take())
+    }
+
+    /// Set up the Lua state pax.lua runs in: variables, `require("pax")` and the module search
+    /// path.
+    fn setup(&self, lua: &Lua) -> mlua::Result<Rc<RefCell<PaxConfig>>> {
+        lua.set_app_data(self.vars()?);
         let rt_conf = Rc::new(RefCell::new(PaxConfig::default()));
+        rt_conf.borrow_mut().opts.jobs = self.jobs;
         lua.globals()
             .set("octal", lua.create_function(lua_octal)?)?;
         let package: mlua::Table = lua.globals().get("package")?;
         let loaded: mlua::Table = package.get("loaded")?;
         loaded.set("pax", rt_conf.clone())?; // use require('pax') to access
-        lua.load(body)
-            .set_name(&self.config)
-            .set_mode(mlua::ChunkMode::Text)
-            .exec()?;
-        Ok(rt_conf.take())
+        let base = std::path::Path::new(&self.config)
+            .parent()
+            .unwrap_or(std::path::Path::new(""));
+        loader::setup(lua, base, std::env::var("PAX_LUA_PATH").ok().as_deref())?;
+        let policy = self.sandbox.then(|| {
+            let mut policy = sandbox::Policy::new(base, &self.allow_write, &self.allow_host);
+            policy.exec = self.allow_exec;
+            policy
+        });
+        sandbox::enable(policy.clone());
+        if let Some(policy) = policy {
+            sandbox::restrict(lua, policy)?;
+        }
+        Ok(rt_conf)
     }
 }
 
@@ -84,12 +282,15 @@ impl Cli {
 struct PaxOptions {
     files_base: Option<String>,
     dist: Option<String>,
+    jobs: Option<usize>,
 }
 
 #[derive(Debug, Default)]
 struct PaxConfig {
     opts: PaxOptions,
     specs: Vec<BuildSpec>,
+    /// Indexes into `specs` of the packages that have been built.
+    built: HashSet<usize>,
     spec: BuildSpec,
 }
 
@@ -116,8 +317,20 @@ impl mlua::UserData for PaxConfig {
                });
             }
         }
-        gen_userdata_getset!(@opts, files_base, dist);
+        gen_userdata_getset!(@opts, files_base, dist, jobs);
         fields.add_field_method_get("specs", PaxConfig::get_specs);
+        fields.add_field_function_get("args", |lua, _| {
+            Ok(lua
+                .app_data_ref::<vars::Vars>()
+                .map(|v| v.args.clone())
+                .unwrap_or_default())
+        });
+        fields.add_field_function_get("vars", |lua, _| {
+            Ok(lua
+                .app_data_ref::<vars::Vars>()
+                .map(|v| v.defines.clone())
+                .unwrap_or_default())
+        });
         fields.add_field("git", GitSubModule);
         fields.add_field("cargo", modules::CargoModule);
         fields.add_field("go", modules::GoModule);
@@ -125,8 +338,11 @@ impl mlua::UserData for PaxConfig {
         fields.add_field("path", modules::PathMod);
         fields.add_field("fs", modules::FSMod);
         fields.add_field("os", modules::OsMod);
+        fields.add_field("control", modules::ControlMod);
+        fields.add_field("deb", modules::DebMod);
         fields.add_field("Urgency", deb::Urgency::Low); // adds all variants
         fields.add_field("Priority", deb::Priority::default());
+        fields.add_field("Compression", deb::Compression::default());
     }
 
     fn add_methods<'lua, M: mlua::prelude::LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
@@ -135,8 +351,14 @@ impl mlua::UserData for PaxConfig {
         methods.add_method_mut("add", Self::method_add_spec);
         methods.add_method_mut("package", Self::method_package);
         methods.add_method_mut("package_crate", Self::method_build_crate);
+        methods.add_method_mut("package_all", Self::method_package_all);
         methods.add_method("packages", |_lua, this, ()| Ok(this.specs.clone()));
         methods.add_function("octal", lua_octal);
+        methods.add_function("task", tasks::task);
+        methods.add_function("var", vars::var);
+        methods.add_function("var_number", vars::var_number);
+        methods.add_function("var_bool", vars::var_bool);
+        methods.add_function("var_list", vars::var_list);
         methods.add_function("new_spec", Self::func_new_spec);
         methods.add_function("table_extend", Self::func_table_extend);
         methods.add_function("exec", Self::func_exec);
@@ -169,20 +391,32 @@ impl<'lua> PaxConfig {
         _lua: &mlua::Lua,
         (cmd, vargs): (String, Option<Vec<String>>),
     ) -> mlua::Result<()> {
-        std::process::Command::new(cmd)
-            .args(vargs.unwrap_or(vec![]))
+        let args = vargs.unwrap_or(vec![]);
+        let line = format!("{} {}", cmd, args.join(" ")).trim_end().to_string();
+        sandbox::check_exec("pax.exec")?;
+        if plan::skip(line.clone()) {
+            return Ok(());
+        }
+        util::command(cmd)?
+            .args(args)
             .stderr(std::io::stderr())
             .stdout(std::io::stdout())
-            .output()?;
+            .output()
+            .map_err(|e| Error::spawn(line, e))?;
         Ok(())
     }
 
     fn func_sh(_: &mlua::Lua, script: String) -> mlua::Result<()> {
-        std::process::Command::new("sh")
+        sandbox::check_exec("pax.sh")?;
+        if plan::skip(format!("sh -c {:?}", script)) {
+            return Ok(());
+        }
+        util::command("sh")?
             .args(["-c", script.as_str()])
             .stderr(std::io::stderr())
             .stdout(std::io::stdout())
-            .output()?;
+            .output()
+            .map_err(|e| Error::spawn("sh", e))?;
         Ok(())
     }
 
@@ -209,7 +443,7 @@ impl<'lua> PaxConfig {
     }
 
     fn func_log(_: &mlua::Lua, msg: String) -> mlua::Result<()> {
-        println!("[\x1b[01;32mpax\x1b[0m] {}", msg);
+        util::log(msg);
         Ok(())
     }
 
@@ -221,11 +455,41 @@ impl<'lua> PaxConfig {
             .unwrap_or(DEFAULT_DIST.to_string());
         _ = std::fs::create_dir_all(&dist); // ignore error
         s.borrow_mut().pre_process(this.opts.files_base.clone())?;
-        s.borrow_mut().build(dist)?;
+        let package = s.borrow().package.clone();
+        s.borrow_mut()
+            .build(dist)
+            .map_err(|e| Error::build(package, e))?;
+        this.built.insert(this.specs.len());
         this.specs.push(s.take());
         Ok(())
     }
 
+    fn method_package_all(
+        _lua: &mlua::Lua,
+        this: &mut Self,
+        opts: BuildAllOpts,
+    ) -> mlua::Result<()> {
+        let dist = this
+            .opts
+            .dist
+            .to_owned()
+            .unwrap_or(DEFAULT_DIST.to_string());
+        _ = std::fs::create_dir_all(&dist); // ignore error
+        let jobs = opts
+            .jobs
+            .or(this.opts.jobs)
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+        let pending = this
+            .specs
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| !this.built.contains(i))
+            .map(|(_, spec)| spec);
+        build::build_all(pending, dist, jobs)?;
+        this.built.extend(0..this.specs.len());
+        Ok(())
+    }
+
     fn method_build_crate(
         lua: &mlua::Lua,
         this: &mut Self,
@@ -247,7 +511,9 @@ impl<'lua> PaxConfig {
             .unwrap_or(DEFAULT_DIST.to_string());
         _ = std::fs::create_dir_all(&dist); // ignore error
         spec.pre_process(this.opts.files_base.clone())?;
-        spec.build(dist)?;
+        spec.build(dist)
+            .map_err(|e| Error::build(spec.package.clone(), e))?;
+        this.built.insert(this.specs.len());
         this.specs.push(spec);
         Ok(())
     }
@@ -262,6 +528,7 @@ impl<'lua> PaxConfig {
 
 fn main() {
     let cli = Cli::parse();
+    BuildSpec::force_rebuild(cli.force);
     let lua = Lua::new();
     match &cli.command {
         Some(Command::Config) => {
@@ -269,30 +536,151 @@ fn main() {
             let s = String::from_utf8(res).unwrap();
             println!("{s}");
         }
-        Some(Command::Generate) => {
-            let annotations = include_bytes!("./lua/_meta/pax.lua");
-            let s = match String::from_utf8(annotations.to_vec()) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Error: {e}");
-                    std::process::exit(1);
+        Some(Command::Generate) => print!("{}", meta::generate()),
+        Some(Command::Repl) => {
+            let res = cli
+                .setup(&lua)
+                .and_then(|_| repl::run(&lua, &cli.config, cli.verbose));
+            if let Err(e) = res {
+                fail(&e, cli.verbose);
+            }
+        }
+        Some(Command::List) => {
+            if let Err(e) = cli.load(&lua) {
+                fail(&e, cli.verbose);
+            }
+            if let Some(tasks) = lua.app_data_ref::<tasks::Tasks>() {
+                if let Err(e) = tasks.list(&mut std::io::stdout().lock()) {
+                    fail(&e, cli.verbose);
                 }
+            }
+        }
+        Some(Command::Test { paths, junit }) => {
+            if let Err(e) = run_tests(&cli, paths, junit.as_deref()) {
+                fail(&e, cli.verbose);
+            }
+        }
+        Some(Command::Build { spec, dist }) => {
+            if let Err(e) = build_spec_file(spec, dist, cli.jobs) {
+                fail(&e, cli.verbose);
+            }
+        }
+        Some(Command::Verify { deb, root, shell }) => {
+            if let Err(e) = verify_deb(deb, root.as_deref(), shell) {
+                fail(&e, cli.verbose);
+            }
+        }
+        Some(Command::Diff { old, new, json }) => {
+            if let Err(e) = diff_debs(old, new, *json) {
+                fail(&e, cli.verbose);
+            }
+        }
+        Some(Command::Convert { spec }) => match specfile::convert(spec) {
+            Ok(lua) => print!("{}", lua),
+            Err(e) => fail(&e, cli.verbose),
+        },
+        Some(Command::Run {
+            dry_run: true,
+            json,
+            ..
+        }) => {
+            plan::enable(true);
+            if let Err(e) = cli.run(&lua) {
+                fail(&e, cli.verbose);
+            }
+            let plan = plan::take();
+            let mut stdout = std::io::stdout().lock();
+            let res = if *json {
+                serde_json::to_writer_pretty(&mut stdout, &plan)
+                    .map_err(std::io::Error::from)
+                    .and_then(|()| writeln!(stdout))
+            } else {
+                plan.write_text(&mut stdout)
             };
-            print!("{}", s);
+            if let Err(e) = res {
+                fail(&e, cli.verbose);
+            }
         }
-        Some(Command::Test) => {}
         _ => {
-            match cli.run(&lua) {
-                Err(e) => {
-                    eprintln!("Error: {}", e);
-                    std::process::exit(1);
-                }
-                Ok(_) => (),
+            let res = if matches!(cli.command, Some(Command::Run { watch: true, .. })) {
+                cli.watch()
+            } else {
+                cli.run(&lua)
             };
+            if let Err(e) = res {
+                fail(&e, cli.verbose);
+            }
         }
     }
 }
 
+/// Report the error and exit.
+fn fail<E: std::error::Error + 'static>(e: &E, verbose: bool) -> ! {
+    error::report(e, verbose);
+    std::process::exit(1);
+}
+
+/// Run each test file in its own Lua state and exit with an error when any case fails.
+fn run_tests(cli: &Cli, paths: &[String], junit: Option<&str>) -> mlua::Result<()> {
+    let files = testing::discover(paths)?;
+    if files.is_empty() {
+        return Err(mlua::Error::runtime(format!(
+            "no *_test.lua files found in {}",
+            paths.join(", ")
+        )));
+    }
+    let mut suites = Vec::new();
+    for file in files {
+        let lua = Lua::new();
+        cli.setup(&lua)?;
+        suites.push(testing::run_file(&lua, &file, cli.verbose));
+    }
+    testing::write_text(&mut std::io::stdout().lock(), &suites)?;
+    if let Some(path) = junit {
+        let mut out = std::io::BufWriter::new(fs::File::create(path)?);
+        testing::write_junit(&mut out, &suites)?;
+        out.flush()?;
+    }
+    if suites.iter().any(|s| s.failures() > 0) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn diff_debs(old: &str, new: &str, json: bool) -> std::io::Result<()> {
+    let diff = diff::diff(&debfile::DebFile::open(old)?, &debfile::DebFile::open(new)?);
+    let mut stdout = std::io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &diff)?;
+        writeln!(stdout)
+    } else {
+        diff.write_text(&mut stdout)
+    }
+}
+
+/// Run `pax verify` and exit with an error when it finds problems.
+fn verify_deb(path: &str, root: Option<&str>, shell: &str) -> std::io::Result<()> {
+    let deb = debfile::DebFile::open(path)?;
+    let tmp = tempfile::tempdir()?;
+    let root = root.map_or(tmp.path(), std::path::Path::new);
+    let report = verify::verify(&deb, root, shell)?;
+    report.write_text(&mut std::io::stdout().lock())?;
+    if !report.problems.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn build_spec_file(path: &str, dist: &str, jobs: Option<usize>) -> std::io::Result<()> {
+    let mut specs = specfile::load(path)?;
+    for spec in &mut specs {
+        spec.pre_process(None)?;
+    }
+    fs::create_dir_all(dist)?;
+    let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    build::build_all(&mut specs, dist, jobs)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::build::File;
@@ -0,0 +1,339 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+
+use crate::build::BuildSpec;
+use crate::util::{to_io_err, walk};
+
+/// Directory in the .pax cache that holds a manifest for each package built.
+static MANIFEST_DIR: &str = ".pax/manifests";
+
+/// Fingerprint of everything that went into a package. A package is only rebuilt when the
+/// fingerprint changes or one of its outputs is missing or modified.
+///
+/// Signing keys given as URLs are not fetched again to check for changes.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub pax_version: String,
+    /// Hash of the whole spec.
+    pub spec: String,
+    /// Hash and mode of each input file.
+    pub inputs: BTreeMap<String, String>,
+    /// Hash of each package written.
+    pub outputs: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub(crate) fn new(spec: &BuildSpec) -> io::Result<Self> {
+        let mut m = Self {
+            pax_version: env!("CARGO_PKG_VERSION").to_string(),
+            spec: spec_hash(spec),
+            ..Default::default()
+        };
+        for file in &spec.files {
+            if !file.src.is_empty() {
+                m.add_input(&file.src)?;
+            }
+        }
+        for service in spec.services.iter().flatten() {
+            m.add_input(&service.unit)?;
+        }
+        for source in spec.apt_sources.iter().flatten() {
+            if let Some(ref key) = source.key_file {
+                m.add_input(key)?;
+            }
+        }
+        if let Some(ref opts) = spec.changelog {
+            // The changelog only depends on the commits and tags reachable from HEAD.
+            let repo = opts.repo.as_deref().unwrap_or(".");
+            m.inputs
+                .insert(format!("git:{}", repo), git_state(repo).unwrap_or_default());
+        }
+        if let Some(ref copyright) = spec.copyright {
+            for (dir, lock) in [(&copyright.cargo, "Cargo.lock"), (&copyright.go, "go.sum")] {
+                if let Some(dir) = dir {
+                    let path = Path::new(dir).join(lock);
+                    if path.exists() {
+                        m.add_input(path)?;
+                    }
+                }
+            }
+            for license in &copyright.licenses {
+                if let (None, Some(file)) = (&license.text, &license.file) {
+                    m.add_input(file)?;
+                }
+            }
+        }
+        Ok(m)
+    }
+
+    /// Where the manifest for a package built into `dir` is kept. The build number is left out
+    /// of the name so that the manifest is found again after it changes, and the resolved `dir`
+    /// keeps packages built into different directories apart.
+    pub(crate) fn path<P: AsRef<Path>>(dir: P, spec: &BuildSpec) -> PathBuf {
+        let dir = dir.as_ref();
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let key = hex::encode(Md5::digest(dir.as_os_str().as_encoded_bytes()));
+        Path::new(MANIFEST_DIR)
+            .join(key)
+            .join(format!("{}_{}.json", spec.package, spec.arch))
+    }
+
+    pub(crate) fn load<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    pub(crate) fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec_pretty(self).map_err(to_io_err)?;
+        fs::write(path, data)
+    }
+
+    /// Record the hashes of the packages that were written.
+    pub(crate) fn set_outputs(&mut self, outputs: &[PathBuf]) -> io::Result<()> {
+        self.outputs.clear();
+        for path in outputs {
+            self.outputs
+                .insert(path.to_string_lossy().to_string(), hash_file(path)?);
+        }
+        Ok(())
+    }
+
+    /// True when `previous` was built from the same inputs and its outputs haven't been touched
+    /// since.
+    pub(crate) fn is_fresh(&self, previous: &Self) -> bool {
+        self.pax_version == previous.pax_version
+            && self.spec == previous.spec
+            && self.inputs == previous.inputs
+            && !previous.outputs.is_empty()
+            && previous
+                .outputs
+                .iter()
+                .all(|(path, hash)| hash_file(path).is_ok_and(|h| h == *hash))
+    }
+
+    /// Add a file, or every file below a directory.
+    fn add_input<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if path.is_dir() {
+            walk(path, |entry| {
+                let p = entry.path();
+                if p.is_file() {
+                    self.inputs
+                        .insert(p.to_string_lossy().to_string(), hash_input(&p)?);
+                }
+                Ok(())
+            })
+        } else {
+            // Missing files are left for the build to report.
+            let hash = hash_input(path).unwrap_or_default();
+            self.inputs.insert(path.to_string_lossy().to_string(), hash);
+            Ok(())
+        }
+    }
+}
+
+/// Hash of every field of the spec that ends up in the package. The build number is left out:
+/// it changes on every build, so a package would never be up to date.
+fn spec_hash(spec: &BuildSpec) -> String {
+    // Destructured so that a new field can't be added without deciding whether it belongs here.
+    let BuildSpec {
+        package,
+        name,
+        version,
+        description,
+        essential,
+        author,
+        email,
+        maintainer,
+        homepage,
+        files,
+        conffiles,
+        pre_depends,
+        dependencies,
+        recommends,
+        suggests,
+        conflicts,
+        breaks,
+        provides,
+        replaces,
+        priority,
+        arch,
+        urgency,
+        section,
+        apt_sources,
+        scripts,
+        changelog,
+        copyright,
+        services,
+        users,
+        dbgsym,
+        compression,
+        compression_threads,
+        buildno: _,
+    } = spec;
+    let fields: [(&str, &dyn std::fmt::Debug); 32] = [
+        ("package", package),
+        ("name", name),
+        ("version", version),
+        ("description", description),
+        ("essential", essential),
+        ("author", author),
+        ("email", email),
+        ("maintainer", maintainer),
+        ("homepage", homepage),
+        ("files", files),
+        ("conffiles", conffiles),
+        ("pre_depends", pre_depends),
+        ("dependencies", dependencies),
+        ("recommends", recommends),
+        ("suggests", suggests),
+        ("conflicts", conflicts),
+        ("breaks", breaks),
+        ("provides", provides),
+        ("replaces", replaces),
+        ("priority", priority),
+        ("arch", arch),
+        ("urgency", urgency),
+        ("section", section),
+        ("apt_sources", apt_sources),
+        ("scripts", scripts),
+        ("changelog", changelog),
+        ("copyright", copyright),
+        ("services", services),
+        ("users", users),
+        ("dbgsym", dbgsym),
+        ("compression", compression),
+        ("compression_threads", compression_threads),
+    ];
+    let mut hasher = Md5::new();
+    for (name, value) in fields {
+        hasher.update(format!("{}={:?}\n", name, value));
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn hash_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hasher = Md5::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Hash of an input file followed by its mode, which the package keeps.
+fn hash_input<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mode = fs::metadata(&path)?.mode() & 0o7777;
+    Ok(format!("{} {:o}", hash_file(path)?, mode))
+}
+
+/// HEAD and every tag of a git repository.
+fn git_state(repo: &str) -> Option<String> {
+    let repo = git2::Repository::discover(repo).ok()?;
+    let mut state = repo.head().ok()?.target()?.to_string();
+    for name in repo.tag_names(None).ok()?.iter().flatten() {
+        state.push(' ');
+        state.push_str(name);
+    }
+    Some(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    use super::Manifest;
+    use crate::build::{BuildSpec, File};
+    use crate::copyright::{Copyright, LicenseParagraph};
+
+    #[test]
+    fn fingerprint() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("tree")).unwrap();
+        fs::write(dir.join("one"), "one").unwrap();
+        fs::write(dir.join("tree/two"), "two").unwrap();
+        let src = |p: &str| dir.join(p).to_string_lossy().to_string();
+        let spec = BuildSpec {
+            package: "demo".to_string(),
+            version: "1.0".to_string(),
+            files: vec![
+                File::new(src("one"), "/usr/share/one".to_string()),
+                File::new(src("tree"), "/usr/share/tree".to_string()),
+            ],
+            ..Default::default()
+        };
+        let out = dir.join("demo.deb");
+        fs::write(&out, "package").unwrap();
+        let mut first = Manifest::new(&spec).unwrap();
+        assert_eq!(first.inputs.len(), 2);
+        first.set_outputs(std::slice::from_ref(&out)).unwrap();
+        let path = dir.join("manifest.json");
+        first.save(&path).unwrap();
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded, first);
+        assert!(Manifest::new(&spec).unwrap().is_fresh(&loaded));
+
+        // changed input
+        fs::write(dir.join("tree/two"), "three").unwrap();
+        assert!(!Manifest::new(&spec).unwrap().is_fresh(&loaded));
+        fs::write(dir.join("tree/two"), "two").unwrap();
+
+        // changed mode
+        let perms = |mode| fs::Permissions::from_mode(mode);
+        fs::set_permissions(dir.join("one"), perms(0o755)).unwrap();
+        assert!(!Manifest::new(&spec).unwrap().is_fresh(&loaded));
+        fs::set_permissions(dir.join("one"), perms(0o644)).unwrap();
+        assert!(Manifest::new(&spec).unwrap().is_fresh(&loaded));
+
+        // license files read into the copyright file
+        fs::write(dir.join("LICENSE"), "license").unwrap();
+        let licensed = BuildSpec {
+            copyright: Some(Copyright {
+                licenses: vec![LicenseParagraph {
+                    name: "Custom".to_string(),
+                    text: None,
+                    file: Some(src("LICENSE")),
+                }],
+                ..Default::default()
+            }),
+            ..spec.clone()
+        };
+        let before = Manifest::new(&licensed).unwrap();
+        assert_eq!(before.inputs.len(), 3);
+        fs::write(dir.join("LICENSE"), "changed").unwrap();
+        assert_ne!(Manifest::new(&licensed).unwrap().inputs, before.inputs);
+
+        // changed spec
+        let other = BuildSpec {
+            version: "1.1".to_string(),
+            ..spec.clone()
+        };
+        assert!(!Manifest::new(&other).unwrap().is_fresh(&loaded));
+
+        // a new build number alone doesn't need a rebuild, and the manifest stays where it was
+        let next = BuildSpec {
+            buildno: Some(2),
+            ..spec.clone()
+        };
+        assert!(Manifest::new(&next).unwrap().is_fresh(&loaded));
+        assert_eq!(Manifest::path(dir, &next), Manifest::path(dir, &spec));
+        assert_ne!(
+            Manifest::path(dir, &spec),
+            Manifest::path(dir.join("tree"), &spec)
+        );
+
+        // modified or missing output
+        fs::write(&out, "tampered").unwrap();
+        assert!(!Manifest::new(&spec).unwrap().is_fresh(&loaded));
+        fs::remove_file(&out).unwrap();
+        assert!(!Manifest::new(&spec).unwrap().is_fresh(&loaded));
+    }
+}
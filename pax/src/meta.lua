@@ -0,0 +1,423 @@
+---@meta pax
+
+error("Cannot require a meta file")
+
+pax = {}
+
+--#CLASSES#
+
+--- @class pax.File
+--- @field src string
+--- @field dst string
+--- @field mode? integer
+--- @field owner? string Owner of the installed file. Default is root.
+--- @field group? string Group of the installed file. Default is root.
+--- @field link? string Install a symlink to this target at `dst` instead of copying `src`.
+
+--- Scripts are merged with any snippets pax generates. Put "#PAX#" on a line of its own to choose
+--- where the snippets go, otherwise they are added to the top of the script.
+--- @class pax.MaintainerScripts
+--- @field dir? string Load preinst, postinst, prerm and postrm from this directory.
+--- @field preinst? string|pax.ScriptFile
+--- @field postinst? string|pax.ScriptFile
+--- @field prerm? string|pax.ScriptFile
+--- @field postrm? string|pax.ScriptFile
+
+--- @class pax.ScriptFile
+--- @field file string Path of the script.
+
+--- @class pax.Service
+--- @field unit                string  Path to the unit file (.service, .socket, .timer, ...).
+--- @field enable?             boolean Enable the unit on first install. Default is true.
+--- @field start?              boolean Start the unit after install. Default is true.
+--- @field restart_on_upgrade? boolean Restart after upgrading instead of stopping before. Default is true.
+
+--- @class pax.AptSources
+--- @field name           string
+--- @field url            string
+--- @field suites         string[]|string Suites, as a list or separated by spaces.
+--- @field components?    string[]|string
+--- @field architectures? string[]|string
+--- @field key_url?       string Signing key downloaded at build time into /usr/share/keyrings.
+--- @field key_file?      string Signing key read at build time into /usr/share/keyrings.
+--- @field signed_by?     string Keyring that already exists on the target system.
+
+--- @class pax.User
+--- @field name             string
+--- @field system?          boolean  Create a system account. Default is true.
+--- @field home?            string   Home directory, created when missing.
+--- @field shell?           string   Login shell. System accounts default to /usr/sbin/nologin.
+--- @field groups?          string[] Supplementary groups, created when missing.
+--- @field remove_on_purge? boolean  Delete the account when the package is purged.
+
+--- @class pax.Stat
+--- @field size number
+--- @field mode? integer
+--- @field mtime number
+--- @field atime number
+--- @field ctime number
+--- @field uid number
+--- @field gid number
+--- @field dev number
+--- @field ino number
+--- @field blocks number
+--- @field blksize number
+--- @field nlink number
+
+--- @param spec table
+--- @return pax.Project
+function pax.project(spec) end
+
+--- @return string
+function pax.cwd() end
+
+--- Number of packages built at the same time, set with --jobs.
+--- @type number?
+pax.jobs = nil
+
+--- Define a task that runs with `pax run <name>`. Every task runs at most once, after the tasks it
+--- depends on. The task named "default" runs when no task is given.
+--- @param name string
+--- @param fn fun()
+--- @param opts? pax.TaskOpts
+function pax.task(name, fn, opts) end
+
+--- Arguments given after '--', as in `pax run -- a b`.
+--- @type string[]
+pax.args = {}
+
+--- Variables set with -D key=value.
+--- @type table<string, string>
+pax.vars = {}
+
+--- Read a variable set with -D, falling back to the environment (and .env) and then `default`.
+--- @param name string
+--- @param default? string
+--- @return string?
+function pax.var(name, default) end
+
+--- Like pax.var, but the value must be a number.
+--- @param name string
+--- @param default? number
+--- @return number?
+function pax.var_number(name, default) end
+
+--- Like pax.var, but the value must be true/false, yes/no, on/off or 1/0.
+--- @param name string
+--- @param default? boolean
+--- @return boolean?
+function pax.var_bool(name, default) end
+
+--- Like pax.var, but the value is split on commas.
+--- @param name string
+--- @param default? string[]
+--- @return string[]?
+function pax.var_list(name, default) end
+
+--- Build every package added with pax:add() in parallel. Every package is attempted and the
+--- failures are reported together.
+--- @param opts? pax.BuildAllOpts
+function pax:package_all(opts) end
+
+---@param ... any
+function pax.print(...) end
+
+--- @param message string
+function pax.log(message) end
+
+--- @param s string
+--- @return number
+function pax.octal(s) end
+
+--- @param bin string
+--- @param args? string[]
+function pax.exec(bin, args) end
+
+---@param script string
+function pax.sh(script) end
+
+-- Run the function inside of the specified directory.
+--- @param dir string
+--- @param fn function
+function pax.in_dir(dir, fn) end
+
+pax.git = {}
+
+--- @return string
+function pax.git.email() end
+
+--- @return string
+function pax.git.username() end
+
+--- @return string
+function pax.git.version() end
+
+--- @param repo string
+--- @param opts? pax.GitCloneOpts
+function pax.git.clone(repo, opts) end
+
+pax.go = {}
+
+--- @param go pax.Go
+--- @return string[]
+function pax.go.list(go) end
+
+--- @param go pax.Go
+function pax.go.build(go) end
+
+--- @param go pax.Go
+function pax.go.run(go) end
+
+--- @param go pax.Go
+function pax.go.generate(go) end
+
+pax.cargo = {}
+
+--- @param opts? pax.Cargo|string
+function pax.cargo.build(opts) end
+
+pax.dl = {}
+
+--- @param url string
+--- @param opts pax.DownloadOpts
+function pax.dl.fetch(url, opts) end
+
+--- @param opts pax.DownloadOpts
+function pax.dl.kubectl(opts) end
+
+--- @param opts pax.DownloadOpts
+function pax.dl.jq(opts) end
+
+--- @param opts pax.DownloadOpts
+function pax.dl.youtube_dl(opts) end
+
+--- @param opts pax.DownloadOpts
+function pax.dl.yt_dlp(opts) end
+
+--- @param opts pax.DownloadOpts
+function pax.dl.mc(opts) end
+
+--- @param opts pax.DownloadOpts
+function pax.dl.tetris(opts) end
+
+--- @param opts pax.DownloadOpts
+function pax.dl.balena_etcher(opts) end
+
+pax.fs = {}
+
+--- @vararg string
+function pax.fs.exists(...) end
+
+--- @vararg string
+function pax.fs.rm(...) end
+
+--- @vararg string
+function pax.fs.rmdir(...) end
+
+--- @vararg string
+function pax.fs.rmdir_all(...) end
+
+--- @vararg string
+function pax.fs.mkdir(...) end
+
+--- @vararg string
+function pax.fs.mkdir_all(...) end
+
+--- @vararg string
+function pax.fs.mkdir_force(...) end
+
+--- @param dir string
+--- @return pax.Stat
+function pax.fs.stat(dir) end
+
+pax.os = {}
+
+--- @param bin string
+--- @param args? string[]
+--- @param opts? pax.ExecOptions
+--- @return number
+function pax.os.exec(bin, args, opts) end
+
+--- Locate a binary in the $PATH
+--- @param name string
+--- @return string
+function pax.os.which(name) end
+
+--- Return the current version of libc.
+--- @return pax.LibcVersion
+function pax.os.libc_version() end
+
+pax.path = {}
+
+--- @vararg string
+--- @return string
+function pax.path.join(...) end
+
+--- @param path string
+--- @return string|nil
+function pax.path.basename(path) end
+
+--- @vararg any
+--- @return boolean
+function pax.path.is_absolute(...) end
+
+--- @vararg any
+--- @return boolean
+function pax.path.is_relative() end
+
+--- @vararg any
+--- @return string
+function pax.path.parent(...) end
+
+pax.control = {}
+
+--- Parse the first paragraph of a debian control file.
+--- @param text string
+--- @return pax.BuildSpec
+function pax.control.parse(text) end
+
+--- Parse every paragraph of a deb822 formatted file.
+--- @param text string
+--- @return pax.BuildSpec[]
+function pax.control.parse_all(text) end
+
+--- Read the control file and maintainer scripts out of a .deb package.
+--- @param path string
+--- @return pax.BuildSpec
+function pax.control.read_deb(path) end
+
+pax.deb = {}
+
+--- Open a .deb package to look at its contents or to import them into a project. Any
+--- compression of the data archive is supported.
+--- @param path string
+--- @return pax.Deb
+function pax.deb.open(path) end
+
+--- A .deb file opened with `pax.deb.open` or read back in `require("pax.testing")`.
+--- @class pax.Deb
+--- @field path     string
+--- @field package? string
+--- @field version? string
+--- @field arch?    string
+--- @field control  table<string, string> Fields of the control file.
+--- @field scripts  table<string, string> The other control archive files by name, like postinst and md5sums.
+--- @field files    pax.DebEntry[] Entries of the data archive sorted by path.
+local Deb = {}
+
+--- @param path string Such as "/usr/bin/foo".
+--- @return pax.DebEntry?
+function Deb:file(path) end
+
+--- A control field, matched without regard to case.
+--- @param name string
+--- @return string?
+function Deb:field(name) end
+
+--- Add the files matching the glob patterns to a project, keeping their modes, owners and
+--- symlinks. A pattern matching a directory also copies what is inside it. Without patterns the
+--- whole package is copied. Conffiles stay conffiles, and files the project already has at the
+--- same paths are replaced.
+--- @param project pax.Project
+--- @param patterns? string|string[] Such as "/usr/lib/foo/**" or { "/usr/bin/*", "/etc/foo" }.
+--- @return integer copied The number of files, directories and links added.
+function Deb:copy(project, patterns) end
+
+--- Add the dependencies of the package to a project's Pre-Depends, Depends, Recommends and
+--- Suggests, skipping the ones it already has.
+--- @param project pax.Project
+--- @return integer added
+function Deb:inherit_deps(project) end
+
+--- @class pax.DebEntry
+--- @field path    string Installed path, like "/usr/bin/foo".
+--- @field kind    "file"|"dir"|"symlink"|"hardlink"
+--- @field mode    integer
+--- @field owner   string
+--- @field group   string
+--- @field uid     integer
+--- @field gid     integer
+--- @field size    integer
+--- @field target? string Target of a link.
+--- @field md5?    string md5 of a file's content.
+
+---@class pax.Project
+---@field base_dir     string
+---@field man_dir      string
+---@field version      string  Project's package version.
+---@field package      string
+---@field arch         string
+---@field essential    boolean
+---@field author?      string Package author.
+---@field email?       string Package email.
+---@field maintainer?  string
+---@field description? string
+local Project = {}
+
+function Project:build() end
+
+function Project:finish() end
+
+---@return string
+function Project:dir() end
+
+--- Files added to the package so far.
+--- @return pax.File[]
+function Project:files() end
+
+--- @param source pax.AptSources
+function Project:apt_source(source) end
+
+---@param path string
+function Project:add_binary(path) end
+
+--- @vararg pax.File
+function Project:add_file(...) end
+
+--- @param files pax.File[]
+function Project:add_files(files) end
+
+--- Copy every file of a .deb package into the project. Same as `pax.deb.open(path):copy(project)`.
+--- @param path string
+function Project:merge_deb(path) end
+
+---@param opts pax.Go
+function Project:go_build(opts) end
+
+--- @param opts pax.Cargo
+function Project:cargo_build(opts) end
+
+function Project:reset_build_number() end
+
+function Project:enable_auto_build_numbers() end
+
+--- @param url string
+--- @param name? string
+--- @param opts? pax.DownloadOpts
+function Project:download_binary(url, name, opts) end
+
+--- @param opts? pax.DownloadOpts
+function Project:download_kubectl(opts) end
+
+--- @param opts? pax.DownloadOpts
+function Project:download_jq(opts) end
+
+--- @param opts? pax.DownloadOpts
+function Project:download_youtube_dl(opts) end
+
+--- @param opts? pax.DownloadOpts
+function Project:download_yt_dlp(opts) end
+
+--- @param opts? pax.DownloadOpts
+function Project:download_mc(opts) end
+
+--- @param opts? pax.DownloadOpts
+function Project:download_tetris(opts) end
+
+--- @param opts? pax.DownloadOpts
+function Project:download_balena_etcher(opts) end
+
+--- @param opts pax.SCDocOpts
+function Project:scdoc(opts) end
+
+return pax
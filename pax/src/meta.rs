@@ -0,0 +1,69 @@
+//! LuaLS annotations for pax.lua, printed by `pax generate`. Classes and enums come from the
+//! `LUA_META` constants emitted by pax-derive, everything else is written by hand in meta.lua.
+
+use crate::build::{BuildAllOpts, BuildSpec};
+use crate::changelog::ChangelogOpts;
+use crate::copyright::{Copyright, FilesParagraph, LicenseParagraph};
+use crate::crates::Cargo;
+use crate::deb::{Compression, Priority, Urgency};
+use crate::dl::DownloadOpts;
+use crate::git::GitCloneOpts;
+use crate::go::{Go, GoBuildData};
+use crate::modules::LibcVersion;
+use crate::os::ExecOptions;
+use crate::tasks::TaskOpts;
+use crate::util::SCDocOpts;
+
+static TEMPLATE: &str = include_str!("meta.lua");
+
+/// Replaced with the generated classes.
+static MARKER: &str = "--#CLASSES#\n";
+
+/// Types exposed to Lua, in the order they are written.
+static CLASSES: &[&str] = &[
+    BuildSpec::LUA_META,
+    BuildAllOpts::LUA_META,
+    Urgency::LUA_META,
+    Priority::LUA_META,
+    Compression::LUA_META,
+    ChangelogOpts::LUA_META,
+    Copyright::LUA_META,
+    FilesParagraph::LUA_META,
+    LicenseParagraph::LUA_META,
+    TaskOpts::LUA_META,
+    DownloadOpts::LUA_META,
+    Go::LUA_META,
+    GoBuildData::LUA_META,
+    Cargo::LUA_META,
+    GitCloneOpts::LUA_META,
+    ExecOptions::LUA_META,
+    LibcVersion::LUA_META,
+    SCDocOpts::LUA_META,
+];
+
+/// The full contents of lua/_meta/pax.lua.
+pub(crate) fn generate() -> String {
+    TEMPLATE.replacen(MARKER, &CLASSES.join("\n"), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate;
+
+    #[test]
+    fn up_to_date() {
+        let meta = generate();
+        assert!(meta.contains("--- @field section? string"));
+        assert!(meta.contains("--- @field stdin_file? string"));
+        assert!(meta.contains("--- @field build_ldflags? fun(data: pax.GoBuildData): string[]"));
+        assert!(meta.contains("function Project:files() end"));
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../lua/_meta/pax.lua");
+        let committed = std::fs::read_to_string(path).unwrap();
+        assert!(
+            committed == meta,
+            "{} is out of date, run `pax generate > lua/_meta/pax.lua`",
+            path
+        );
+    }
+}
@@ -5,11 +5,16 @@ use std::path::{Path, PathBuf};
 use mlua::Lua;
 use which::which;
 
+use crate::build::BuildSpec;
+use crate::control;
+use crate::debfile::{self, Deb};
 use crate::dl;
+use crate::error::Error;
 use crate::git;
 use crate::git::GitCloneOpts;
 use crate::go::Go;
 use crate::os::{exec, ExecOptions};
+use crate::sandbox;
 use crate::util::{gcc_features, get_user_email, get_user_name, git_version};
 
 macro_rules! sub_module {
@@ -56,7 +61,7 @@ impl GitSubModule {
     }
     fn clone(_lua: &mlua::Lua, (repo, opts): (String, Option<GitCloneOpts>)) -> mlua::Result<()> {
         git::git_clone(opts.unwrap_or_else(|| GitCloneOpts::new(repo)))
-            .map_err(mlua::Error::runtime)?;
+            .map_err(mlua::Error::external)?;
         Ok(())
     }
 }
@@ -80,14 +85,7 @@ impl CargoModule {
             _ => crates::Cargo::from_lua(args, lua)?,
         };
         println!("building {}", cargo.root);
-        let res = cargo.build();
-        match res {
-            Err(e) => {
-                println!("{:?}", e);
-                Err(mlua::Error::runtime(e))
-            }
-            Ok(_) => Ok(()),
-        }
+        cargo.build().map_err(mlua::Error::external)
     }
 }
 
@@ -95,19 +93,19 @@ sub_module!(@userdata GoModule; list, build, run, generate);
 
 impl GoModule {
     fn list(_lua: &mlua::Lua, go: Go) -> mlua::Result<String> {
-        go.list().map_err(mlua::Error::runtime)
+        go.list().map_err(mlua::Error::external)
     }
 
     fn build(_lua: &mlua::Lua, go: Go) -> mlua::Result<()> {
-        go.build().map_err(mlua::Error::runtime)
+        go.build().map_err(mlua::Error::external)
     }
 
     fn run(_lua: &mlua::Lua, go: Go) -> mlua::Result<()> {
-        go.run().map_err(mlua::Error::runtime)
+        go.run().map_err(mlua::Error::external)
     }
 
     fn generate(_lua: &mlua::Lua, go: Go) -> mlua::Result<()> {
-        go.generate(&go.cmd).map_err(mlua::Error::runtime)
+        go.generate(&go.cmd).map_err(mlua::Error::external)
     }
 }
 
@@ -115,35 +113,35 @@ sub_module!(@userdata DlModule; fetch, kubectl, jq, youtube_dl, yt_dlp, mc, tetr
 
 impl DlModule {
     fn fetch(_lua: &mlua::Lua, (url, opts): (String, dl::DownloadOpts)) -> mlua::Result<()> {
-        dl::fetch(url, opts).map_err(mlua::Error::runtime)?;
+        dl::fetch(url, opts).map_err(mlua::Error::external)?;
         Ok(())
     }
     fn kubectl(_lua: &mlua::Lua, opts: dl::DownloadOpts) -> mlua::Result<()> {
-        dl::kubectl(opts).map_err(mlua::Error::runtime)?;
+        dl::kubectl(opts).map_err(mlua::Error::external)?;
         Ok(())
     }
     fn jq(_: &mlua::Lua, opts: dl::DownloadOpts) -> mlua::Result<()> {
-        dl::jq(opts).map_err(mlua::Error::runtime)?;
+        dl::jq(opts).map_err(mlua::Error::external)?;
         Ok(())
     }
     fn youtube_dl(_: &Lua, opts: dl::DownloadOpts) -> mlua::Result<()> {
-        dl::youtube_dl(opts).map_err(mlua::Error::runtime)?;
+        dl::youtube_dl(opts).map_err(mlua::Error::external)?;
         Ok(())
     }
     fn yt_dlp(_: &Lua, opts: dl::DownloadOpts) -> mlua::Result<()> {
-        dl::yt_dlp(opts).map_err(mlua::Error::runtime)?;
+        dl::yt_dlp(opts).map_err(mlua::Error::external)?;
         Ok(())
     }
     fn mc(_: &Lua, opts: dl::DownloadOpts) -> mlua::Result<()> {
-        dl::mc(opts).map_err(mlua::Error::runtime)?;
+        dl::mc(opts).map_err(mlua::Error::external)?;
         Ok(())
     }
     fn tetris(_: &Lua, opts: dl::DownloadOpts) -> mlua::Result<()> {
-        dl::tetris(opts).map_err(mlua::Error::runtime)?;
+        dl::tetris(opts).map_err(mlua::Error::external)?;
         Ok(())
     }
     fn balena_etcher(_: &Lua, opts: dl::DownloadOpts) -> mlua::Result<()> {
-        dl::balena_etcher(opts).map_err(mlua::Error::runtime)?;
+        dl::balena_etcher(opts).map_err(mlua::Error::external)?;
         Ok(())
     }
 }
@@ -190,6 +188,7 @@ impl FSMod {
 
     fn rm(_: &Lua, args: mlua::Variadic<String>) -> mlua::Result<()> {
         for path in args {
+            sandbox::check_write("pax.fs.rm", &path)?;
             fs::remove_file(path)?;
         }
         Ok(())
@@ -197,6 +196,7 @@ impl FSMod {
 
     fn rmdir(_: &Lua, args: mlua::Variadic<String>) -> mlua::Result<()> {
         for a in args {
+            sandbox::check_write("pax.fs.rmdir", &a)?;
             fs::remove_dir(a)?;
         }
         Ok(())
@@ -204,6 +204,7 @@ impl FSMod {
 
     fn rmdir_all(_: &Lua, args: mlua::Variadic<String>) -> mlua::Result<()> {
         for a in args {
+            sandbox::check_write("pax.fs.rmdir_all", &a)?;
             fs::remove_dir_all(a)?;
         }
         Ok(())
@@ -211,6 +212,7 @@ impl FSMod {
 
     fn mkdir(_: &Lua, args: mlua::Variadic<String>) -> mlua::Result<()> {
         for a in args {
+            sandbox::check_write("pax.fs.mkdir", &a)?;
             fs::create_dir(a)?;
         }
         Ok(())
@@ -218,6 +220,7 @@ impl FSMod {
 
     fn mkdir_all(_: &Lua, args: mlua::Variadic<String>) -> mlua::Result<()> {
         for a in args {
+            sandbox::check_write("pax.fs.mkdir_all", &a)?;
             fs::create_dir_all(a)?;
         }
         Ok(())
@@ -225,6 +228,7 @@ impl FSMod {
 
     fn mkdir_force(_: &Lua, args: mlua::Variadic<String>) -> mlua::Result<()> {
         for path in args {
+            sandbox::check_write("pax.fs.mkdir_force", &path)?;
             match fs::create_dir(path) {
                 Ok(_) => (),
                 Err(e) => return Err(e.into()),
@@ -296,7 +300,7 @@ fn path_from_lua(args: mlua::Variadic<mlua::Value>) -> mlua::Result<PathBuf> {
 sub_module!(@userdata OsMod; exec, which, libc_version);
 
 #[derive(Debug, Default, pax_derive::FromLuaTable, pax_derive::IntoLua)]
-struct LibcVersion {
+pub(crate) struct LibcVersion {
     major: u32,
     minor: u32,
 }
@@ -325,3 +329,30 @@ impl OsMod {
         })
     }
 }
+
+sub_module!(@userdata ControlMod; parse, parse_all, read_deb);
+
+impl ControlMod {
+    fn parse(_: &Lua, text: String) -> mlua::Result<BuildSpec> {
+        BuildSpec::parse(text.as_bytes()).map_err(mlua::Error::external)
+    }
+
+    fn parse_all(_: &Lua, text: String) -> mlua::Result<Vec<BuildSpec>> {
+        control::parse(&text)?
+            .iter()
+            .map(|p| BuildSpec::from_paragraph(p).map_err(mlua::Error::external))
+            .collect()
+    }
+
+    fn read_deb(_: &Lua, path: String) -> mlua::Result<BuildSpec> {
+        control::read_deb(&path).map_err(|e| Error::archive(path, e).into())
+    }
+}
+
+sub_module!(@userdata DebMod; open);
+
+impl DebMod {
+    fn open(lua: &Lua, path: String) -> mlua::Result<Deb> {
+        debfile::open(lua, path)
+    }
+}
@@ -1,7 +1,10 @@
 #[derive(Clone, Debug, Default, pax_derive::FromLuaTable, pax_derive::IntoLua)]
 pub(crate) struct ExecOptions {
+    /// Directory the command runs in.
     dir: Option<String>,
+    /// Read the command's stdin from this file.
     stdin_file: Option<String>,
+    /// Write the command's stdout to this file.
     stdout_file: Option<String>,
 }
 
@@ -24,7 +27,14 @@ impl mlua::FromLua<'_> for ExecOptions {
 }
 
 pub(crate) fn exec(bin: String, args: Vec<String>, opts: Option<ExecOptions>) -> mlua::Result<i32> {
-    let mut cmd = &mut std::process::Command::new(bin);
+    crate::sandbox::check_exec("pax.os.exec")?;
+    if let Some(file) = opts.as_ref().and_then(|o| o.stdout_file.as_ref()) {
+        crate::sandbox::check_write("pax.os.exec", file)?;
+    }
+    if crate::plan::skip(format!("{} {}", bin, args.join(" ")).trim_end()) {
+        return Ok(0);
+    }
+    let mut cmd = &mut crate::util::command(bin)?;
     if args.len() > 0 {
         cmd = cmd.args(args);
     }
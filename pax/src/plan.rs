@@ -0,0 +1,252 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{Serialize, Serializer};
+
+use crate::build::BuildSpec;
+use crate::control;
+use crate::scripts::Script;
+use crate::testing;
+use crate::util::walk;
+
+thread_local! {
+    // Per thread, like the `pax test` mocks, so that a dry run only covers the config being run
+    // and not whatever else runs alongside it.
+    static DRY_RUN: Cell<bool> = const { Cell::new(false) };
+}
+static PLAN: Mutex<Plan> = Mutex::new(Plan {
+    packages: Vec::new(),
+    commands: Vec::new(),
+});
+
+/// Everything a dry run would have done.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct Plan {
+    pub packages: Vec<PackagePlan>,
+    /// External commands and downloads, in the order they were requested.
+    pub commands: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PackagePlan {
+    /// Path the package would have been written to.
+    pub path: String,
+    pub package: String,
+    pub version: String,
+    pub arch: String,
+    #[serde(serialize_with = "ordered_map")]
+    pub control: Vec<(String, String)>,
+    pub files: Vec<FilePlan>,
+    pub scripts: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FilePlan {
+    pub src: String,
+    pub dst: String,
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+fn ordered_map<S: Serializer>(fields: &[(String, String)], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_map(fields.iter().map(|(k, v)| (k, v)))
+}
+
+/// Record what would happen instead of building packages, running commands or downloading.
+pub(crate) fn enable(dry_run: bool) {
+    DRY_RUN.set(dry_run);
+}
+
+pub(crate) fn dry_run() -> bool {
+    DRY_RUN.get()
+}
+
+/// Record an external command during a dry run, or when `pax test` mocks it. Returns true when
+/// the caller should skip running it.
+pub(crate) fn skip<S: Into<String>>(command: S) -> bool {
+    let command = command.into();
+    if testing::mocked(&command) {
+        return true;
+    }
+    if !dry_run() {
+        return false;
+    }
+    PLAN.lock().unwrap().commands.push(command);
+    true
+}
+
+/// Record the package that `spec` would have been built into.
+pub(crate) fn add_package(spec: &BuildSpec, path: &Path) -> io::Result<()> {
+    let mut files: Vec<_> = spec
+        .files
+        .iter()
+        .map(|f| FilePlan {
+            src: match (&f.link, &f.dir) {
+                (Some(target), _) => format!("symlink to {}", target),
+                (None, Some(dir)) => dir.clone(),
+                (None, None) => f.src.clone(),
+            },
+            dst: f.dst.clone(),
+            mode: f.mode,
+            owner: f.owner.clone(),
+            group: f.group.clone(),
+        })
+        .collect();
+    for service in spec.services.iter().flatten() {
+        files.push(FilePlan {
+            src: service.unit.clone(),
+            dst: service.dst()?,
+            mode: Some(0o644),
+            owner: None,
+            group: None,
+        });
+    }
+
+    // The inputs may not exist yet since nothing was built, so the installed size only counts
+    // the files that do.
+    let mut size = 0;
+    for file in spec.files.iter().filter(|f| !f.src.is_empty()) {
+        _ = walk_size(Path::new(&file.src), &mut size);
+    }
+    let mut buf = Vec::new();
+    spec.generate_control(&mut buf, size)?;
+    let control = control::parse(&String::from_utf8_lossy(&buf))?
+        .into_iter()
+        .next()
+        .map(|p| p.fields().to_vec())
+        .unwrap_or_default();
+
+    let composer = spec.script_composer()?;
+    let user = spec.scripts.clone().unwrap_or_default();
+    let scripts = Script::ALL
+        .into_iter()
+        .filter_map(|s| Some((s.name().to_string(), composer.compose(s, user.get(s))?)))
+        .collect();
+
+    PLAN.lock().unwrap().packages.push(PackagePlan {
+        path: path.to_string_lossy().to_string(),
+        package: spec.package.clone(),
+        version: spec.version(),
+        arch: spec.arch.clone(),
+        control,
+        files,
+        scripts,
+    });
+    Ok(())
+}
+
+fn walk_size(path: &Path, size: &mut u64) -> io::Result<()> {
+    let meta = fs::metadata(path)?;
+    if !meta.is_dir() {
+        *size += meta.len();
+        return Ok(());
+    }
+    walk(path, |entry| {
+        let meta = entry.metadata()?;
+        if meta.is_file() {
+            *size += meta.len();
+        }
+        Ok(())
+    })
+}
+
+/// Take the plan recorded so far.
+pub(crate) fn take() -> Plan {
+    std::mem::take(&mut *PLAN.lock().unwrap())
+}
+
+impl Plan {
+    pub(crate) fn write_text<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for pkg in &self.packages {
+            writeln!(w, "package {} {} ({})", pkg.package, pkg.version, pkg.path)?;
+            writeln!(w, "  control:")?;
+            for (key, value) in &pkg.control {
+                let mut lines = value.lines();
+                writeln!(w, "    {}: {}", key, lines.next().unwrap_or_default())?;
+                for line in lines {
+                    writeln!(w, "     {}", line)?;
+                }
+            }
+            writeln!(w, "  files:")?;
+            for file in &pkg.files {
+                let mode = file.mode.map_or("-".to_string(), |m| format!("{:04o}", m));
+                write!(w, "    {} {} -> {}", mode, file.src, file.dst)?;
+                if file.owner.is_some() || file.group.is_some() {
+                    write!(
+                        w,
+                        " ({}:{})",
+                        file.owner.as_deref().unwrap_or("root"),
+                        file.group.as_deref().unwrap_or("root")
+                    )?;
+                }
+                writeln!(w)?;
+            }
+            if !pkg.scripts.is_empty() {
+                writeln!(w, "  scripts:")?;
+                for (name, body) in &pkg.scripts {
+                    writeln!(w, "    {}:", name)?;
+                    for line in body.lines() {
+                        writeln!(w, "      {}", line)?;
+                    }
+                }
+            }
+        }
+        if !self.commands.is_empty() {
+            writeln!(w, "commands:")?;
+            for cmd in &self.commands {
+                writeln!(w, "  {}", cmd)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_package, enable, skip, take};
+    use crate::build::{BuildSpec, File};
+
+    #[test]
+    fn dry_run() {
+        assert!(!skip("go build"));
+        enable(true);
+        assert!(skip("cargo build --release"));
+        let spec = BuildSpec {
+            package: "demo".to_string(),
+            version: "1.0".to_string(),
+            arch: "amd64".to_string(),
+            author: Some("A".to_string()),
+            email: Some("a@b.c".to_string()),
+            files: vec![File::new(
+                "target/release/demo".to_string(),
+                "/usr/bin/demo".to_string(),
+            )],
+            ..Default::default()
+        };
+        add_package(&spec, "dist/demo.deb".as_ref()).unwrap();
+        enable(false);
+        let plan = take();
+        assert_eq!(plan.commands, vec!["cargo build --release"]);
+        let json = serde_json::to_value(&plan).unwrap();
+        let pkg = &json["packages"][0];
+        assert_eq!(pkg["path"], "dist/demo.deb");
+        assert_eq!(pkg["control"]["Package"], "demo");
+        assert_eq!(pkg["control"]["Maintainer"], "A <a@b.c>");
+        assert_eq!(pkg["files"][0]["dst"], "/usr/bin/demo");
+
+        let mut text = Vec::new();
+        plan.write_text(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("package demo 1.0 (dist/demo.deb)"));
+        assert!(text.contains("target/release/demo -> /usr/bin/demo"));
+        assert!(text.contains("commands:\n  cargo build --release\n"));
+        assert!(take().packages.is_empty());
+    }
+}
@@ -4,17 +4,17 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use flate2::read::GzDecoder;
 use md5::{Digest, Md5};
 use mlua::FromLua;
-use xz2::read::XzDecoder;
 
 use crate::{
     build::{BuildSpec, File, DEFAULT_DIST},
     deb::Version,
+    debfile::{install_path, DebFile},
     dl::{self, DownloadOpts},
     go::Go,
-    util::{self, scdoc, SCDocOpts},
+    plan,
+    util::{self, scdoc, to_io_err, SCDocOpts},
 };
 
 #[derive(Debug)]
@@ -119,7 +119,7 @@ impl mlua::UserData for Project {
                         .to_string(),
                 );
             }
-            opts.build().map_err(mlua::Error::runtime)?;
+            opts.build().map_err(mlua::Error::external)?;
             if let Some(mode) = opts.bin_access_mode {
                 this.add_bin_mode(opts.out.unwrap(), mode)?;
             } else {
@@ -138,7 +138,7 @@ impl mlua::UserData for Project {
                 mlua::Value::Nil => crates::Cargo::from_path("."),
                 _ => crates::Cargo::from_lua(args, lua)?,
             };
-            cargo.build().map_err(mlua::Error::runtime)?;
+            cargo.build().map_err(mlua::Error::external)?;
             this.add_bin(cargo.bin())?;
             Ok(())
         });
@@ -165,49 +165,49 @@ impl mlua::UserData for Project {
         methods.add_method_mut("download_kubectl", |_, this, opts: DownloadOpts| {
             let mut opts = opts.clone();
             opts.out = Some(this.bin_path("kubectl"));
-            let out = dl::kubectl(opts).map_err(mlua::Error::runtime)?;
+            let out = dl::kubectl(opts).map_err(mlua::Error::external)?;
             this.add_bin(out)?;
             Ok(())
         });
         methods.add_method_mut("download_jq", |_, this, opts: DownloadOpts| {
             let mut opts = opts.clone();
             opts.out = Some(this.bin_path("jq"));
-            let out = dl::jq(opts).map_err(mlua::Error::runtime)?;
+            let out = dl::jq(opts).map_err(mlua::Error::external)?;
             this.add_bin(out)?;
             Ok(())
         });
         methods.add_method_mut("download_youtube_dl", |_, this, opts: DownloadOpts| {
             let mut opts = opts.clone();
             opts.out = Some(this.bin_path("youtube-dl"));
-            let out = dl::youtube_dl(opts).map_err(mlua::Error::runtime)?;
+            let out = dl::youtube_dl(opts).map_err(mlua::Error::external)?;
             this.add_bin(out)?;
             Ok(())
         });
         methods.add_method_mut("download_yt_dlp", |_, this, opts: DownloadOpts| {
             let mut opts = opts.clone();
             opts.out = Some(this.bin_path("yt-dlp"));
-            let out = dl::yt_dlp(opts).map_err(mlua::Error::runtime)?;
+            let out = dl::yt_dlp(opts).map_err(mlua::Error::external)?;
             this.add_bin(out)?;
             Ok(())
         });
         methods.add_method_mut("download_mc", |_, this, opts: DownloadOpts| {
             let mut opts = opts.clone();
             opts.out = Some(this.bin_path("mc"));
-            let out = dl::mc(opts).map_err(mlua::Error::runtime)?;
+            let out = dl::mc(opts).map_err(mlua::Error::external)?;
             this.add_bin(out)?;
             Ok(())
         });
         methods.add_method_mut("download_tetris", |_, this, opts: DownloadOpts| {
             let mut opts = opts.clone();
             opts.out = Some(this.bin_path("tetris"));
-            let out = dl::tetris(opts).map_err(mlua::Error::runtime)?;
+            let out = dl::tetris(opts).map_err(mlua::Error::external)?;
             this.add_bin(out)?;
             Ok(())
         });
         methods.add_method_mut("download_balena_etcher", |_, this, opts: DownloadOpts| {
             let mut opts = opts.clone();
             opts.out = Some(this.bin_path("BalenaEtcher.AppImage"));
-            let out = dl::balena_etcher(opts).map_err(mlua::Error::runtime)?;
+            let out = dl::balena_etcher(opts).map_err(mlua::Error::external)?;
             this.add_bin(out)?;
             Ok(())
         });
@@ -252,18 +252,22 @@ impl Project {
             dst: dst.to_string_lossy().to_string(),
             mode: Some(mode),
             dir: None,
+            owner: None,
+            group: None,
+            link: None,
         });
         Ok(())
     }
 
     fn build(&mut self) -> mlua::Result<()> {
         _ = std::fs::create_dir_all(DEFAULT_DIST);
-        if let Some(n) = self.build {
-            self.spec.buildno = Some(n);
-            self.increment_build_no()?;
-        }
+        self.spec.buildno = self.build;
         self.spec.pre_process(Some(self.base_dir.clone()))?;
-        self.spec.build(DEFAULT_DIST.to_string())?;
+        let built = self.spec.build(DEFAULT_DIST)?;
+        // The number is only used up when a package was written with it.
+        if built && self.build.is_some() && !plan::dry_run() {
+            self.build = Some(self.increment_build_no()?);
+        }
         Ok(())
     }
 
@@ -297,53 +301,36 @@ impl Project {
             dst,
             mode: Some(0o644),
             dir: None,
+            owner: None,
+            group: None,
+            link: None,
         });
         Ok(())
     }
 
     fn merge_deb(&mut self, source: &str) -> mlua::Result<()> {
-        let source_path = Path::new(source);
-        let source_name = source_path
-            .file_name()
-            .ok_or(mlua::Error::runtime(
-                "failed to get debian package filename",
-            ))?
-            .to_string_lossy()
-            .to_string()
-            .replace(".deb", "");
-        let base = &self.cache_dir().join("debs").join(source_name);
-        _ = fs::remove_dir_all(base);
-        fs::create_dir_all(base).map_err(|e| {
-            io::Error::new(e.kind(), format!("{}: failed to create dir {:?}", e, &base))
-        })?;
-
-        let mut pkg = ar::Archive::new(fs::File::open(source)?);
-        while let Some(ar_entry) = pkg.next_entry() {
-            let entry = ar_entry?;
-            let name = String::from_utf8(entry.header().identifier().to_vec())
-                .map_err(mlua::Error::runtime)?;
-            if !name.starts_with("data.tar") {
-                continue;
-            }
-
-            if name.ends_with("gz") {
-                tar::Archive::new(GzDecoder::new(entry)).unpack(base)?;
-            } else if name.ends_with("xz") {
-                tar::Archive::new(XzDecoder::new(entry)).unpack(base)?;
-            } else {
-                return Err(mlua::Error::runtime("could not deturmine compression type"));
-            }
-            break;
-        }
-        self.spec.files.push(File {
-            src: base.to_string_lossy().to_string(),
-            dst: "/".to_string(),
-            mode: None,
-            dir: None,
-        });
+        self.import(&DebFile::open(source)?, &[])?;
         Ok(())
     }
 
+    /// Copy the entries of `deb` matching any of the glob `patterns` into the package, along
+    /// with the conffiles among them. Entries already in the package at the same path are
+    /// replaced. Returns the number of entries added.
+    pub(crate) fn import(&mut self, deb: &DebFile, patterns: &[String]) -> io::Result<usize> {
+        let name = deb
+            .path
+            .file_stem()
+            .ok_or(to_io_err("failed to get debian package filename"))?;
+        let files = import(deb, patterns, &self.cache_dir().join("debs").join(name))?;
+        Ok(add_imported(&mut self.spec, deb, files))
+    }
+
+    /// Add the dependencies of `deb` that the package does not have yet, keeping Pre-Depends
+    /// apart from Depends. Returns the number added.
+    pub(crate) fn inherit_deps(&mut self, deb: &DebFile) -> usize {
+        inherit_deps(&mut self.spec, deb)
+    }
+
     fn download_binary(
         &mut self,
         url: String,
@@ -422,6 +409,107 @@ impl Project {
     }
 }
 
+/// Unpack `deb` into `dir` and list the entries matching any of the glob `patterns` as files
+/// that keep their modes, owners and symlinks. A pattern that matches a directory also selects
+/// everything inside it and no patterns selects the whole package.
+fn import(deb: &DebFile, patterns: &[String], dir: &Path) -> io::Result<Vec<File>> {
+    let patterns = patterns
+        .iter()
+        .map(|p| {
+            glob::Pattern::new(&install_path(Path::new(p)))
+                .map_err(|e| to_io_err(format!("invalid pattern {:?}: {}", p, e)))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let opts = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let matches = |path: &str| {
+        patterns.is_empty()
+            || Path::new(path)
+                .ancestors()
+                .any(|p| patterns.iter().any(|pat| pat.matches_path_with(p, opts)))
+    };
+
+    _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: failed to create dir {:?}", e, dir)))?;
+    deb.unpack(dir)?;
+
+    // dpkg reads a missing owner as root.
+    let name = |name: &str| (name != "root").then(|| name.to_string());
+    let mut files = Vec::new();
+    for entry in deb.entries.iter().filter(|e| matches(&e.path)) {
+        let file = match (entry.kind.as_str(), &entry.target) {
+            ("symlink", Some(target)) => File::symlink(entry.path.as_str(), target),
+            ("dir", _) => File {
+                mode: Some(entry.mode),
+                ..File::new("", entry.path.as_str())
+            },
+            _ => File::from_paths(
+                dir.join(entry.path.trim_start_matches('/')),
+                PathBuf::from(&entry.path),
+                entry.mode,
+            )?,
+        };
+        files.push(File {
+            owner: name(&entry.owner),
+            group: name(&entry.group),
+            ..file
+        });
+    }
+    Ok(files)
+}
+
+/// Add `files` imported from `deb` to `spec`, replacing the files at the same paths, and mark
+/// the ones that were conffiles in `deb` as conffiles. Returns the number of new paths.
+fn add_imported(spec: &mut BuildSpec, deb: &DebFile, files: Vec<File>) -> usize {
+    let conffiles = deb.conffiles();
+    let mut added = 0;
+    for file in files {
+        if conffiles.contains(&file.dst) {
+            let list = spec.conffiles.get_or_insert_with(Vec::new);
+            if !list.contains(&file.dst) {
+                list.push(file.dst.clone());
+            }
+        }
+        match spec.files.iter_mut().find(|f| f.dst == file.dst) {
+            Some(existing) => *existing = file,
+            None => {
+                spec.files.push(file);
+                added += 1;
+            }
+        }
+    }
+    added
+}
+
+/// Add the relationships of `deb` that `spec` does not have yet. Returns the number added.
+fn inherit_deps(spec: &mut BuildSpec, deb: &DebFile) -> usize {
+    fn merge(into: &mut Vec<String>, from: Option<Vec<String>>) -> usize {
+        let mut n = 0;
+        for dep in from.unwrap_or_default() {
+            if !into.contains(&dep) {
+                into.push(dep);
+                n += 1;
+            }
+        }
+        n
+    }
+    merge(
+        spec.pre_depends.get_or_insert_with(Vec::new),
+        deb.control.get_list("Pre-Depends"),
+    ) + merge(&mut spec.dependencies, deb.control.get_list("Depends"))
+        + merge(
+            spec.recommends.get_or_insert_with(Vec::new),
+            deb.control.get_list("Recommends"),
+        )
+        + merge(
+            spec.suggests.get_or_insert_with(Vec::new),
+            deb.control.get_list("Suggests"),
+        )
+}
+
 fn get_build(path: &PathBuf, f: &mut fs::File) -> io::Result<u32> {
     if !path.exists() {
         let mut newf = fs::File::options().write(true).create(true).open(&path)?;
@@ -434,3 +522,121 @@ fn get_build(path: &PathBuf, f: &mut fs::File) -> io::Result<u32> {
         .parse()
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{add_imported, import, inherit_deps};
+    use crate::build::tests::{test_spec, write_test_deb};
+    use crate::build::{BuildSpec, File};
+    use crate::deb::Compression;
+
+    fn spec(package: &str, files: Vec<File>) -> BuildSpec {
+        BuildSpec {
+            compression: Compression::Zstd,
+            ..test_spec(package, "1.0.0", files)
+        }
+    }
+
+    #[test]
+    fn import_deb() {
+        let dir = tempfile::tempdir().unwrap();
+        let run = dir.path().join("run");
+        let readme = dir.path().join("README");
+        std::fs::write(&run, "#!/bin/sh\n").unwrap();
+        std::fs::write(&readme, "docs\n").unwrap();
+        let owned = |file: File| File {
+            owner: Some("app".to_string()),
+            group: Some("app".to_string()),
+            ..file
+        };
+        let source = spec(
+            "app",
+            vec![
+                owned(File {
+                    mode: Some(0o750),
+                    ..File::new("", "/opt/app")
+                }),
+                owned(File {
+                    mode: Some(0o4755),
+                    ..File::new(run.to_str().unwrap(), "/opt/app/run")
+                }),
+                File::symlink("/usr/bin/app", "../../opt/app/run"),
+                File::new(readme.to_str().unwrap(), "/usr/share/doc/app/README"),
+            ],
+        );
+        let deb = write_test_deb(source, dir.path());
+        assert_eq!(deb.data_member, "data.tar.zst");
+
+        let patterns = ["/opt/app".to_string(), "usr/bin/*".to_string()];
+        let mut files = import(&deb, &patterns, &dir.path().join("unpacked")).unwrap();
+        let dsts: Vec<_> = files.iter().map(|f| f.dst.as_str()).collect();
+        assert_eq!(dsts, ["/opt/app", "/opt/app/run", "/usr/bin/app"]);
+        assert_eq!(files[2].link.as_deref(), Some("../../opt/app/run"));
+        assert!(import(&deb, &["[".to_string()], &dir.path().join("bad")).is_err());
+
+        files.sort_by(|a, b| a.dst.cmp(&b.dst));
+        let deb = write_test_deb(spec("copy", files), dir.path());
+        let paths: Vec<_> = deb.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/opt",
+                "/opt/app",
+                "/opt/app/run",
+                "/usr",
+                "/usr/bin",
+                "/usr/bin/app"
+            ]
+        );
+        let app = deb.entry("/opt/app").unwrap();
+        assert_eq!((app.kind.as_str(), app.mode), ("dir", 0o750));
+        assert_eq!(app.owner, "app");
+        let run = deb.entry("/opt/app/run").unwrap();
+        assert_eq!((run.mode, run.owner.as_str()), (0o4755, "app"));
+        let link = deb.entry("/usr/bin/app").unwrap();
+        assert_eq!(link.kind, "symlink");
+        assert_eq!(link.target.as_deref(), Some("../../opt/app/run"));
+    }
+
+    #[test]
+    fn import_twice() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf = dir.path().join("conf");
+        std::fs::write(&conf, "key = value\n").unwrap();
+        let source = BuildSpec {
+            conffiles: Some(vec!["/opt/app/app.conf".to_string()]),
+            pre_depends: Some(vec!["init-system-helpers".to_string()]),
+            dependencies: vec!["libc6".to_string()],
+            ..spec(
+                "app",
+                vec![
+                    File::new(conf.to_str().unwrap(), "/etc/app.conf"),
+                    File::new(conf.to_str().unwrap(), "/opt/app/app.conf"),
+                ],
+            )
+        };
+        let deb = write_test_deb(source, dir.path());
+        assert_eq!(deb.conffiles(), ["/etc/app.conf", "/opt/app/app.conf"]);
+
+        let mut copy = spec("copy", vec![]);
+        let unpacked = dir.path().join("unpacked");
+        let files = import(&deb, &[], &unpacked).unwrap();
+        assert_eq!(add_imported(&mut copy, &deb, files), 5);
+        let files = import(&deb, &[], &unpacked).unwrap();
+        assert_eq!(add_imported(&mut copy, &deb, files), 0);
+        assert_eq!(copy.files.len(), 5);
+        assert_eq!(inherit_deps(&mut copy, &deb), 2);
+        assert_eq!(inherit_deps(&mut copy, &deb), 0);
+        assert_eq!(
+            copy.pre_depends,
+            Some(vec!["init-system-helpers".to_string()])
+        );
+        assert_eq!(copy.dependencies, ["libc6"]);
+
+        copy.files.sort_by(|a, b| a.dst.cmp(&b.dst));
+        let deb = write_test_deb(copy, dir.path());
+        assert_eq!(deb.conffiles(), ["/etc/app.conf", "/opt/app/app.conf"]);
+        assert_eq!(deb.field("Pre-Depends"), Some("init-system-helpers"));
+        assert_eq!(deb.field("Depends"), Some("libc6"));
+    }
+}
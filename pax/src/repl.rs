@@ -0,0 +1,666 @@
+//! `pax repl`, an interactive prompt for trying out the pax Lua API.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use mlua::{Lua, MultiValue, Value, Variadic};
+
+use crate::{deb, debfile, error, modules, project, util, PaxConfig};
+
+const PROMPT: &str = "pax> ";
+const CONTINUE_PROMPT: &str = "...> ";
+
+/// Number of lines kept in the history file.
+const HISTORY_SIZE: usize = 1000;
+
+static HELP: &str = "\
+Enter Lua statements or expressions, expressions are printed.
+  :load [file]  run a pax.lua file, default is the --config file
+  :help         show this message
+  :quit         leave the repl, as does ctrl-d
+";
+
+/// Run the repl on a Lua state set up by `Cli::setup`. `config` is the file run by `:load`
+/// without an argument.
+pub(crate) fn run(lua: &Lua, config: &str, verbose: bool) -> mlua::Result<()> {
+    let require: mlua::Function = lua.globals().get("require")?;
+    lua.globals().set("pax", require.call::<_, Value>("pax")?)?;
+    let types = Types::new();
+    let mut editor = Editor::new(history_file());
+    if editor.tty {
+        println!("pax repl, :help for help and ctrl-d to quit");
+    }
+    let mut chunk = String::new();
+    loop {
+        let prompt = if chunk.is_empty() {
+            PROMPT
+        } else {
+            CONTINUE_PROMPT
+        };
+        let line = match editor.read_line(prompt, |line| complete(lua, &types, line))? {
+            Input::Line(line) => line,
+            Input::Interrupted => {
+                chunk.clear();
+                continue;
+            }
+            Input::Eof => break,
+        };
+        if chunk.is_empty() {
+            if let Some(cmd) = line.trim().strip_prefix(':') {
+                match command(lua, cmd, config) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => error::report(&e, verbose),
+                }
+                continue;
+            }
+        }
+        chunk.push_str(&line);
+        chunk.push('\n');
+        match eval(lua, &chunk) {
+            Err(mlua::Error::SyntaxError {
+                incomplete_input: true,
+                ..
+            }) => continue,
+            Err(e) => error::report(&e, verbose),
+            Ok(values) => {
+                for v in values {
+                    util::print_function(lua, Variadic::from_iter([v]))?;
+                }
+            }
+        }
+        chunk.clear();
+    }
+    editor.save()?;
+    Ok(())
+}
+
+/// Run a repl command, returning true when the repl should exit.
+fn command(lua: &Lua, cmd: &str, config: &str) -> mlua::Result<bool> {
+    let (name, arg) = cmd.split_once(' ').unwrap_or((cmd, ""));
+    match name {
+        "q" | "quit" | "exit" => return Ok(true),
+        "h" | "help" => print!("{}", HELP),
+        "l" | "load" => {
+            let file = match arg.trim() {
+                "" => config,
+                file => file,
+            };
+            lua.load(Path::new(file)).exec()?;
+            util::log(format!("loaded {}", file));
+        }
+        _ => {
+            return Err(mlua::Error::runtime(format!(
+                "unknown command :{}, see :help",
+                name
+            )))
+        }
+    }
+    Ok(false)
+}
+
+/// Run a chunk, trying it as an expression first so that its value can be printed.
+fn eval<'lua>(lua: &'lua Lua, chunk: &str) -> mlua::Result<MultiValue<'lua>> {
+    match lua
+        .load(format!("return {}", chunk))
+        .set_name("=repl")
+        .into_function()
+    {
+        Ok(f) => f.call(()),
+        Err(_) => lua.load(chunk).set_name("=repl").eval(),
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    let state = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(&env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("pax/repl_history"))
+}
+
+/// Names of the fields and methods registered by a userdata type, collected by passing it to
+/// the type's `add_fields` and `add_methods`.
+#[derive(Debug, Default)]
+pub(crate) struct Names(Vec<String>);
+
+impl Names {
+    pub(crate) fn of<T: mlua::UserData>() -> Self {
+        let mut names = Self::default();
+        T::add_fields(&mut names);
+        T::add_methods(&mut names);
+        names.0.sort();
+        names.0.dedup();
+        names
+    }
+
+    fn push(&mut self, name: impl AsRef<str>) {
+        self.0.push(name.as_ref().to_string());
+    }
+}
+
+macro_rules! record {
+    ($($name:ident<$($t:ident),*>($arg:ident)),* $(,)?) => {$(
+        fn $name<$($t),*>(&mut self, name: impl AsRef<str>, _: $arg) {
+            self.push(name);
+        }
+    )*};
+}
+
+// Metamethods are left out, they are not something to complete.
+macro_rules! skip {
+    ($($name:ident<$($t:ident),*>($arg:ident)),* $(,)?) => {$(
+        fn $name<$($t),*>(&mut self, _: impl AsRef<str>, _: $arg) {}
+    )*};
+}
+
+impl<'lua, T> mlua::UserDataFields<'lua, T> for Names {
+    record! {
+        add_field<V>(V),
+        add_field_method_get<M, R>(M),
+        add_field_method_set<M, A>(M),
+        add_field_function_get<F, R>(F),
+        add_field_function_set<F, A>(F),
+    }
+    skip! {
+        add_meta_field<V>(V),
+        add_meta_field_with<F, R>(F),
+    }
+}
+
+impl<'lua, T> mlua::UserDataMethods<'lua, T> for Names {
+    record! {
+        add_method<M, A, R>(M),
+        add_method_mut<M, A, R>(M),
+        add_function<F, A, R>(F),
+        add_function_mut<F, A, R>(F),
+        add_async_function<F, A, FR, R>(F),
+    }
+    skip! {
+        add_meta_method<M, A, R>(M),
+        add_meta_method_mut<M, A, R>(M),
+        add_meta_function<F, A, R>(F),
+        add_meta_function_mut<F, A, R>(F),
+        add_async_meta_function<F, A, FR, R>(F),
+    }
+
+    fn add_async_method<'s, M, A, MR, R>(&mut self, name: impl AsRef<str>, _: M)
+    where
+        'lua: 's,
+    {
+        self.push(name);
+    }
+
+    fn add_async_method_mut<'s, M, A, MR, R>(&mut self, name: impl AsRef<str>, _: M)
+    where
+        'lua: 's,
+    {
+        self.push(name);
+    }
+
+    fn add_async_meta_method<'s, M, A, MR, R>(&mut self, _: impl AsRef<str>, _: M)
+    where
+        'lua: 's,
+    {
+    }
+
+    fn add_async_meta_method_mut<'s, M, A, MR, R>(&mut self, _: impl AsRef<str>, _: M)
+    where
+        'lua: 's,
+    {
+    }
+}
+
+/// The fields and methods of the userdata types reachable from `require("pax")`, by the
+/// `__name` Lua sees them under.
+struct Types(BTreeMap<&'static str, Names>);
+
+impl Types {
+    fn new() -> Self {
+        fn entry<T: mlua::UserData>() -> (&'static str, Names) {
+            (short_name(std::any::type_name::<T>()), Names::of::<T>())
+        }
+        Self(BTreeMap::from([
+            entry::<PaxConfig>(),
+            entry::<project::Project>(),
+            entry::<modules::GitSubModule>(),
+            entry::<modules::CargoModule>(),
+            entry::<modules::GoModule>(),
+            entry::<modules::DlModule>(),
+            entry::<modules::PathMod>(),
+            entry::<modules::FSMod>(),
+            entry::<modules::OsMod>(),
+            entry::<modules::ControlMod>(),
+            entry::<modules::DebMod>(),
+            entry::<debfile::Deb>(),
+            entry::<deb::Urgency>(),
+            entry::<deb::Priority>(),
+            entry::<deb::Compression>(),
+        ]))
+    }
+
+    fn names(&self, value: &Value) -> Vec<String> {
+        match value {
+            Value::Table(t) => t
+                .clone()
+                .pairs::<Value, Value>()
+                .filter_map(|pair| match pair {
+                    Ok((Value::String(k), _)) => k.to_str().ok().map(String::from),
+                    _ => None,
+                })
+                .collect(),
+            Value::UserData(ud) => ud
+                .get_metatable()
+                .and_then(|mt| mt.get::<String>("__name"))
+                .ok()
+                .and_then(|name| self.0.get(short_name(&name)))
+                .map(|names| names.0.clone())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The type name without its path or the wrappers around it, as in `Rc<RefCell<PaxConfig>>`.
+fn short_name(name: &str) -> &str {
+    let name = name.trim_end_matches('>');
+    name.rsplit(['<', ':']).next().unwrap_or(name)
+}
+
+/// Completions for the end of `line`: the byte offset where the completed word starts, and the
+/// words it can be completed to.
+fn complete(lua: &Lua, types: &Types, line: &str) -> (usize, Vec<String>) {
+    let start = line
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.' || c == ':'))
+        .map_or(0, |i| i + 1);
+    let expr = &line[start..];
+    let (value, word) = match expr.rfind(['.', ':']) {
+        None => (Value::Table(lua.globals()), expr),
+        Some(i) => {
+            let path = &expr[..i];
+            let valid = !path.is_empty()
+                && path.split('.').all(|part| {
+                    part.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                        && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+                });
+            if !valid {
+                return (line.len(), Vec::new());
+            }
+            let value = lua
+                .load(format!("return {}", path))
+                .eval::<Value>()
+                .unwrap_or(Value::Nil);
+            (value, &expr[i + 1..])
+        }
+    };
+    let mut names: Vec<String> = types
+        .names(&value)
+        .into_iter()
+        .filter(|name| name.starts_with(word))
+        .collect();
+    names.sort();
+    names.dedup();
+    (line.len() - word.len(), names)
+}
+
+enum Input {
+    Line(String),
+    /// Ctrl-C, the current input is dropped.
+    Interrupted,
+    /// Ctrl-D on an empty line, or the end of stdin.
+    Eof,
+}
+
+/// A small line editor with history and tab completion. When stdin is not a terminal lines are
+/// read as they are, without a prompt.
+struct Editor {
+    tty: bool,
+    history: Vec<String>,
+    file: Option<PathBuf>,
+}
+
+impl Editor {
+    fn new(file: Option<PathBuf>) -> Self {
+        let history = file
+            .as_ref()
+            .and_then(|f| fs::read_to_string(f).ok())
+            .map(|s| s.lines().map(String::from).collect())
+            .unwrap_or_default();
+        Self {
+            tty: io::stdin().is_terminal() && io::stdout().is_terminal(),
+            history,
+            file,
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if !self.tty {
+            return Ok(());
+        }
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let skip = self.history.len().saturating_sub(HISTORY_SIZE);
+        let mut text = self.history[skip..].join("\n");
+        text.push('\n');
+        fs::write(file, text)
+    }
+
+    fn read_line<F>(&mut self, prompt: &str, complete: F) -> io::Result<Input>
+    where
+        F: Fn(&str) -> (usize, Vec<String>),
+    {
+        if !self.tty {
+            let mut line = String::new();
+            return Ok(match io::stdin().lock().read_line(&mut line)? {
+                0 => Input::Eof,
+                _ => Input::Line(line.trim_end_matches(['\n', '\r']).to_string()),
+            });
+        }
+        let input = {
+            let _raw = RawMode::enable()?;
+            self.edit(prompt, complete)?
+        };
+        if let Input::Line(ref line) = input {
+            if !line.trim().is_empty() && self.history.last() != Some(line) {
+                self.history.push(line.clone());
+            }
+        }
+        Ok(input)
+    }
+
+    fn edit<F>(&self, prompt: &str, complete: F) -> io::Result<Input>
+    where
+        F: Fn(&str) -> (usize, Vec<String>),
+    {
+        let mut stdin = io::stdin().lock();
+        let mut out = io::stdout().lock();
+        let mut buf: Vec<char> = Vec::new();
+        let mut pos = 0;
+        // Position in the history, and the line being edited before moving into it.
+        let mut index = self.history.len();
+        let mut saved = Vec::new();
+        refresh(&mut out, prompt, &buf, pos)?;
+        loop {
+            let key = read_key(&mut stdin)?;
+            match key {
+                Key::Enter => {
+                    write!(out, "\r\n")?;
+                    return Ok(Input::Line(buf.iter().collect()));
+                }
+                Key::Ctrl('c') => {
+                    write!(out, "^C\r\n")?;
+                    return Ok(Input::Interrupted);
+                }
+                Key::Ctrl('d') if buf.is_empty() => {
+                    write!(out, "\r\n")?;
+                    return Ok(Input::Eof);
+                }
+                Key::Ctrl('d') | Key::Delete if pos < buf.len() => {
+                    buf.remove(pos);
+                }
+                Key::Backspace | Key::Ctrl('h') if pos > 0 => {
+                    pos -= 1;
+                    buf.remove(pos);
+                }
+                Key::Left | Key::Ctrl('b') => pos = pos.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => pos = (pos + 1).min(buf.len()),
+                Key::Home | Key::Ctrl('a') => pos = 0,
+                Key::End | Key::Ctrl('e') => pos = buf.len(),
+                Key::Ctrl('k') => buf.truncate(pos),
+                Key::Ctrl('u') => {
+                    buf.drain(..pos);
+                    pos = 0;
+                }
+                Key::Ctrl('w') => {
+                    let mut start = pos;
+                    while start > 0 && buf[start - 1] == ' ' {
+                        start -= 1;
+                    }
+                    while start > 0 && buf[start - 1] != ' ' {
+                        start -= 1;
+                    }
+                    buf.drain(start..pos);
+                    pos = start;
+                }
+                Key::Ctrl('l') => write!(out, "\x1b[H\x1b[2J")?,
+                Key::Up | Key::Ctrl('p') if index > 0 => {
+                    if index == self.history.len() {
+                        saved = buf.clone();
+                    }
+                    index -= 1;
+                    buf = self.history[index].chars().collect();
+                    pos = buf.len();
+                }
+                Key::Down | Key::Ctrl('n') if index < self.history.len() => {
+                    index += 1;
+                    buf = match self.history.get(index) {
+                        Some(line) => line.chars().collect(),
+                        None => saved.clone(),
+                    };
+                    pos = buf.len();
+                }
+                Key::Tab => {
+                    let before: String = buf[..pos].iter().collect();
+                    let (start, names) = complete(&before);
+                    let word_len = before[start..].chars().count();
+                    let common = common_prefix(&names);
+                    if common.chars().count() > word_len {
+                        let rest: Vec<char> = common.chars().skip(word_len).collect();
+                        let n = rest.len();
+                        buf.splice(pos..pos, rest);
+                        pos += n;
+                    } else if names.len() > 1 {
+                        write!(out, "\r\n{}\r\n", names.join("  "))?;
+                    } else {
+                        write!(out, "\x07")?;
+                    }
+                }
+                Key::Char(c) => {
+                    buf.insert(pos, c);
+                    pos += 1;
+                }
+                _ => {}
+            }
+            refresh(&mut out, prompt, &buf, pos)?;
+        }
+    }
+}
+
+fn common_prefix(names: &[String]) -> &str {
+    let Some(first) = names.first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for name in &names[1..] {
+        len = first
+            .char_indices()
+            .zip(name.chars())
+            .take_while(|((i, a), b)| *i < len && a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0);
+    }
+    &first[..len]
+}
+
+/// Redraw the prompt and line, leaving the cursor at `pos`.
+fn refresh<W: Write>(out: &mut W, prompt: &str, buf: &[char], pos: usize) -> io::Result<()> {
+    let line: String = buf.iter().collect();
+    write!(out, "\r{}{}\x1b[K\r", prompt, line)?;
+    let col = prompt.chars().count() + pos;
+    if col > 0 {
+        write!(out, "\x1b[{}C", col)?;
+    }
+    out.flush()
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Unknown,
+}
+
+fn read_byte<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0u8];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_key<R: Read>(r: &mut R) -> io::Result<Key> {
+    let b = read_byte(r)?;
+    Ok(match b {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        127 => Key::Backspace,
+        0x1b => match read_byte(r)? {
+            b'[' | b'O' => match read_byte(r)? {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Right,
+                b'D' => Key::Left,
+                b'H' => Key::Home,
+                b'F' => Key::End,
+                n @ b'0'..=b'9' => {
+                    // Sequences like ESC [ 3 ~ end at the '~'.
+                    let mut last = read_byte(r)?;
+                    while last.is_ascii_digit() || last == b';' {
+                        last = read_byte(r)?;
+                    }
+                    match n {
+                        b'1' | b'7' => Key::Home,
+                        b'4' | b'8' => Key::End,
+                        b'3' => Key::Delete,
+                        _ => Key::Unknown,
+                    }
+                }
+                _ => Key::Unknown,
+            },
+            _ => Key::Unknown,
+        },
+        1..=26 => Key::Ctrl((b'a' + b - 1) as char),
+        0..=31 => Key::Unknown,
+        _ => {
+            // The remaining bytes of a UTF-8 character.
+            let len = match b {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![b];
+            for _ in 1..len {
+                bytes.push(read_byte(r)?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            }
+        }
+    })
+}
+
+/// Puts the terminal in raw mode until dropped.
+struct RawMode {
+    orig: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        let fd = io::stdin().as_raw_fd();
+        // SAFETY: termios is plain data filled in by tcgetattr.
+        let mut orig: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut orig) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = orig;
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { orig })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(io::stdin().as_raw_fd(), libc::TCSAFLUSH, &self.orig) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{common_prefix, complete, read_key, Key, Names, Types};
+
+    #[test]
+    fn completion() {
+        let lua = mlua::Lua::new();
+        let pax = std::rc::Rc::new(std::cell::RefCell::new(crate::PaxConfig::default()));
+        lua.globals().set("pax", pax).unwrap();
+        lua.load("things = { alpha = 1, alps = 2, beta = 3 }")
+            .exec()
+            .unwrap();
+        let types = Types::new();
+        let names = |line: &str| complete(&lua, &types, line).1;
+
+        assert!(Names::of::<crate::PaxConfig>()
+            .0
+            .contains(&"package".to_string()));
+        assert_eq!(names("x = things.al"), vec!["alpha", "alps"]);
+        assert_eq!(complete(&lua, &types, "x = things.al").0, 11);
+        assert_eq!(names("pax.gi"), vec!["git"]);
+        assert_eq!(names("pax.git.em"), vec!["email"]);
+        assert_eq!(names("pax:package_a"), vec!["package_all"]);
+        assert_eq!(names("pax.Urgency.Lo"), vec!["Low"]);
+        assert_eq!(names("thin"), vec!["things"]);
+        assert!(names("(x).y").is_empty());
+        assert!(names("nothing.y").is_empty());
+
+        let names = ["alpha".to_string(), "alps".to_string()];
+        assert_eq!(common_prefix(&names), "alp");
+        assert_eq!(common_prefix(&[]), "");
+
+        let mut keys: &[u8] = b"a\x1b[A\x1b[3~\x01\x7f\xc3\xa9\r";
+        let mut read = Vec::new();
+        for _ in 0..7 {
+            read.push(read_key(&mut keys).unwrap());
+        }
+        assert_eq!(
+            read,
+            vec![
+                Key::Char('a'),
+                Key::Up,
+                Key::Delete,
+                Key::Ctrl('a'),
+                Key::Backspace,
+                Key::Char('é'),
+                Key::Enter
+            ]
+        );
+    }
+}
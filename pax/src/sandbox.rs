@@ -0,0 +1,400 @@
+//! Restrictions for pax.lua files that are not trusted, turned on with --sandbox. Commands
+//! cannot be run, files can only be written inside the project and the network can only reach
+//! the allowed hosts. Each of these can be opened up again from the command line.
+
+use std::{
+    cell::RefCell,
+    env, fmt, fs,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use mlua::{Function, Lua, MultiValue, Table, Value};
+
+use crate::error::Error;
+
+/// Directory of the cache, relative to the working directory, that is always writable.
+static CACHE_DIR: &str = ".pax";
+
+thread_local! {
+    // Per thread, like the dry-run flag, so that it only covers the config being run. Threads
+    // that build packages for it are handed the policy by `build::build_all`.
+    static POLICY: RefCell<Option<Policy>> = const { RefCell::new(None) };
+}
+
+/// Something a pax.lua file needs to be allowed to do by the sandbox.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Capability {
+    Exec,
+    Write,
+    Net,
+}
+
+impl Capability {
+    /// The command line flag that grants the capability.
+    fn flag(&self) -> &'static str {
+        match self {
+            Self::Exec => "--allow-exec",
+            Self::Write => "--allow-write",
+            Self::Net => "--allow-host",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Exec => "exec",
+            Self::Write => "fs-write",
+            Self::Net => "net",
+        })
+    }
+}
+
+/// A call the sandbox did not allow.
+#[derive(Debug)]
+pub(crate) struct Denied {
+    pub call: String,
+    pub capability: Capability,
+    /// The path or host the call was for.
+    pub target: Option<String>,
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sandbox blocked {}: it needs the {} capability",
+            self.call, self.capability
+        )?;
+        if let Some(target) = &self.target {
+            write!(f, " for {}", target)?;
+        }
+        write!(f, " (allow it with {}", self.capability.flag())?;
+        match (self.capability, &self.target) {
+            (Capability::Exec, _) | (_, None) => write!(f, ")"),
+            (_, Some(target)) => write!(f, " {})", target),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Policy {
+    /// Whether commands can be run.
+    pub exec: bool,
+    /// Directories that files can be written in.
+    pub write: Vec<PathBuf>,
+    /// Hosts that can be downloaded from. "*.example.com" allows every subdomain of
+    /// example.com.
+    pub hosts: Vec<String>,
+}
+
+impl Policy {
+    /// A policy allowing writes to the project directory and the .pax cache, along with the
+    /// given directories.
+    pub(crate) fn new<P: AsRef<Path>>(project: P, write: &[String], hosts: &[String]) -> Self {
+        let mut dirs = vec![project.as_ref().to_path_buf(), PathBuf::from(CACHE_DIR)];
+        dirs.extend(write.iter().map(PathBuf::from));
+        Self {
+            exec: false,
+            write: dirs.iter().map(resolve).collect(),
+            hosts: hosts.to_vec(),
+        }
+    }
+
+    fn check_exec(&self, call: &str) -> Result<(), Error> {
+        match self.exec {
+            true => Ok(()),
+            false => Err(deny(call, Capability::Exec, None)),
+        }
+    }
+
+    fn check_write(&self, call: &str, path: &Path) -> Result<(), Error> {
+        let path = resolve(path);
+        match self.write.iter().any(|dir| path.starts_with(dir)) {
+            true => Ok(()),
+            false => Err(deny(
+                call,
+                Capability::Write,
+                Some(path.display().to_string()),
+            )),
+        }
+    }
+
+    fn check_host(&self, call: &str, host: &str) -> Result<(), Error> {
+        let host = host.to_ascii_lowercase();
+        let allowed = self.hosts.iter().any(|h| match h.strip_prefix("*.") {
+            Some(domain) => host.ends_with(&format!(".{}", domain)),
+            None => h.eq_ignore_ascii_case(&host),
+        });
+        match allowed {
+            true => Ok(()),
+            false => Err(deny(call, Capability::Net, Some(host))),
+        }
+    }
+}
+
+fn deny(call: &str, capability: Capability, target: Option<String>) -> Error {
+    Error::Denied(Denied {
+        call: call.to_string(),
+        capability,
+        target,
+    })
+}
+
+/// An absolute path with `.` and `..` taken out and the part that exists resolved through
+/// symlinks, so that a path cannot leave an allowed directory by way of a link.
+fn resolve<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = env::current_dir().unwrap_or_default().join(path);
+    let mut normal = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            c => normal.push(c),
+        }
+    }
+    let mut existing = normal.as_path();
+    let mut rest = Vec::new();
+    while fs::symlink_metadata(existing).is_err() {
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => break,
+        }
+    }
+    let mut out = fs::canonicalize(existing).unwrap_or_else(|_| existing.to_path_buf());
+    out.extend(rest.iter().rev());
+    out
+}
+
+/// Turn the sandbox on, or off with `None`.
+pub(crate) fn enable(policy: Option<Policy>) {
+    POLICY.set(policy);
+}
+
+/// The policy in force on this thread.
+pub(crate) fn policy() -> Option<Policy> {
+    POLICY.with_borrow(Clone::clone)
+}
+
+/// Fails when the sandbox does not allow running commands.
+pub(crate) fn check_exec(call: &str) -> Result<(), Error> {
+    POLICY.with_borrow(|policy| match policy {
+        Some(policy) => policy.check_exec(call),
+        None => Ok(()),
+    })
+}
+
+/// Fails when the sandbox does not allow writing to `path`.
+pub(crate) fn check_write<P: AsRef<Path>>(call: &str, path: P) -> Result<(), Error> {
+    POLICY.with_borrow(|policy| match policy {
+        Some(policy) => policy.check_write(call, path.as_ref()),
+        None => Ok(()),
+    })
+}
+
+/// Fails when the sandbox does not allow connecting to the host of `url`. URLs without a host,
+/// like local paths, are allowed.
+pub(crate) fn check_url(call: &str, url: &str) -> Result<(), Error> {
+    let Some(policy) = policy() else {
+        return Ok(());
+    };
+    let host = match url::Url::parse(url) {
+        Ok(u) => u.host_str().map(String::from),
+        // scp-like git urls such as git@github.com:user/repo.git
+        Err(_) => git_url_parse::GitUrl::parse(url).ok().and_then(|u| u.host),
+    };
+    match host {
+        Some(host) => policy.check_host(call, &host),
+        None => Ok(()),
+    }
+}
+
+/// Replace the parts of the Lua standard library that get around the sandbox: os.execute and
+/// io.popen run commands, and io.open, io.output, os.remove and os.rename write files.
+pub(crate) fn restrict(lua: &Lua, policy: Policy) -> mlua::Result<()> {
+    let policy = Arc::new(policy);
+    let globals = lua.globals();
+    let os: Table = globals.get("os")?;
+    let io: Table = globals.get("io")?;
+    wrap(lua, &os, "execute", &policy, |p, _| {
+        p.check_exec("os.execute")
+    })?;
+    wrap(lua, &io, "popen", &policy, |p, _| p.check_exec("io.popen"))?;
+    wrap(lua, &io, "open", &policy, |p, args| {
+        match (arg(args, 0), arg(args, 1)) {
+            (Some(path), Some(mode)) if mode.contains(['w', 'a', '+']) => {
+                p.check_write("io.open", Path::new(&path))
+            }
+            _ => Ok(()),
+        }
+    })?;
+    wrap(lua, &io, "output", &policy, |p, args| match arg(args, 0) {
+        Some(path) => p.check_write("io.output", Path::new(&path)),
+        None => Ok(()),
+    })?;
+    wrap(lua, &os, "remove", &policy, |p, args| {
+        p.check_write("os.remove", Path::new(&arg(args, 0).unwrap_or_default()))
+    })?;
+    wrap(lua, &os, "rename", &policy, |p, args| {
+        p.check_write("os.rename", Path::new(&arg(args, 0).unwrap_or_default()))?;
+        p.check_write("os.rename", Path::new(&arg(args, 1).unwrap_or_default()))
+    })
+}
+
+/// Replace `table[name]` with a function that calls `check` before the original.
+fn wrap<F>(lua: &Lua, table: &Table, name: &str, policy: &Arc<Policy>, check: F) -> mlua::Result<()>
+where
+    F: Fn(&Policy, &MultiValue) -> Result<(), Error> + 'static,
+{
+    let orig = lua.create_registry_value(table.get::<_, Function>(name)?)?;
+    let policy = policy.clone();
+    let f = lua.create_function(move |lua, args: MultiValue| {
+        check(&policy, &args).map_err(Error::to_lua)?;
+        lua.registry_value::<Function>(&orig)?
+            .call::<_, MultiValue>(args)
+    })?;
+    table.set(name, f)
+}
+
+fn arg(args: &MultiValue, i: usize) -> Option<String> {
+    match args.get(i) {
+        Some(Value::String(s)) => Some(s.to_string_lossy().to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::{enable, restrict, Policy};
+    use crate::build::build_all;
+    use crate::build::tests::test_spec;
+    use crate::crates::Cargo;
+
+    #[test]
+    fn policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        fs::create_dir_all(&project).unwrap();
+        std::os::unix::fs::symlink("/etc", project.join("etc")).unwrap();
+        let policy = Policy::new(
+            &project,
+            &[],
+            &["github.com".to_string(), "*.example.com".to_string()],
+        );
+
+        assert!(policy.check_write("w", &project.join("dist/a.deb")).is_ok());
+        let err = policy
+            .check_write("pax.fs.rm", &project.join("../outside"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("pax.fs.rm: it needs the fs-write capability"),
+            "{}",
+            err
+        );
+        assert!(policy
+            .check_write("w", &project.join("etc/passwd"))
+            .is_err());
+        assert!(policy.check_host("dl", "GitHub.com").is_ok());
+        assert!(policy.check_host("dl", "cdn.example.com").is_ok());
+        assert!(policy.check_host("dl", "example.com").is_err());
+        let err = policy.check_host("dl", "evil.com").unwrap_err().to_string();
+        assert!(
+            err.ends_with("for evil.com (allow it with --allow-host evil.com)"),
+            "{}",
+            err
+        );
+        assert!(policy.check_exec("pax.sh").is_err());
+
+        let lua = mlua::Lua::new();
+        restrict(&lua, policy).unwrap();
+        let file = project.join("out.txt");
+        lua.load(format!(
+            r#"
+            local ok, err = pcall(os.execute, "true")
+            assert(not ok and tostring(err):find("needs the exec capability"), tostring(err))
+            assert(not pcall(io.popen, "ls"))
+            assert(not pcall(io.open, "/etc/pax-sandbox-test", "w"))
+            assert(not pcall(os.remove, "/etc/pax-sandbox-test"))
+            local f = assert(io.open({:?}, "w"))
+            f:write("ok")
+            f:close()
+            assert(io.open({:?}, "r"):read("a") == "ok")
+            "#,
+            file.to_str().unwrap(),
+            file.to_str().unwrap()
+        ))
+        .exec()
+        .unwrap();
+        assert!(Path::new(&file).exists());
+    }
+
+    #[test]
+    fn builds() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        // Set up first, since that turns the sandbox off when the CLI doesn't ask for it.
+        let lua = mlua::Lua::new();
+        crate::Cli::default().setup(&lua).unwrap();
+        enable(Some(Policy::new(&project, &[], &[])));
+
+        let root = project.to_str().unwrap();
+        let err = Cargo::from_path(root).build().unwrap_err().to_string();
+        assert!(
+            err.contains("blocked cargo: it needs the exec capability"),
+            "{}",
+            err
+        );
+        let clean = Cargo {
+            clean: true,
+            target_dir: Some(outside.to_str().unwrap().to_string()),
+            ..Cargo::from_path(root)
+        };
+        let err = clean.build().unwrap_err().to_string();
+        assert!(
+            err.contains("blocked cargo clean: it needs the fs-write capability"),
+            "{}",
+            err
+        );
+        assert!(outside.exists());
+
+        let err = lua
+            .load(format!(
+                r#"require("pax").go.build({{ root = {:?} }})"#,
+                root
+            ))
+            .exec()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("blocked go: it needs the exec capability"),
+            "{}",
+            err
+        );
+
+        // The threads building packages are held to the same policy.
+        let mut specs = vec![
+            test_spec("one", "1.0", vec![]),
+            test_spec("two", "1.0", vec![]),
+        ];
+        let err = build_all(&mut specs, &outside, 2).unwrap_err().to_string();
+        assert!(err.starts_with("2 of 2 packages failed"), "{}", err);
+        assert!(
+            err.contains("blocked package: it needs the fs-write capability"),
+            "{}",
+            err
+        );
+        enable(None);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Write;
+
+/// Placeholder that user supplied maintainer scripts can use to choose where generated snippets
+/// are inserted.
+pub(crate) static PLACEHOLDER: &str = "#PAX#";
+
+static HEADER: &str = "#!/bin/sh\nset -e\n";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Script {
+    Preinst,
+    Postinst,
+    Prerm,
+    Postrm,
+}
+
+impl Script {
+    pub(crate) const ALL: [Script; 4] = [
+        Script::Preinst,
+        Script::Postinst,
+        Script::Prerm,
+        Script::Postrm,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Preinst => "preinst",
+            Self::Postinst => "postinst",
+            Self::Prerm => "prerm",
+            Self::Postrm => "postrm",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Fragment {
+    script: Script,
+    /// Values of "$1" that the fragment runs for. Empty means it always runs.
+    actions: Vec<String>,
+    /// Name of the feature that added the fragment.
+    source: String,
+    body: String,
+}
+
+/// Collects shell snippets from different features and merges them with the user's own
+/// maintainer scripts.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ScriptComposer {
+    fragments: Vec<Fragment>,
+}
+
+impl ScriptComposer {
+    /// Add a snippet to a script that only runs when "$1" is one of the actions given. An empty
+    /// list of actions will always run the snippet.
+    pub(crate) fn add(&mut self, script: Script, actions: &[&str], source: &str, body: &str) {
+        self.fragments.push(Fragment {
+            script,
+            actions: actions.iter().map(|a| a.to_string()).collect(),
+            source: source.to_string(),
+            body: body.trim_end().to_string(),
+        });
+    }
+
+    /// Generate the snippets for one script. Each snippet runs in a subshell with errexit on, and
+    /// the script exits when it fails, whatever the user's script does. The status is checked on
+    /// a line of its own because "( ... ) || exit" would turn errexit off inside the subshell.
+    pub(crate) fn generate(&self, script: Script) -> String {
+        let mut out = String::new();
+        for frag in self.fragments.iter().filter(|f| f.script == script) {
+            let mut body = String::new();
+            if frag.actions.is_empty() {
+                _ = writeln!(body, "{}", frag.body);
+            } else {
+                _ = writeln!(body, "case \"$1\" in");
+                _ = writeln!(body, "    {})", frag.actions.join("|"));
+                for line in frag.body.lines() {
+                    indent(&mut body, line, 2);
+                }
+                _ = writeln!(body, "        ;;");
+                _ = writeln!(body, "esac");
+            }
+            _ = writeln!(out, "# Automatically added by pax ({})", frag.source);
+            _ = writeln!(out, "(");
+            _ = writeln!(out, "    set -e");
+            for line in body.lines() {
+                indent(&mut out, line, 1);
+            }
+            _ = writeln!(out, ")");
+            _ = writeln!(
+                out,
+                "pax_status=$?; [ \"$pax_status\" -eq 0 ] || exit \"$pax_status\""
+            );
+            _ = writeln!(out, "# End automatically added section");
+        }
+        out
+    }
+
+    /// Merge the generated snippets into the user's script. The snippets replace the "#PAX#"
+    /// placeholder, or are inserted at the top of the script when there is no placeholder so that
+    /// an early "exit 0" can't skip them. Scripts with their own shebang are otherwise left as
+    /// written. Returns None when there is nothing to write.
+    pub(crate) fn compose(&self, script: Script, user: Option<&str>) -> Option<String> {
+        let generated = self.generate(script);
+        let user = user.map(str::trim).filter(|s| !s.is_empty());
+        let user = match user {
+            None if generated.is_empty() => return None,
+            None => return Some(format!("{}\n{}", HEADER, generated)),
+            Some(s) => s,
+        };
+        let (shebang, body) = match user.strip_prefix("#!") {
+            Some(_) => match user.split_once('\n') {
+                Some((shebang, body)) => (format!("{}\n", shebang), body),
+                None => (format!("{}\n", user), ""),
+            },
+            None => (HEADER.to_string(), user),
+        };
+        let body = if body.contains(PLACEHOLDER) {
+            body.replace(PLACEHOLDER, generated.trim_end())
+        } else if generated.is_empty() {
+            body.to_string()
+        } else {
+            format!("\n{}{}", generated, body)
+        };
+        let mut out = shebang + &body;
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        Some(out)
+    }
+}
+
+/// Quote `s` as a single shell word.
+pub(crate) fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Append `line` indented by `depth` levels, leaving empty lines empty.
+fn indent(out: &mut String, line: &str, depth: usize) {
+    if !line.is_empty() {
+        out.push_str(&"    ".repeat(depth));
+        out.push_str(line);
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, Script, ScriptComposer};
+
+    #[test]
+    fn compose() {
+        let mut c = ScriptComposer::default();
+        assert_eq!(c.compose(Script::Postinst, None), None);
+        assert_eq!(
+            c.compose(Script::Postinst, Some("echo hi")),
+            Some("#!/bin/sh\nset -e\necho hi\n".to_string())
+        );
+        c.add(
+            Script::Postinst,
+            &["configure"],
+            "test",
+            "echo one\n\necho two\n",
+        );
+        c.add(Script::Postinst, &[], "always", "echo always");
+        c.add(Script::Postrm, &["purge"], "test", "echo purge");
+        let generated = "# Automatically added by pax (test)\n\
+            (\n\
+            \x20   set -e\n\
+            \x20   case \"$1\" in\n\
+            \x20       configure)\n\
+            \x20           echo one\n\
+            \n\
+            \x20           echo two\n\
+            \x20           ;;\n\
+            \x20   esac\n\
+            )\n\
+            pax_status=$?; [ \"$pax_status\" -eq 0 ] || exit \"$pax_status\"\n\
+            # End automatically added section\n\
+            # Automatically added by pax (always)\n\
+            (\n\
+            \x20   set -e\n\
+            \x20   echo always\n\
+            )\n\
+            pax_status=$?; [ \"$pax_status\" -eq 0 ] || exit \"$pax_status\"\n\
+            # End automatically added section\n";
+        assert_eq!(
+            c.compose(Script::Postinst, None).unwrap(),
+            format!("#!/bin/sh\nset -e\n\n{}", generated)
+        );
+        assert_eq!(
+            c.compose(
+                Script::Postinst,
+                Some("#!/bin/bash\nset -eu\n#PAX#\nexit 0\n")
+            )
+            .unwrap(),
+            format!("#!/bin/bash\nset -eu\n{}exit 0\n", generated)
+        );
+        assert_eq!(
+            c.compose(Script::Postinst, Some("#!/bin/bash\nexit 0"))
+                .unwrap(),
+            format!("#!/bin/bash\n\n{}exit 0\n", generated)
+        );
+        assert_eq!(
+            c.compose(Script::Postinst, Some("#!/bin/sh -e\nexit 0"))
+                .unwrap(),
+            format!("#!/bin/sh -e\n\n{}exit 0\n", generated)
+        );
+        assert_eq!(
+            c.compose(
+                Script::Postinst,
+                Some("#!/bin/bash\nset -o errexit\n#PAX#\nset +e\n")
+            )
+            .unwrap(),
+            format!("#!/bin/bash\nset -o errexit\n{}set +e\n", generated)
+        );
+        assert_eq!(
+            c.compose(Script::Postinst, Some("#!/bin/bash\nset -u\n#PAX#"))
+                .unwrap(),
+            format!("#!/bin/bash\nset -u\n{}", generated)
+        );
+        assert_eq!(c.compose(Script::Prerm, Some("  ")), None);
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert!(c
+            .compose(Script::Postrm, None)
+            .unwrap()
+            .contains("        purge)\n            echo purge\n"));
+    }
+}
@@ -0,0 +1,315 @@
+use std::{fmt::Write as _, fs, io, path::Path};
+
+use serde_json::Value;
+
+use crate::build::BuildSpec;
+use crate::util::to_io_err;
+
+/// Keys written first when converting a spec, the rest follow in alphabetical order.
+static LEADING_KEYS: &[&str] = &["package", "name", "version", "arch", "description"];
+
+static LUA_KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Packages can be described in TOML or JSON instead of Lua. A file holds either one package or
+/// a list of them under "packages".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(Self::Toml),
+            Some("json") => Ok(Self::Json),
+            _ => Err(to_io_err(format!(
+                "{:?} is not a supported spec file, expected a .toml or .json file",
+                path
+            ))),
+        }
+    }
+}
+
+/// Read every package in a spec file.
+pub(crate) fn load<P: AsRef<Path>>(path: P) -> io::Result<Vec<BuildSpec>> {
+    let path = path.as_ref();
+    let text = read(path)?;
+    parse(&text, Format::from_path(path)?)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+/// Write a spec file as the equivalent pax.lua.
+pub(crate) fn convert<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let path = path.as_ref();
+    let text = read(path)?;
+    to_lua(&text, Format::from_path(path)?, &path.to_string_lossy())
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+fn read(path: &Path) -> io::Result<String> {
+    crate::watch::track(path);
+    fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("{}: could not read spec file {:?}", e, path),
+        )
+    })
+}
+
+pub(crate) fn parse(text: &str, format: Format) -> io::Result<Vec<BuildSpec>> {
+    let value = to_value(text, format)?;
+    packages(&value)?
+        .into_iter()
+        .map(|v| serde_json::from_value(v.clone()).map_err(to_io_err))
+        .collect()
+}
+
+fn to_value(text: &str, format: Format) -> io::Result<Value> {
+    match format {
+        Format::Json => serde_json::from_str(text).map_err(to_io_err),
+        Format::Toml => {
+            let value: toml::Value = toml::from_str(text).map_err(to_io_err)?;
+            serde_json::to_value(value).map_err(to_io_err)
+        }
+    }
+}
+
+fn packages(value: &Value) -> io::Result<Vec<&Value>> {
+    match value.get("packages") {
+        None => Ok(vec![value]),
+        Some(Value::Array(list)) => Ok(list.iter().collect()),
+        Some(_) => Err(to_io_err("\"packages\" must be a list of packages")),
+    }
+}
+
+/// Convert a spec file into a pax.lua script. The spec is validated first, and shorthand forms
+/// such as "src:dst" files or a scripts directory are kept as they were written.
+pub(crate) fn to_lua(text: &str, format: Format, source: &str) -> io::Result<String> {
+    let value = to_value(text, format)?;
+    let specs = parse(text, format)?;
+    let mut out = format!(
+        "-- Converted from {} by pax convert.\nlocal pax = require(\"pax\")\n\n",
+        source
+    );
+    let packages = packages(&value)?;
+    let func = if specs.len() == 1 { "package" } else { "add" };
+    for pkg in packages {
+        _ = write!(out, "pax:{}(", func);
+        write_value(&mut out, None, pkg, 0);
+        out.push_str(")\n");
+    }
+    if specs.len() != 1 {
+        out.push_str("pax:package_all()\n");
+    }
+    Ok(out)
+}
+
+fn write_value(out: &mut String, key: Option<&str>, value: &Value, indent: usize) {
+    match value {
+        Value::Null => out.push_str("nil"),
+        Value::Bool(b) => _ = write!(out, "{}", b),
+        Value::Number(n) if key == Some("mode") => match n.as_u64() {
+            Some(mode) => _ = write!(out, "pax.octal(\"{:04o}\")", mode),
+            None => _ = write!(out, "{}", n),
+        },
+        Value::Number(n) => _ = write!(out, "{}", n),
+        Value::String(s) if key == Some("mode") => {
+            _ = write!(out, "pax.octal({})", lua_string(s));
+        }
+        Value::String(s) => out.push_str(&lua_string(s)),
+        Value::Array(list) => {
+            let scalars = list
+                .iter()
+                .all(|v| !matches!(v, Value::Array(_) | Value::Object(_)));
+            if scalars {
+                let mut inline = String::new();
+                for (i, v) in list.iter().enumerate() {
+                    if i > 0 {
+                        inline.push_str(", ");
+                    }
+                    write_value(&mut inline, key, v, indent);
+                }
+                if inline.len() <= 72 {
+                    if list.is_empty() {
+                        out.push_str("{}");
+                    } else {
+                        _ = write!(out, "{{ {} }}", inline);
+                    }
+                    return;
+                }
+            }
+            out.push_str("{\n");
+            for v in list {
+                out.push_str(&"\t".repeat(indent + 1));
+                write_value(out, key, v, indent + 1);
+                out.push_str(",\n");
+            }
+            _ = write!(out, "{}}}", "\t".repeat(indent));
+        }
+        Value::Object(map) => {
+            if map.is_empty() {
+                out.push_str("{}");
+                return;
+            }
+            let mut keys: Vec<&String> = map.keys().collect();
+            if indent == 0 {
+                keys.sort_by_key(|k| {
+                    LEADING_KEYS
+                        .iter()
+                        .position(|l| l == k)
+                        .unwrap_or(usize::MAX)
+                });
+            }
+            out.push_str("{\n");
+            for k in keys {
+                out.push_str(&"\t".repeat(indent + 1));
+                if is_identifier(k) {
+                    out.push_str(k);
+                } else {
+                    _ = write!(out, "[{}]", lua_string(k));
+                }
+                out.push_str(" = ");
+                write_value(out, Some(k), &map[k], indent + 1);
+                out.push_str(",\n");
+            }
+            _ = write!(out, "{}}}", "\t".repeat(indent));
+        }
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !LUA_KEYWORDS.contains(&s)
+}
+
+fn lua_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_ascii_control() => _ = write!(out, "\\{:03}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, to_lua, Format};
+    use crate::build::BuildSpec;
+    use crate::deb::{Compression, Priority};
+
+    static TOML: &str = r#"
+package = "demo"
+version = "1.2.0"
+arch = "amd64"
+author = "Jerry"
+email = "jerry@jerry.se"
+description = "A demo\nwith a \"longer\" description"
+priority = "Required"
+compression = "zstd"
+dependencies = ["libc6"]
+files = [
+    "README.md:/usr/share/doc/demo/README.md",
+    { src = "target/release/demo", dst = "/usr/bin/demo", mode = 0o755, owner = "demo" },
+]
+users = ["demo", { name = "other", system = false }]
+services = [{ unit = "demo.service", start = false }]
+changelog = true
+
+[scripts]
+postinst = "echo installed"
+
+[[apt_sources]]
+name = "example"
+url = "https://example.com/apt"
+components = "stable main"
+"#;
+
+    #[test]
+    fn toml() {
+        let specs = parse(TOML, Format::Toml).unwrap();
+        assert_eq!(specs.len(), 1);
+        let s = &specs[0];
+        assert_eq!(s.package, "demo");
+        assert_eq!(s.priority, Priority::Required);
+        assert_eq!(s.compression, Compression::Zstd);
+        assert_eq!(s.files[0].src, "README.md");
+        assert_eq!(s.files[0].dst, "/usr/share/doc/demo/README.md");
+        assert_eq!(s.files[1].mode, Some(0o755));
+        assert_eq!(s.files[1].owner.as_deref(), Some("demo"));
+        let users = s.users.as_ref().unwrap();
+        assert!(users[0].system);
+        assert!(!users[1].system);
+        let services = s.services.as_ref().unwrap();
+        assert!(services[0].enable && !services[0].start);
+        assert!(s.changelog.is_some());
+        let apt = &s.apt_sources.as_ref().unwrap()[0];
+        assert_eq!(apt.suites, vec!["stable"]);
+        assert_eq!(apt.components, vec!["main"]);
+        assert_eq!(
+            s.scripts.as_ref().unwrap().postinst.as_deref(),
+            Some("echo installed")
+        );
+
+        let err = parse("package = \"x\"\nversoin = \"1\"", Format::Toml).unwrap_err();
+        assert!(
+            err.to_string().contains("unknown field `versoin`"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn json() {
+        let specs = parse(
+            r#"{"packages": [
+                {"package": "one", "version": "1", "files": [{"src": "a", "dst": "/a", "mode": "0644"}]},
+                {"package": "two", "version": "2"}
+            ]}"#,
+            Format::Json,
+        )
+        .unwrap();
+        assert_eq!(specs.len(), 2);
+        assert_eq!(specs[0].files[0].mode, Some(0o644));
+        assert_eq!(specs[1].package, "two");
+        assert_eq!(specs[1].arch, "all");
+    }
+
+    #[test]
+    fn convert() {
+        let lua_src = to_lua(TOML, Format::Toml, "demo.toml").unwrap();
+        assert!(lua_src.contains("pax:package({\n\tpackage = \"demo\",\n\tversion = \"1.2.0\",\n"));
+        assert!(lua_src.contains("mode = pax.octal(\"0755\")"));
+
+        // Running the generated script has to give the same spec as the spec file.
+        let lua = mlua::Lua::new();
+        lua.load(
+            r#"package.loaded.pax = {
+                octal = function(s) return tonumber(s, 8) end,
+                package = function(self, spec) result = spec end,
+            }"#,
+        )
+        .exec()
+        .unwrap();
+        lua.load(lua_src.as_str()).exec().unwrap();
+        let from_lua: BuildSpec = lua.globals().get("result").unwrap();
+        let from_toml = parse(TOML, Format::Toml).unwrap().remove(0);
+        assert_eq!(format!("{:?}", from_lua), format!("{:?}", from_toml));
+    }
+}
@@ -0,0 +1,279 @@
+use std::{io, path::Path};
+
+use crate::scripts::{quote, Script, ScriptComposer};
+use crate::util::to_io_err;
+
+/// Directory that packaged unit files are installed into.
+pub(crate) static UNIT_DIR: &str = "/lib/systemd/system";
+
+static UNIT_TYPES: &[&str] = &[
+    "service",
+    "socket",
+    "timer",
+    "path",
+    "target",
+    "mount",
+    "automount",
+];
+
+#[derive(Clone, Debug, pax_derive::IntoLua, serde::Deserialize)]
+#[serde(from = "ServiceRepr")]
+pub(crate) struct Service {
+    /// Path to the unit file. The file name is used as the unit name.
+    pub unit: String,
+    /// Enable the unit when the package is first installed.
+    pub enable: bool,
+    /// Start the unit after the package is installed.
+    pub start: bool,
+    /// Restart the unit after an upgrade instead of stopping it before the upgrade and starting
+    /// it again afterwards.
+    pub restart_on_upgrade: bool,
+}
+
+/// A unit path or a table, the same as in Lua.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum ServiceRepr {
+    Unit(String),
+    Table(ServiceTable),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceTable {
+    unit: String,
+    enable: Option<bool>,
+    start: Option<bool>,
+    restart_on_upgrade: Option<bool>,
+}
+
+impl From<ServiceRepr> for Service {
+    fn from(value: ServiceRepr) -> Self {
+        match value {
+            ServiceRepr::Unit(unit) => Self::new(unit),
+            ServiceRepr::Table(ServiceTable {
+                unit,
+                enable,
+                start,
+                restart_on_upgrade,
+            }) => Self {
+                unit,
+                enable: enable.unwrap_or(true),
+                start: start.unwrap_or(true),
+                restart_on_upgrade: restart_on_upgrade.unwrap_or(true),
+            },
+        }
+    }
+}
+
+impl mlua::FromLua<'_> for Service {
+    /// A plain string is the unit path with everything else left at its default.
+    fn from_lua(
+        value: mlua::prelude::LuaValue<'_>,
+        _lua: &'_ mlua::prelude::Lua,
+    ) -> mlua::prelude::LuaResult<Self> {
+        use mlua::Value;
+        match value {
+            Value::String(s) => Ok(Self::new(s.to_str()?)),
+            // Booleans read nil as false so the defaults have to be applied here.
+            Value::Table(t) => Ok(Self {
+                unit: t.get("unit")?,
+                enable: t.get::<_, Option<bool>>("enable")?.unwrap_or(true),
+                start: t.get::<_, Option<bool>>("start")?.unwrap_or(true),
+                restart_on_upgrade: t
+                    .get::<_, Option<bool>>("restart_on_upgrade")?
+                    .unwrap_or(true),
+            }),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: std::any::type_name::<Self>(),
+                message: None,
+            }),
+        }
+    }
+}
+
+impl Service {
+    pub(crate) fn new<S: Into<String>>(unit: S) -> Self {
+        Self {
+            unit: unit.into(),
+            enable: true,
+            start: true,
+            restart_on_upgrade: true,
+        }
+    }
+
+    /// Name of the installed unit, e.g. "myapp.service".
+    pub(crate) fn name(&self) -> io::Result<String> {
+        Path::new(&self.unit)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .ok_or_else(|| to_io_err(format!("invalid unit path {:?}", self.unit)))
+    }
+
+    /// Where the unit file is installed in the package.
+    pub(crate) fn dst(&self) -> io::Result<String> {
+        Ok(format!("{}/{}", UNIT_DIR, self.name()?))
+    }
+
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        let name = self.name()?;
+        let kind = name
+            .rsplit_once('.')
+            .map(|(_, ext)| ext)
+            .unwrap_or_default();
+        if !UNIT_TYPES.contains(&kind) {
+            return Err(to_io_err(format!(
+                "{:?} is not a supported unit type, expected one of: {}",
+                name,
+                UNIT_TYPES.join(", ")
+            )));
+        }
+        if name.contains("@.") && (self.enable || self.start) {
+            return Err(to_io_err(format!(
+                "template unit {:?} can't be enabled or started",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Add the snippets that reload systemd and enable, start, stop and clean up the unit.
+    pub(crate) fn add_scripts(&self, composer: &mut ScriptComposer) -> io::Result<()> {
+        let unit = self.name()?;
+        let source = format!("systemd {}", unit);
+        let name = quote(&unit);
+        let mut postinst = String::from(
+            "if [ -d /run/systemd/system ]; then\n    systemctl --system daemon-reload >/dev/null || true\nfi\n",
+        );
+        if self.enable {
+            postinst.push_str(&format!(
+                "if [ -z \"$2\" ]; then\n    systemctl --no-reload enable {} >/dev/null || true\nfi\n",
+                name
+            ));
+        }
+        if self.start {
+            let upgrade = if self.restart_on_upgrade {
+                "restart"
+            } else {
+                "start"
+            };
+            postinst.push_str(&format!(
+                "if [ -d /run/systemd/system ]; then\n\
+                \x20   if [ -n \"$2\" ]; then\n\
+                \x20       systemctl {} {name} || true\n\
+                \x20   else\n\
+                \x20       systemctl start {name} || true\n\
+                \x20   fi\n\
+                fi\n",
+                upgrade,
+                name = name
+            ));
+        }
+        composer.add(
+            Script::Postinst,
+            &[
+                "configure",
+                "abort-upgrade",
+                "abort-deconfigure",
+                "abort-remove",
+            ],
+            &source,
+            &postinst,
+        );
+
+        if self.start {
+            // Stopping on upgrade is only needed when the unit isn't restarted afterwards.
+            let actions: &[&str] = if self.restart_on_upgrade {
+                &["remove"]
+            } else {
+                &["remove", "upgrade", "deconfigure"]
+            };
+            composer.add(
+                Script::Prerm,
+                actions,
+                &source,
+                &format!(
+                    "if [ -d /run/systemd/system ]; then\n    systemctl stop {} || true\nfi\n",
+                    name
+                ),
+            );
+        }
+
+        composer.add(
+            Script::Postrm,
+            &["remove"],
+            &source,
+            "if [ -d /run/systemd/system ]; then\n    systemctl --system daemon-reload >/dev/null || true\nfi\n",
+        );
+        if self.enable {
+            // The unit file is already gone at this point so 'systemctl disable' can't be used.
+            composer.add(
+                Script::Postrm,
+                &["purge"],
+                &source,
+                &format!(
+                    "rm -f /etc/systemd/system/*.wants/{name} /etc/systemd/system/*.requires/{name}\n\
+                    if [ -d /run/systemd/system ]; then\n    systemctl --system daemon-reload >/dev/null || true\nfi\n",
+                    name = name
+                ),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Service;
+    use crate::scripts::{Script, ScriptComposer};
+
+    #[test]
+    fn scripts() {
+        let lua = mlua::Lua::new();
+        let services: Vec<Service> = lua
+            .load(
+                r#"{
+                    "units/myapp.socket",
+                    { unit = "units/myapp.service", enable = false, restart_on_upgrade = false },
+                    { unit = "units/cleanup.timer", start = false },
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        let mut c = ScriptComposer::default();
+        for s in &services {
+            s.validate().unwrap();
+            s.add_scripts(&mut c).unwrap();
+        }
+        assert_eq!(
+            services[1].dst().unwrap(),
+            "/lib/systemd/system/myapp.service"
+        );
+
+        let postinst = c.compose(Script::Postinst, None).unwrap();
+        assert!(postinst.contains("systemctl --no-reload enable 'myapp.socket'"));
+        assert!(postinst.contains("systemctl restart 'myapp.socket'"));
+        assert!(!postinst.contains("enable 'myapp.service'"));
+        assert!(postinst.contains("systemctl start 'myapp.service'"));
+        assert!(!postinst.contains("restart 'myapp.service'"));
+        assert!(postinst.contains("enable 'cleanup.timer'"));
+        assert!(!postinst.contains("start 'cleanup.timer'"));
+
+        let prerm = c.compose(Script::Prerm, None).unwrap();
+        assert!(prerm.contains("        remove|upgrade|deconfigure)\n            if [ -d /run/systemd/system ]; then\n                systemctl stop 'myapp.service'"));
+        assert!(!prerm.contains("cleanup.timer"));
+
+        let postrm = c.compose(Script::Postrm, None).unwrap();
+        assert!(postrm.contains("/etc/systemd/system/*.wants/'myapp.socket'"));
+        assert!(!postrm.contains("*.wants/'myapp.service'"));
+
+        let mut c = ScriptComposer::default();
+        Service::new("it's.service").add_scripts(&mut c).unwrap();
+        let postrm = c.compose(Script::Postrm, None).unwrap();
+        assert!(postrm.contains("*.wants/'it'\\''s.service'"));
+
+        assert!(Service::new("myapp.conf").validate().is_err());
+        assert!(Service::new("getty@.service").validate().is_err());
+    }
+}
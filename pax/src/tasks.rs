@@ -0,0 +1,212 @@
+use std::{collections::HashSet, io};
+
+use mlua::{ErrorContext, Function, Lua, RegistryKey};
+
+use crate::util;
+
+/// Name of the task that runs when no task is given on the command line.
+pub(crate) const DEFAULT_TASK: &str = "default";
+
+#[derive(Clone, Debug, Default, pax_derive::FromLuaTable)]
+pub(crate) struct TaskOpts {
+    /// Tasks that have to run before this one.
+    #[lua_default(Vec::new())]
+    pub deps: Vec<String>,
+    /// Shown by `pax list`.
+    pub description: Option<String>,
+}
+
+impl mlua::FromLua<'_> for TaskOpts {
+    fn from_lua(value: mlua::Value<'_>, lua: &'_ Lua) -> mlua::Result<Self> {
+        use mlua::Value;
+        match value {
+            Value::Nil => Ok(Self::default()),
+            Value::Table(t) => Self::from_lua_table(t, lua),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: std::any::type_name::<Self>(),
+                message: None,
+            }),
+        }
+    }
+}
+
+struct Task {
+    name: String,
+    opts: TaskOpts,
+    func: RegistryKey,
+}
+
+/// Tasks registered with pax.task(), kept as app data on the Lua state.
+#[derive(Default)]
+pub(crate) struct Tasks {
+    tasks: Vec<Task>,
+}
+
+impl Tasks {
+    fn get(&self, name: &str) -> mlua::Result<&Task> {
+        self.tasks.iter().find(|t| t.name == name).ok_or_else(|| {
+            let known: Vec<_> = self.tasks.iter().map(|t| t.name.as_str()).collect();
+            mlua::Error::runtime(format!(
+                "unknown task {:?}, the known tasks are: {}",
+                name,
+                known.join(", ")
+            ))
+        })
+    }
+
+    /// The order to run `names` in so that every task runs after its dependencies, and only once.
+    pub(crate) fn order(&self, names: &[String]) -> mlua::Result<Vec<String>> {
+        let mut order = Vec::new();
+        let mut done = HashSet::new();
+        let mut stack = Vec::new();
+        for name in names {
+            self.visit(name, &mut stack, &mut done, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit(
+        &self,
+        name: &str,
+        stack: &mut Vec<String>,
+        done: &mut HashSet<String>,
+        order: &mut Vec<String>,
+    ) -> mlua::Result<()> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if let Some(i) = stack.iter().position(|n| n == name) {
+            let mut cycle = stack[i..].to_vec();
+            cycle.push(name.to_string());
+            return Err(mlua::Error::runtime(format!(
+                "tasks depend on each other: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        let task = self.get(name)?;
+        stack.push(name.to_string());
+        for dep in &task.opts.deps {
+            self.visit(dep, stack, done, order)?;
+        }
+        stack.pop();
+        done.insert(name.to_string());
+        order.push(name.to_string());
+        Ok(())
+    }
+
+    /// Print every task with its description and dependencies.
+    pub(crate) fn list<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let width = self.tasks.iter().map(|t| t.name.len()).max().unwrap_or(0);
+        for task in &self.tasks {
+            write!(w, "{:width$}", task.name, width = width)?;
+            if let Some(desc) = &task.opts.description {
+                write!(w, "  {}", desc)?;
+            }
+            if !task.opts.deps.is_empty() {
+                write!(w, "  (after {})", task.opts.deps.join(", "))?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+/// pax.task(name, fn, opts)
+pub(crate) fn task<'lua>(
+    lua: &'lua Lua,
+    (name, func, opts): (String, Function<'lua>, TaskOpts),
+) -> mlua::Result<()> {
+    if lua.app_data_ref::<Tasks>().is_none() {
+        lua.set_app_data(Tasks::default());
+    }
+    let func = lua.create_registry_value(func)?;
+    let mut tasks = lua.app_data_mut::<Tasks>().unwrap();
+    if tasks.tasks.iter().any(|t| t.name == name) {
+        return Err(mlua::Error::runtime(format!(
+            "task {:?} is already defined",
+            name
+        )));
+    }
+    tasks.tasks.push(Task { name, opts, func });
+    Ok(())
+}
+
+/// Run the tasks in `names` and their dependencies. Without any names the "default" task runs,
+/// if there is one.
+pub(crate) fn run(lua: &Lua, names: &[String]) -> mlua::Result<()> {
+    let (order, funcs) = {
+        let Some(tasks) = lua.app_data_ref::<Tasks>() else {
+            return match names.first() {
+                Some(name) => Err(mlua::Error::runtime(format!(
+                    "unknown task {:?}, no tasks are defined",
+                    name
+                ))),
+                None => Ok(()),
+            };
+        };
+        let order = if !names.is_empty() {
+            tasks.order(names)?
+        } else if tasks.tasks.iter().any(|t| t.name == DEFAULT_TASK) {
+            tasks.order(&[DEFAULT_TASK.to_string()])?
+        } else {
+            Vec::new()
+        };
+        let funcs = order
+            .iter()
+            .map(|name| lua.registry_value::<Function>(&tasks.get(name)?.func))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        (order, funcs)
+    };
+    for (name, func) in order.iter().zip(funcs) {
+        util::log(format!("task {}", name));
+        func.call::<_, ()>(())
+            .with_context(|_| format!("task {} failed", name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tasks;
+
+    #[test]
+    fn order() {
+        let lua = mlua::Lua::new();
+        lua.globals()
+            .set("task", lua.create_function(super::task).unwrap())
+            .unwrap();
+        lua.load(
+            r#"
+            ran = {}
+            task("proto", function() table.insert(ran, "proto") end)
+            task("server", function() table.insert(ran, "server") end, { deps = { "proto" } })
+            task("client", function() table.insert(ran, "client") end, { deps = { "proto" }, description = "The client" })
+            task("a", function() end, { deps = { "b" } })
+            task("b", function() end, { deps = { "a" } })
+            assert(not pcall(task, "proto", function() end))
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        super::run(&lua, &["server".to_string(), "client".to_string()]).unwrap();
+        let ran: Vec<String> = lua.load("ran").eval().unwrap();
+        assert_eq!(ran, vec!["proto", "server", "client"]);
+
+        let tasks = lua.app_data_ref::<Tasks>().unwrap();
+        let err = tasks.order(&["a".to_string()]).unwrap_err().to_string();
+        assert!(err.contains("a -> b -> a"), "{}", err);
+        let err = tasks.order(&["nope".to_string()]).unwrap_err().to_string();
+        assert!(err.contains("proto, server, client, a, b"), "{}", err);
+
+        let mut out = Vec::new();
+        tasks.list(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(
+            out.contains("client  The client  (after proto)\n"),
+            "{}",
+            out
+        );
+    }
+}
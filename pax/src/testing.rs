@@ -0,0 +1,520 @@
+//! `pax test`: runs the *_test.lua files of a project. Each file gets its own Lua state with
+//! `require("pax.testing")` for adding test cases, building packages and asserting on them.
+//! External commands and downloads are mocked while the tests run.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use mlua::{AnyUserData, Function, Lua, MultiValue, RegistryKey, Table, Value};
+
+use crate::build::RefCellBuildSpec;
+use crate::debfile::{self, Deb};
+use crate::error;
+use crate::util::walk;
+
+static TESTING: &str = include_str!("lua/testing.lua");
+
+/// Directories that are not searched for test files.
+static SKIP_DIRS: &[&str] = &[".git", ".pax", "target", "node_modules", "dist"];
+
+thread_local! {
+    // Per thread so that the mocks only cover the tests being run, and not whatever else runs
+    // alongside them.
+    static MOCKS: RefCell<Mocks> = const {
+        RefCell::new(Mocks {
+            active: false,
+            unmocked: Vec::new(),
+            calls: Vec::new(),
+            fixtures: BTreeMap::new(),
+        })
+    };
+}
+
+/// The kinds of calls that are mocked during `pax test`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    Dl,
+    Git,
+    Exec,
+}
+
+impl Kind {
+    /// The kind of a command line as given to `plan::skip`.
+    fn of(command: &str) -> Self {
+        if command.starts_with("download ") {
+            Self::Dl
+        } else if command.starts_with("git ") {
+            Self::Git
+        } else {
+            Self::Exec
+        }
+    }
+
+    fn parse(s: &str) -> mlua::Result<Self> {
+        match s {
+            "dl" => Ok(Self::Dl),
+            "git" => Ok(Self::Git),
+            "exec" => Ok(Self::Exec),
+            _ => Err(mlua::Error::runtime(format!(
+                "unknown mock {:?}, expected \"dl\", \"git\" or \"exec\"",
+                s
+            ))),
+        }
+    }
+}
+
+struct Mocks {
+    active: bool,
+    unmocked: Vec<Kind>,
+    calls: Vec<(Kind, String)>,
+    /// Files written by mocked downloads, by url.
+    fixtures: BTreeMap<String, PathBuf>,
+}
+
+impl Mocks {
+    fn mocks(&self, kind: Kind) -> bool {
+        self.active && !self.unmocked.contains(&kind)
+    }
+
+    /// Start over for a new test file.
+    fn reset(&mut self, active: bool) {
+        self.active = active;
+        self.unmocked.clear();
+        self.calls.clear();
+        self.fixtures.clear();
+    }
+}
+
+/// Record `command` when its kind is mocked. Returns true when the caller should skip running
+/// it.
+pub(crate) fn mocked(command: &str) -> bool {
+    let kind = Kind::of(command);
+    with_mocks(|mocks| {
+        if !mocks.mocks(kind) {
+            return false;
+        }
+        mocks.calls.push((kind, command.to_string()));
+        true
+    })
+}
+
+/// True while the tests of a file are running on this thread.
+pub(crate) fn active() -> bool {
+    with_mocks(|mocks| mocks.active)
+}
+
+pub(crate) fn is_mocked(kind: Kind) -> bool {
+    with_mocks(|mocks| mocks.mocks(kind))
+}
+
+/// The file registered with `fixture()` for a mocked download of `url`.
+pub(crate) fn fixture(url: &str) -> Option<PathBuf> {
+    with_mocks(|mocks| mocks.fixtures.get(url).cloned())
+}
+
+fn with_mocks<T>(f: impl FnOnce(&mut Mocks) -> T) -> T {
+    MOCKS.with(|mocks| f(&mut mocks.borrow_mut()))
+}
+
+/// Test cases added by the file being run, kept as app data on the Lua state.
+#[derive(Default)]
+struct Cases(Vec<(String, RegistryKey)>);
+
+/// Where t.build() writes packages, kept as app data on the Lua state.
+struct Dist(tempfile::TempDir);
+
+/// The result of one test case.
+#[derive(Debug)]
+pub(crate) struct Case {
+    pub name: String,
+    pub time: Duration,
+    /// The error report when the case failed.
+    pub failure: Option<String>,
+}
+
+/// The results of one test file.
+#[derive(Debug)]
+pub(crate) struct Suite {
+    pub file: String,
+    pub time: Duration,
+    pub cases: Vec<Case>,
+}
+
+impl Suite {
+    pub(crate) fn failures(&self) -> usize {
+        self.cases.iter().filter(|c| c.failure.is_some()).count()
+    }
+}
+
+/// Find the test files under each of `paths`, which can also name test files directly.
+pub(crate) fn discover<P: AsRef<Path>>(paths: &[P]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        if !fs::metadata(path)?.is_dir() {
+            files.push(path.to_path_buf());
+            continue;
+        }
+        walk(path, |entry| {
+            let p = entry.path();
+            let skipped = p
+                .components()
+                .any(|c| SKIP_DIRS.iter().any(|d| c.as_os_str() == *d));
+            let is_test = p
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.ends_with("_test.lua"));
+            if is_test && !skipped && entry.file_type()?.is_file() {
+                files.push(p.strip_prefix("./").unwrap_or(&p).to_path_buf());
+            }
+            Ok(())
+        })?;
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Add `require("pax.testing")` to a Lua state.
+pub(crate) fn setup(lua: &Lua) -> mlua::Result<()> {
+    let native = lua.create_table()?;
+    native.set("test", lua.create_function(add_case)?)?;
+    native.set("build", lua.create_function(build)?)?;
+    native.set("open", lua.create_function(debfile::open)?)?;
+    native.set(
+        "is_deb",
+        lua.create_function(|_, d: AnyUserData| Ok(d.is::<Deb>()))?,
+    )?;
+    native.set(
+        "mock",
+        lua.create_function(|_, kind: String| {
+            let kind = Kind::parse(&kind)?;
+            with_mocks(|mocks| mocks.unmocked.retain(|k| *k != kind));
+            Ok(())
+        })?,
+    )?;
+    native.set(
+        "unmock",
+        lua.create_function(|_, kind: String| {
+            let kind = Kind::parse(&kind)?;
+            with_mocks(|mocks| mocks.unmocked.push(kind));
+            Ok(())
+        })?,
+    )?;
+    native.set(
+        "calls",
+        lua.create_function(|_, kind: Option<String>| {
+            let kind = kind.as_deref().map(Kind::parse).transpose()?;
+            Ok(with_mocks(|mocks| {
+                mocks
+                    .calls
+                    .iter()
+                    .filter(|(k, _)| kind.is_none_or(|kind| *k == kind))
+                    .map(|(_, call)| call.clone())
+                    .collect::<Vec<_>>()
+            }))
+        })?,
+    )?;
+    native.set(
+        "fixture",
+        lua.create_function(|_, (url, path): (String, String)| {
+            fs::metadata(&path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: fixture {:?}", e, path)))?;
+            with_mocks(|mocks| mocks.fixtures.insert(url, path.into()));
+            Ok(())
+        })?,
+    )?;
+    let native = lua.create_registry_value(native)?;
+    let loader = lua.create_function(move |lua, _: MultiValue| {
+        lua.load(TESTING)
+            .set_name("pax/testing.lua")
+            .call::<_, Value>(lua.registry_value::<Table>(&native)?)
+    })?;
+    let package: Table = lua.globals().get("package")?;
+    let preload: Table = package.get("preload")?;
+    preload.set("pax.testing", loader)
+}
+
+/// t.test(name, fn)
+fn add_case(lua: &Lua, (name, func): (String, Function)) -> mlua::Result<()> {
+    let func = lua.create_registry_value(func)?;
+    if lua.app_data_ref::<Cases>().is_none() {
+        lua.set_app_data(Cases::default());
+    }
+    lua.app_data_mut::<Cases>().unwrap().0.push((name, func));
+    Ok(())
+}
+
+/// t.build(spec)
+fn build(lua: &Lua, spec: RefCellBuildSpec) -> mlua::Result<Deb> {
+    if lua.app_data_ref::<Dist>().is_none() {
+        lua.set_app_data(Dist(tempfile::tempdir()?));
+    }
+    let dist = lua.app_data_ref::<Dist>().unwrap().0.path().to_path_buf();
+    let mut spec = spec.take();
+    spec.pre_process(None)?;
+    // Written straight to the temporary directory, leaving the project's build manifests alone.
+    spec.files.sort_by_key(|f| f.dst.clone());
+    spec.validate()
+        .and_then(|()| spec.write_package(&dist))
+        .map_err(|e| error::Error::build(spec.package.clone(), e))?;
+    Ok(Deb(debfile::DebFile::open(dist.join(spec.filename()))?))
+}
+
+/// Run a test file in `lua`, which has already been set up like it is for pax.lua.
+pub(crate) fn run_file(lua: &Lua, file: &Path, verbose: bool) -> Suite {
+    let start = Instant::now();
+    with_mocks(|mocks| mocks.reset(true));
+    let mut suite = Suite {
+        file: file.to_string_lossy().to_string(),
+        time: Duration::ZERO,
+        cases: Vec::new(),
+    };
+    let loaded = setup(lua).and_then(|()| {
+        lua.load(fs::read_to_string(file)?)
+            .set_name(format!("@{}", file.display()))
+            .exec()
+    });
+    if let Err(e) = loaded {
+        suite.cases.push(Case {
+            name: "(load)".to_string(),
+            time: start.elapsed(),
+            failure: Some(error::render(&e, verbose)),
+        });
+    }
+    let cases = lua
+        .remove_app_data::<Cases>()
+        .map(|c| c.0)
+        .unwrap_or_default();
+    for (name, func) in cases {
+        with_mocks(|mocks| mocks.calls.clear());
+        let start = Instant::now();
+        let res = lua
+            .registry_value::<Function>(&func)
+            .and_then(|f| f.call::<_, ()>(()));
+        suite.cases.push(Case {
+            name,
+            time: start.elapsed(),
+            failure: res.err().map(|e| error::render(&e, verbose)),
+        });
+    }
+    with_mocks(|mocks| mocks.reset(false));
+    suite.time = start.elapsed();
+    suite
+}
+
+/// Print each case as it would appear in the terminal, with the failures after them.
+pub(crate) fn write_text<W: Write>(w: &mut W, suites: &[Suite]) -> io::Result<()> {
+    let mut failed = Vec::new();
+    for suite in suites {
+        for case in &suite.cases {
+            match &case.failure {
+                None => writeln!(w, "ok   {} > {}", suite.file, case.name)?,
+                Some(failure) => {
+                    writeln!(w, "FAIL {} > {}", suite.file, case.name)?;
+                    failed.push((suite, case, failure));
+                }
+            }
+        }
+    }
+    for (suite, case, failure) in &failed {
+        writeln!(w, "\n--- {} > {}", suite.file, case.name)?;
+        write!(w, "{}", failure)?;
+    }
+    let total: usize = suites.iter().map(|s| s.cases.len()).sum();
+    let time: Duration = suites.iter().map(|s| s.time).sum();
+    writeln!(
+        w,
+        "\n{} passed, {} failed in {:.2?}",
+        total - failed.len(),
+        failed.len(),
+        time
+    )
+}
+
+/// Write the results in the JUnit XML format read by CI servers.
+pub(crate) fn write_junit<W: Write>(w: &mut W, suites: &[Suite]) -> io::Result<()> {
+    let total: usize = suites.iter().map(|s| s.cases.len()).sum();
+    let failures: usize = suites.iter().map(Suite::failures).sum();
+    let time: Duration = suites.iter().map(|s| s.time).sum();
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<testsuites name="pax" tests="{}" failures="{}" time="{:.3}">"#,
+        total,
+        failures,
+        time.as_secs_f64()
+    )?;
+    for suite in suites {
+        writeln!(
+            w,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" errors="0" skipped="0" time="{:.3}">"#,
+            Xml(&suite.file),
+            suite.cases.len(),
+            suite.failures(),
+            suite.time.as_secs_f64()
+        )?;
+        for case in &suite.cases {
+            write!(
+                w,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}""#,
+                Xml(&case.name),
+                Xml(&suite.file),
+                case.time.as_secs_f64()
+            )?;
+            match &case.failure {
+                None => writeln!(w, "/>")?,
+                Some(failure) => {
+                    let message = failure.lines().next().unwrap_or_default();
+                    let message = message.strip_prefix("error: ").unwrap_or(message);
+                    writeln!(w, ">")?;
+                    writeln!(
+                        w,
+                        r#"      <failure message="{}" type="failure">{}</failure>"#,
+                        Xml(message),
+                        Xml(failure)
+                    )?;
+                    writeln!(w, "    </testcase>")?;
+                }
+            }
+        }
+        writeln!(w, "  </testsuite>")?;
+    }
+    writeln!(w, "</testsuites>")
+}
+
+/// Text escaped for XML attributes and content.
+struct Xml<'a>(&'a str);
+
+impl fmt::Display for Xml<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&apos;")?,
+                '\n' | '\t' => write!(f, "{}", c)?,
+                // Control characters such as colour codes are not allowed in XML 1.0.
+                c if c.is_control() => {}
+                c => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{discover, run_file, write_junit, write_text};
+
+    #[test]
+    fn run() {
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("tool");
+        let jq = dir.path().join("jq");
+        fs::write(&bin, "#!/bin/sh\n").unwrap();
+        fs::write(&jq, "fake jq").unwrap();
+        fs::create_dir_all(dir.path().join("tests/dist")).unwrap();
+        fs::write(dir.path().join("tests/dist/ignored_test.lua"), "").unwrap();
+        let file = dir.path().join("tests/tool_test.lua");
+        fs::write(
+            &file,
+            format!(
+                r#"
+                local t = require("pax.testing")
+                local expect = t.expect
+
+                t.test("package", function()
+                    local deb = t.build({{
+                        package = "tool",
+                        version = "1.2.0",
+                        arch = "amd64",
+                        maintainer = "Jo <jo@example.com>",
+                        description = "A tool",
+                        files = {{ {{ src = {bin:?}, dst = "/usr/bin/tool", mode = tonumber("755", 8) }} }},
+                        scripts = {{ postinst = "echo configured" }},
+                    }})
+                    expect(deb).to_contain("/usr/bin/tool", {{ mode = "0755", kind = "file", owner = "root" }})
+                    expect(deb).to_contain("/usr/bin/tool", {{ mode = require("pax").octal("0755") }})
+                    local ok, err = pcall(expect(deb).to_contain, "/usr/bin/tool", {{ mode = 0755 }})
+                    assert(not ok and err:find('mode is 0755, not 1363 %(write octal modes as "0755"%)'), err)
+                    expect(deb).not_to_contain("/usr/bin/other")
+                    expect(deb).to_have_field("Version", "1.2.0")
+                    expect(deb).to_have_script("postinst", "echo configured")
+                    expect(deb.package).to_be("tool")
+                end)
+
+                t.test("mocks", function()
+                    local pax = require("pax")
+                    t.fixture("https://example.com/jq", {jq:?})
+                    local out = {out:?}
+                    pax.dl.fetch("https://example.com/jq", {{ out = out }})
+                    pax.exec("make", {{ "install" }})
+                    expect(t.calls("exec")).to_equal({{ "make install" }})
+                    expect(#t.calls()).to_be(2)
+                    expect(io.open(out):read("a")).to_be("fake jq")
+                end)
+
+                t.test("fails <here>", function()
+                    expect({{ 1, 2 }}).to_equal({{ 1, 3 }})
+                end)
+                "#,
+                bin = bin.to_str().unwrap(),
+                jq = jq.to_str().unwrap(),
+                out = dir.path().join("out/jq").to_str().unwrap(),
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(discover(&[dir.path()]).unwrap(), vec![file.clone()]);
+        let lua = mlua::Lua::new();
+        crate::Cli::default().setup(&lua).unwrap();
+        let suite = run_file(&lua, &file, false);
+        let failures: Vec<_> = suite
+            .cases
+            .iter()
+            .map(|c| (c.name.as_str(), c.failure.as_deref()))
+            .collect();
+        assert_eq!(failures[0], ("package", None));
+        assert_eq!(failures[1], ("mocks", None));
+        let failure = failures[2].1.unwrap();
+        assert!(
+            failure.contains("tool_test.lua:37: expected { 1, 3 }, got { 1, 2 }\n"),
+            "{}",
+            failure
+        );
+        assert!(failure.contains("37 |"), "{}", failure);
+        // Nothing runs once the file is done.
+        assert!(!super::mocked("make install"));
+
+        let suites = [suite];
+        let mut text = Vec::new();
+        write_text(&mut text, &suites).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("2 passed, 1 failed"), "{}", text);
+        let mut xml = Vec::new();
+        write_junit(&mut xml, &suites).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.contains(r#"tests="3" failures="1""#), "{}", xml);
+        assert!(
+            xml.contains(r#"<testcase name="fails &lt;here&gt;""#),
+            "{}",
+            xml
+        );
+        assert!(
+            xml.contains(r#"tool_test.lua:37: expected { 1, 3 }, got { 1, 2 }" type="failure">"#),
+            "{}",
+            xml
+        );
+    }
+}
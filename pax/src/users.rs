@@ -0,0 +1,245 @@
+use std::io;
+
+use crate::scripts::{quote, Script, ScriptComposer};
+use crate::util::to_io_err;
+
+/// An account created by the package's preinst script.
+#[derive(Clone, Debug, PartialEq, pax_derive::IntoLua, serde::Deserialize)]
+#[serde(from = "UserRepr")]
+pub(crate) struct User {
+    pub name: String,
+    /// Create a system account. Default is true.
+    pub system: bool,
+    /// Home directory, created and owned by the user when given.
+    pub home: Option<String>,
+    /// Login shell. System accounts default to /usr/sbin/nologin.
+    pub shell: Option<String>,
+    /// Supplementary groups, created when they don't exist.
+    pub groups: Vec<String>,
+    /// Delete the account and its group when the package is purged. Default is false.
+    pub remove_on_purge: bool,
+}
+
+impl User {
+    pub(crate) fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            system: true,
+            home: None,
+            shell: None,
+            groups: Vec::new(),
+            remove_on_purge: false,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        for name in std::iter::once(&self.name).chain(self.groups.iter()) {
+            let valid = name
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-".contains(c))
+                && name.len() <= 32;
+            if !valid {
+                return Err(to_io_err(format!("invalid user or group name {:?}", name)));
+            }
+        }
+        for path in self.home.iter().chain(self.shell.iter()) {
+            if !path.starts_with('/') || path.contains(|c: char| c == '\'' || c.is_control()) {
+                return Err(to_io_err(format!(
+                    "invalid path {:?} for user {:?}, expected an absolute path",
+                    path, self.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the snippets that create the account before the package is unpacked, so that files
+    /// in the package can be owned by it, and optionally delete it on purge.
+    pub(crate) fn add_scripts(&self, composer: &mut ScriptComposer) {
+        let source = format!("user {}", self.name);
+        let system = if self.system { " --system" } else { "" };
+        let shell = self
+            .shell
+            .as_deref()
+            .or(self.system.then_some("/usr/sbin/nologin"));
+        let name = quote(&self.name);
+        let mut useradd = format!("useradd{} --gid {name}", system, name = name);
+        match self.home {
+            Some(ref home) => useradd.push_str(&format!(" --home-dir {}", quote(home))),
+            None if self.system => useradd.push_str(" --home-dir /nonexistent"),
+            None => {}
+        }
+        useradd.push_str(" --no-create-home");
+        if let Some(shell) = shell {
+            useradd.push_str(&format!(" --shell {}", quote(shell)));
+        }
+        useradd.push_str(&format!(" {}", name));
+
+        let mut preinst = format!(
+            "if ! getent group {name} >/dev/null; then\n    groupadd{} {name}\nfi\n\
+            if ! getent passwd {name} >/dev/null; then\n    {}\nfi\n",
+            system,
+            useradd,
+            name = name
+        );
+        for group in &self.groups {
+            preinst.push_str(&format!(
+                "if ! getent group {group} >/dev/null; then\n    groupadd{} {group}\nfi\n",
+                system,
+                group = quote(group)
+            ));
+        }
+        if !self.groups.is_empty() {
+            preinst.push_str(&format!(
+                "usermod --append --groups {} {}\n",
+                quote(&self.groups.join(",")),
+                name
+            ));
+        }
+        if let Some(ref home) = self.home {
+            preinst.push_str(&format!(
+                "if [ ! -d {home} ]; then\n    mkdir -p {home}\n    chown {owner} {home}\nfi\n",
+                home = quote(home),
+                owner = quote(&format!("{0}:{0}", self.name))
+            ));
+        }
+        composer.add(Script::Preinst, &["install", "upgrade"], &source, &preinst);
+
+        if self.remove_on_purge {
+            composer.add(
+                Script::Postrm,
+                &["purge"],
+                &source,
+                &format!(
+                    "if getent passwd {name} >/dev/null; then\n    userdel {name} || true\nfi\n\
+                    if getent group {name} >/dev/null; then\n    groupdel {name} || true\nfi\n",
+                    name = name
+                ),
+            );
+        }
+    }
+}
+
+/// An account name or a table, the same as in Lua.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum UserRepr {
+    Name(String),
+    Table(UserTable),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserTable {
+    name: String,
+    system: Option<bool>,
+    home: Option<String>,
+    shell: Option<String>,
+    #[serde(default)]
+    groups: Vec<String>,
+    #[serde(default)]
+    remove_on_purge: bool,
+}
+
+impl From<UserRepr> for User {
+    fn from(value: UserRepr) -> Self {
+        match value {
+            UserRepr::Name(name) => Self::new(name),
+            UserRepr::Table(UserTable {
+                name,
+                system,
+                home,
+                shell,
+                groups,
+                remove_on_purge,
+            }) => Self {
+                name,
+                system: system.unwrap_or(true),
+                home,
+                shell,
+                groups,
+                remove_on_purge,
+            },
+        }
+    }
+}
+
+impl mlua::FromLua<'_> for User {
+    /// A plain string is the name of a system account.
+    fn from_lua(
+        value: mlua::prelude::LuaValue<'_>,
+        _lua: &'_ mlua::prelude::Lua,
+    ) -> mlua::prelude::LuaResult<Self> {
+        use mlua::Value;
+        match value {
+            Value::String(s) => Ok(Self::new(s.to_str()?)),
+            Value::Table(t) => Ok(Self {
+                name: t.get("name")?,
+                system: t.get::<_, Option<bool>>("system")?.unwrap_or(true),
+                home: t.get("home")?,
+                shell: t.get("shell")?,
+                groups: t
+                    .get::<_, Option<Vec<String>>>("groups")?
+                    .unwrap_or_default(),
+                remove_on_purge: t.get("remove_on_purge")?,
+            }),
+            _ => Err(mlua::Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: std::any::type_name::<Self>(),
+                message: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::User;
+    use crate::scripts::{Script, ScriptComposer};
+
+    #[test]
+    fn scripts() {
+        let lua = mlua::Lua::new();
+        let users: Vec<User> = lua
+            .load(
+                r#"{
+                    { name = "myapp", home = "/var/lib/myapp", groups = { "adm", "video" }, remove_on_purge = true },
+                    "other",
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(users[1], User::new("other"));
+        let mut c = ScriptComposer::default();
+        for u in &users {
+            u.validate().unwrap();
+            u.add_scripts(&mut c);
+        }
+        let preinst = c.compose(Script::Preinst, None).unwrap();
+        assert!(preinst.contains(
+            "useradd --system --gid 'myapp' --home-dir '/var/lib/myapp' --no-create-home \
+            --shell '/usr/sbin/nologin' 'myapp'"
+        ));
+        assert!(preinst.contains("usermod --append --groups 'adm,video' 'myapp'"));
+        assert!(preinst.contains("chown 'myapp:myapp' '/var/lib/myapp'"));
+        assert!(preinst.contains("--home-dir /nonexistent --no-create-home"));
+        let postrm = c.compose(Script::Postrm, None).unwrap();
+        assert!(postrm.contains("userdel 'myapp'"));
+        assert!(!postrm.contains("userdel 'other'"));
+
+        assert!(User::new("Bad Name").validate().is_err());
+        assert!(User::new("1abc").validate().is_err());
+        for path in ["var/lib/myapp", "/var/lib/it's", "/var/lib/a\nb"] {
+            let mut user = User::new("myapp");
+            user.home = Some(path.to_string());
+            assert!(user.validate().is_err());
+            user.home = None;
+            user.shell = Some(path.to_string());
+            assert!(user.validate().is_err());
+        }
+    }
+}
@@ -15,6 +15,7 @@ use md5::Digest;
 use mlua::{Table, Value};
 
 use crate::error::Error;
+use crate::sandbox;
 
 #[inline]
 pub(crate) fn get_user_name() -> std::io::Result<String> {
@@ -44,6 +45,11 @@ pub(crate) fn print_function<'a>(
     Ok(())
 }
 
+/// Print a status message with the pax prefix.
+pub(crate) fn log<S: std::fmt::Display>(msg: S) {
+    println!("[\x1b[01;32mpax\x1b[0m] {}", msg);
+}
+
 pub(crate) fn lua_octal(_lua: &'_ mlua::Lua, n: String) -> mlua::Result<u32> {
     Ok(u32::from_str_radix(&n, 8).map_err(mlua::Error::runtime)?)
 }
@@ -161,13 +167,21 @@ impl Printer {
     }
 }
 
+/// A command running `program`. Every external command goes through here so that none of them
+/// can get around the sandbox.
+pub(crate) fn command<S: AsRef<std::ffi::OsStr>>(program: S) -> io::Result<process::Command> {
+    let program = program.as_ref();
+    sandbox::check_exec(&program.to_string_lossy())?;
+    Ok(process::Command::new(program))
+}
+
 fn git_cmd<I, S>(args: I) -> io::Result<String>
 where
     I: IntoIterator<Item = S>,
     S: AsRef<std::ffi::OsStr>,
 {
     use std::str; // needed for 'from_utf8'
-    let out = process::Command::new("git").args(args).output()?;
+    let out = command("git")?.args(args).output()?;
     if !out.status.success() {
         let s = str::from_utf8(&out.stderr)
             .map(|s| s.strip_suffix('\n').unwrap_or(s))
@@ -399,7 +413,10 @@ pub struct SCDocOpts {
 pub fn scdoc(opts: SCDocOpts) -> io::Result<()> {
     use flate2::write::GzEncoder;
     use flate2::Compression;
-    let mut child = process::Command::new("scdoc")
+    if crate::plan::skip(format!("scdoc < {} > {}", opts.input, opts.output)) {
+        return Ok(());
+    }
+    let mut child = command("scdoc")?
         .stdin(process::Stdio::piped())
         .stdout(process::Stdio::piped())
         .spawn()
@@ -453,7 +470,7 @@ pub struct GccFeatures {
 }
 
 pub(crate) fn gcc_features() -> Result<GccFeatures, io::Error> {
-    let mut child = process::Command::new("gcc")
+    let mut child = command("gcc")?
         .args(&["-dM", "-E", "-"])
         .stdin(process::Stdio::piped())
         .stdout(process::Stdio::piped())
@@ -0,0 +1,295 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs, io,
+    path::Path,
+    sync::Mutex,
+};
+
+use mlua::Lua;
+
+use crate::util::to_io_err;
+
+/// Variables that were set from a .env file, as opposed to the real environment.
+static DOTENV_KEYS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Values passed into pax.lua from the command line.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Vars {
+    /// Variables set with -D.
+    pub defines: BTreeMap<String, String>,
+    /// Arguments given after '--'.
+    pub args: Vec<String>,
+}
+
+impl Vars {
+    /// Look a variable up in the -D definitions and then in the environment.
+    pub(crate) fn get(&self, name: &str) -> Option<String> {
+        self.defines
+            .get(name)
+            .cloned()
+            .or_else(|| env::var(name).ok())
+    }
+}
+
+/// Parse a "key=value" argument.
+pub(crate) fn parse_define(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {:?}", s)),
+    }
+}
+
+/// Parse a .env file. Lines are "KEY=VALUE", optionally prefixed by "export". Values can be
+/// quoted, and double quoted values understand \n, \t, \" and \\.
+pub(crate) fn parse_dotenv(text: &str) -> io::Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| to_io_err(format!("line {}: expected KEY=VALUE", i + 1)))?;
+        let key = key.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(to_io_err(format!(
+                "line {}: invalid variable name {:?}",
+                i + 1,
+                key
+            )));
+        }
+        let value = value.trim();
+        let value = if let Some(v) = quoted(value, '\'') {
+            v.to_string()
+        } else if let Some(v) = quoted(value, '"') {
+            unescape(v)
+        } else {
+            // Unquoted values can have a trailing comment.
+            match value.find(" #") {
+                Some(i) => value[..i].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+fn quoted(s: &str, quote: char) -> Option<&str> {
+    s.strip_prefix(quote)?.strip_suffix(quote)
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Load a .env file into the environment. Variables that are already set are left alone so that
+/// the real environment always wins, except for those set by an earlier load, which are replaced
+/// or removed so that --watch sees edits to the file.
+pub(crate) fn load_dotenv<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    crate::watch::track(path);
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: could not read {:?}", e, path)))?;
+    let vars = parse_dotenv(&text)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let mut loaded = DOTENV_KEYS.lock().unwrap_or_else(|e| e.into_inner());
+    for key in loaded.iter() {
+        if !vars.iter().any(|(k, _)| k == key) {
+            env::remove_var(key);
+        }
+    }
+    loaded.retain(|key| vars.iter().any(|(k, _)| k == key));
+    for (key, value) in vars {
+        if loaded.contains(&key) || env::var_os(&key).is_none() {
+            env::set_var(&key, value);
+            loaded.insert(key);
+        }
+    }
+    Ok(())
+}
+
+fn lookup(lua: &Lua, name: &str) -> Option<String> {
+    match lua.app_data_ref::<Vars>() {
+        Some(vars) => vars.get(name),
+        None => env::var(name).ok(),
+    }
+}
+
+/// pax.var(name, default)
+pub(crate) fn var(
+    lua: &Lua,
+    (name, default): (String, Option<String>),
+) -> mlua::Result<Option<String>> {
+    Ok(lookup(lua, &name).or(default))
+}
+
+/// pax.var_number(name, default)
+pub(crate) fn var_number<'lua>(
+    lua: &'lua Lua,
+    (name, default): (String, mlua::Value<'lua>),
+) -> mlua::Result<mlua::Value<'lua>> {
+    let Some(v) = lookup(lua, &name) else {
+        return Ok(default);
+    };
+    let v = v.trim();
+    if let Ok(i) = v.parse() {
+        return Ok(mlua::Value::Integer(i));
+    }
+    v.parse()
+        .map(mlua::Value::Number)
+        .map_err(|_| mlua::Error::runtime(format!("variable {} is not a number: {:?}", name, v)))
+}
+
+/// pax.var_bool(name, default)
+pub(crate) fn var_bool(
+    lua: &Lua,
+    (name, default): (String, Option<bool>),
+) -> mlua::Result<Option<bool>> {
+    match lookup(lua, &name) {
+        None => Ok(default),
+        Some(v) => match v.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "0" | "false" | "no" | "off" | "" => Ok(Some(false)),
+            _ => Err(mlua::Error::runtime(format!(
+                "variable {} is not a boolean: {:?}",
+                name, v
+            ))),
+        },
+    }
+}
+
+/// pax.var_list(name, default), where the value is separated by commas.
+pub(crate) fn var_list(
+    lua: &Lua,
+    (name, default): (String, Option<Vec<String>>),
+) -> mlua::Result<Option<Vec<String>>> {
+    Ok(match lookup(lua, &name) {
+        None => default,
+        Some(v) => Some(
+            v.split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::{load_dotenv, parse_define, parse_dotenv, Vars};
+
+    #[test]
+    fn dotenv() {
+        let vars = parse_dotenv(
+            "# comment\n\
+            VERSION=1.2.3\n\
+            export CHANNEL = 'beta # not a comment'\n\
+            \n\
+            NOTES=\"line one\\nline \\\"two\\\"\"\n\
+            ARCH=arm64 # trailing comment\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                ("VERSION".to_string(), "1.2.3".to_string()),
+                ("CHANNEL".to_string(), "beta # not a comment".to_string()),
+                ("NOTES".to_string(), "line one\nline \"two\"".to_string()),
+                ("ARCH".to_string(), "arm64".to_string()),
+            ]
+        );
+        assert!(parse_dotenv("NOPE").is_err());
+        assert!(parse_dotenv("BAD KEY=1").is_err());
+        assert_eq!(
+            parse_define("a=b=c").unwrap(),
+            ("a".to_string(), "b=c".to_string())
+        );
+        assert!(parse_define("=x").is_err());
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+        env::set_var("PAX_TEST_RELOAD_REAL", "real");
+        fs::write(
+            &path,
+            "PAX_TEST_RELOAD_VERSION=1.0\nPAX_TEST_RELOAD_OLD=x\nPAX_TEST_RELOAD_REAL=env\n",
+        )
+        .unwrap();
+        load_dotenv(&path).unwrap();
+        assert_eq!(env::var("PAX_TEST_RELOAD_VERSION").unwrap(), "1.0");
+        assert_eq!(env::var("PAX_TEST_RELOAD_OLD").unwrap(), "x");
+        fs::write(
+            &path,
+            "PAX_TEST_RELOAD_VERSION=2.0\nPAX_TEST_RELOAD_REAL=env\n",
+        )
+        .unwrap();
+        load_dotenv(&path).unwrap();
+        assert_eq!(env::var("PAX_TEST_RELOAD_VERSION").unwrap(), "2.0");
+        assert!(env::var_os("PAX_TEST_RELOAD_OLD").is_none());
+        assert_eq!(env::var("PAX_TEST_RELOAD_REAL").unwrap(), "real");
+    }
+
+    #[test]
+    fn helpers() {
+        let lua = mlua::Lua::new();
+        let mut vars = Vars::default();
+        for (k, v) in [
+            ("version", "1.2.3"),
+            ("jobs", "4"),
+            ("release", "yes"),
+            ("arches", "amd64, arm64"),
+        ] {
+            vars.defines.insert(k.to_string(), v.to_string());
+        }
+        lua.set_app_data(vars);
+        let g = lua.globals();
+        g.set("var", lua.create_function(super::var).unwrap())
+            .unwrap();
+        g.set(
+            "var_number",
+            lua.create_function(super::var_number).unwrap(),
+        )
+        .unwrap();
+        g.set("var_bool", lua.create_function(super::var_bool).unwrap())
+            .unwrap();
+        g.set("var_list", lua.create_function(super::var_list).unwrap())
+            .unwrap();
+        lua.load(
+            r#"
+            assert(var("version") == "1.2.3")
+            assert(var("missing_pax_var", "dflt") == "dflt")
+            assert(var("missing_pax_var") == nil)
+            assert(math.type(var_number("jobs", 1)) == "integer" and var_number("jobs") == 4)
+            assert(var_number("missing_pax_var", 2) == 2)
+            assert(var_bool("release", false) == true)
+            local arches = var_list("arches")
+            assert(#arches == 2 and arches[2] == "arm64")
+            assert(not pcall(var_number, "version"))
+            assert(not pcall(var_bool, "version"))
+            "#,
+        )
+        .exec()
+        .unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+//! `pax verify`: installs a package into a directory and removes it again, running the
+//! maintainer scripts along the way, to find scripts that fail and files that are left behind.
+//!
+//! There is no chroot. Absolute paths in the scripts are rewritten to point into the root and
+//! commands that change the system, like useradd and systemctl, are replaced with stubs that
+//! only log how they were called.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use crate::debfile::DebFile;
+use crate::error::Error;
+use crate::scripts::Script;
+use crate::util::{self, to_io_err};
+
+/// Top level directories whose paths are rewritten to be inside the root.
+static TOP_DIRS: &[&str] = &[
+    "bin", "boot", "etc", "home", "lib", "lib32", "lib64", "opt", "root", "run", "sbin", "srv",
+    "usr", "var",
+];
+
+/// Directories that the stubbed commands are installed in.
+static BIN_DIRS: &[&str] = &[
+    "/bin",
+    "/sbin",
+    "/usr/bin",
+    "/usr/sbin",
+    "/usr/local/bin",
+    "/usr/local/sbin",
+];
+
+/// Commands that change the system outside the root. They are logged instead of run.
+static STUBS: &[&str] = &[
+    "addgroup",
+    "adduser",
+    "apt",
+    "apt-get",
+    "apt-key",
+    "chgrp",
+    "chown",
+    "deb-systemd-helper",
+    "deb-systemd-invoke",
+    "delgroup",
+    "deluser",
+    "dpkg-maintscript-helper",
+    "dpkg-trigger",
+    "groupadd",
+    "groupdel",
+    "invoke-rc.d",
+    "ldconfig",
+    "service",
+    "systemctl",
+    "update-alternatives",
+    "update-initramfs",
+    "update-rc.d",
+    "useradd",
+    "userdel",
+    "usermod",
+];
+
+/// One maintainer script run, or another step of the install and removal.
+#[derive(Debug)]
+pub(crate) struct Step {
+    /// Like "postinst configure" or "unpack".
+    pub name: String,
+    pub ok: bool,
+    /// What the script printed, or a summary of the step.
+    pub output: String,
+    /// Stubbed commands the script called.
+    pub calls: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    pub steps: Vec<Step>,
+    pub problems: Vec<String>,
+}
+
+impl Report {
+    pub(crate) fn write_text<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for step in &self.steps {
+            writeln!(
+                w,
+                "{}: {}",
+                step.name,
+                if step.ok { "ok" } else { "FAILED" }
+            )?;
+            for call in &step.calls {
+                writeln!(w, "  $ {}", call)?;
+            }
+            for line in step.output.lines() {
+                writeln!(w, "  | {}", line)?;
+            }
+        }
+        match self.problems.len() {
+            0 => writeln!(w, "no problems found"),
+            n => {
+                writeln!(w, "{} problem{}:", n, if n == 1 { "" } else { "s" })?;
+                for problem in &self.problems {
+                    writeln!(w, "  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+struct Simulation<'a> {
+    deb: &'a DebFile,
+    root: PathBuf,
+    shell: &'a str,
+    /// Holds the command stubs, the rewritten scripts and the log of stubbed calls.
+    work: tempfile::TempDir,
+    report: Report,
+}
+
+/// Install `deb` into `root` and remove it, then purge it, running the maintainer scripts with
+/// `shell`. Files in `root` from before are left alone.
+pub(crate) fn verify<P: AsRef<Path>>(deb: &DebFile, root: P, shell: &str) -> io::Result<Report> {
+    fs::create_dir_all(&root)?;
+    let root = fs::canonicalize(root)?;
+    if root == Path::new("/") {
+        return Err(to_io_err("refusing to verify a package in /"));
+    }
+    let mut sim = Simulation {
+        deb,
+        root,
+        shell,
+        work: tempfile::tempdir()?,
+        report: Report::default(),
+    };
+    sim.write_stubs()?;
+    let before = snapshot(&sim.root)?;
+    let conffiles = deb.conffiles();
+
+    sim.run(Script::Preinst, &["install"])?;
+    let unpacked = deb.unpack(&sim.root);
+    sim.step(
+        "unpack",
+        &unpacked,
+        format!("{} entries", deb.entries.len()),
+    );
+    sim.run(Script::Postinst, &["configure", ""])?;
+    for entry in &deb.entries {
+        if fs::symlink_metadata(sim.path(&entry.path)).is_err() {
+            sim.problem(format!("missing after install: {}", entry.path));
+        }
+    }
+
+    sim.run(Script::Prerm, &["remove"])?;
+    let removed = sim.remove(&before, &conffiles);
+    sim.step("remove", &removed, String::new());
+    sim.run(Script::Postrm, &["remove"])?;
+    let keep: BTreeSet<_> = before.iter().chain(&conffiles).cloned().collect();
+    for path in left_over(&snapshot(&sim.root)?, &keep) {
+        sim.problem(format!("left after remove: {}", path));
+    }
+
+    let purged = conffiles
+        .iter()
+        .filter(|path| !before.contains(*path))
+        .try_for_each(|path| match fs::remove_file(sim.path(path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        });
+    sim.step("purge", &purged, format!("{} conffiles", conffiles.len()));
+    sim.run(Script::Postrm, &["purge"])?;
+    for path in left_over(&snapshot(&sim.root)?, &before) {
+        sim.problem(format!("left after purge: {}", path));
+    }
+    Ok(sim.report)
+}
+
+impl Simulation<'_> {
+    /// Where an installed path is in the root.
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    fn log(&self) -> PathBuf {
+        self.work.path().join("calls.log")
+    }
+
+    fn problem(&mut self, problem: String) {
+        self.report.problems.push(problem);
+    }
+
+    fn step(&mut self, name: &str, res: &io::Result<()>, output: String) {
+        let output = match res {
+            Ok(()) => output,
+            Err(e) => e.to_string(),
+        };
+        if let Err(e) = res {
+            self.problem(format!("{} failed: {}", name, e));
+        }
+        self.report.steps.push(Step {
+            name: name.to_string(),
+            ok: res.is_ok(),
+            output,
+            calls: Vec::new(),
+        });
+    }
+
+    fn write_stubs(&self) -> io::Result<()> {
+        let bin = self.work.path().join("bin");
+        fs::create_dir_all(&bin)?;
+        for name in STUBS {
+            let path = bin.join(name);
+            fs::write(
+                &path,
+                format!(
+                    "#!/bin/sh\nprintf '%s\\n' \"{} $*\" >> \"$PAX_VERIFY_LOG\"\n",
+                    name
+                ),
+            )?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(())
+    }
+
+    /// Run a maintainer script, when the package has it.
+    fn run(&mut self, script: Script, args: &[&str]) -> io::Result<()> {
+        let Some(text) = self.deb.control_files.get(script.name()) else {
+            return Ok(());
+        };
+        let name = format!("{} {}", script.name(), args.join(" "))
+            .trim_end()
+            .to_string();
+        let path = self.work.path().join(script.name());
+        let stubs = self.work.path().join("bin");
+        let text = rewrite_paths(text, &self.root.to_string_lossy(), &stubs.to_string_lossy());
+        fs::write(&path, text)?;
+        fs::write(self.log(), "")?;
+        let search = std::env::var("PATH").unwrap_or_default();
+        let output = util::command(self.shell)?
+            .arg(&path)
+            .args(args)
+            .current_dir(&self.root)
+            .env("PATH", format!("{}:{}", stubs.display(), search))
+            .env("PAX_VERIFY_LOG", self.log())
+            .env("DPKG_ROOT", &self.root)
+            .env("DPKG_MAINTSCRIPT_NAME", script.name())
+            .env(
+                "DPKG_MAINTSCRIPT_PACKAGE",
+                self.deb.field("Package").unwrap_or(""),
+            )
+            .env(
+                "DPKG_MAINTSCRIPT_ARCH",
+                self.deb.field("Architecture").unwrap_or(""),
+            )
+            .output()
+            .map_err(|e| io::Error::from(Error::spawn(self.shell, e)))?;
+        let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        if !output.status.success() {
+            self.problem(format!("{} failed with {}", name, output.status));
+        }
+        // Shown with the paths as the script wrote them.
+        let root = self.root.to_string_lossy();
+        let calls = fs::read_to_string(self.log())?
+            .lines()
+            .map(|call| call.replace(&*root, ""))
+            .collect();
+        self.report.steps.push(Step {
+            name,
+            ok: output.status.success(),
+            output: text,
+            calls,
+        });
+        Ok(())
+    }
+
+    /// Delete what the package installed, like dpkg does when removing it. Conffiles and
+    /// directories that are not empty stay.
+    fn remove(&self, before: &BTreeSet<String>, conffiles: &[String]) -> io::Result<()> {
+        for entry in self.deb.entries.iter().rev() {
+            if before.contains(&entry.path) || conffiles.contains(&entry.path) {
+                continue;
+            }
+            let path = self.path(&entry.path);
+            let res = match entry.kind.as_str() {
+                "dir" => match fs::remove_dir(&path) {
+                    Err(_) if path.read_dir().is_ok_and(|mut d| d.next().is_some()) => Ok(()),
+                    res => res,
+                },
+                _ => fs::remove_file(&path),
+            };
+            match res {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("{}: could not remove {}", e, entry.path),
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every path under `root`, written as installed paths like "/usr/bin".
+fn snapshot(root: &Path) -> io::Result<BTreeSet<String>> {
+    fn visit(root: &Path, dir: &Path, paths: &mut BTreeSet<String>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let rel = path.strip_prefix(root).unwrap_or(&path);
+            paths.insert(format!("/{}", rel.to_string_lossy()));
+            if entry.file_type()?.is_dir() {
+                visit(root, &path, paths)?;
+            }
+        }
+        Ok(())
+    }
+    let mut paths = BTreeSet::new();
+    visit(root, root, &mut paths)?;
+    Ok(paths)
+}
+
+/// The paths that are not in `keep`, leaving out directories that hold a kept path.
+fn left_over(paths: &BTreeSet<String>, keep: &BTreeSet<String>) -> Vec<String> {
+    paths
+        .iter()
+        .filter(|p| !keep.contains(*p))
+        .filter(|p| {
+            let dir = format!("{}/", p);
+            !keep.iter().any(|k| k.starts_with(&dir))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Put `root` in front of absolute paths into the usual top level directories, such as
+/// '/etc/foo' or /var/lib/foo, and point absolute paths to stubbed commands, like
+/// /usr/sbin/adduser, at the stub in `stubs`. The #! line is left alone, and so are the
+/// directories of a PATH assignment, which only get `stubs` put in front so that the commands
+/// it finds are still stubbed.
+fn rewrite_paths(script: &str, root: &str, stubs: &str) -> String {
+    let ends_word = |c: char| c.is_whitespace() || "'\";)|&`<>".contains(c);
+    let mut out = String::with_capacity(script.len());
+    for (n, line) in script.split_inclusive('\n').enumerate() {
+        if n == 0 && line.starts_with("#!") {
+            out.push_str(line);
+            continue;
+        }
+        let mut prev = None;
+        let mut skip_to = 0;
+        for (i, c) in line.char_indices() {
+            if i < skip_to {
+                continue;
+            }
+            let starts_word =
+                prev.is_none_or(|p: char| p.is_whitespace() || "'\"(;|&`".contains(p));
+            let rest = &line[i..];
+            let word = &rest[..rest.find(ends_word).unwrap_or(rest.len())];
+            if starts_word && word.starts_with("PATH=") {
+                out.push_str(&format!("PATH={}:{}", stubs, &word["PATH=".len()..]));
+                skip_to = i + word.len();
+                prev = word.chars().last();
+                continue;
+            }
+            let starts_path = c == '/'
+                && prev.is_none_or(|p: char| p.is_whitespace() || "'\"=(<>:;|&`".contains(p));
+            if starts_path {
+                let stub = word
+                    .rsplit_once('/')
+                    .filter(|(dir, name)| BIN_DIRS.contains(dir) && STUBS.contains(name));
+                if let Some((_, name)) = stub {
+                    out.push_str(&format!("{}/{}", stubs, name));
+                    skip_to = i + word.len();
+                    prev = word.chars().last();
+                    continue;
+                }
+                let top = word[1..].split('/').next().unwrap_or_default();
+                if TOP_DIRS.contains(&top) {
+                    out.push_str(root);
+                }
+            }
+            out.push(c);
+            prev = Some(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, fs};
+
+    use super::{rewrite_paths, verify};
+    use crate::build::tests::{test_spec, write_test_deb};
+    use crate::build::{BuildSpec, File};
+    use crate::deb::MaintainerScripts;
+
+    #[test]
+    fn install_and_remove() {
+        assert_eq!(
+            rewrite_paths(
+                "#!/bin/sh\nmkdir -p '/var/lib/app' /run/x >/dev/null\nexport PATH=/usr/bin:/bin\n\
+                [ -x /usr/sbin/useradd ] && /usr/sbin/useradd app; /etc/useradd.d/x\n",
+                "/r",
+                "/s"
+            ),
+            "#!/bin/sh\nmkdir -p '/r/var/lib/app' /r/run/x >/dev/null\nexport PATH=/s:/usr/bin:/bin\n\
+            [ -x /s/useradd ] && /s/useradd app; /r/etc/useradd.d/x\n"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let bin = dir.path().join("app");
+        fs::write(&bin, "#!/bin/sh\n").unwrap();
+        let spec = BuildSpec {
+            scripts: Some(MaintainerScripts {
+                preinst: Some("useradd --system app".to_string()),
+                postinst: Some(
+                    "mkdir -p /var/lib/app /var/cache/app\ntouch /var/lib/app/state".to_string(),
+                ),
+                postrm: Some(
+                    "if [ \"$1\" = purge ]; then rm -rf /var/lib/app; fi\nexit 0".to_string(),
+                ),
+                ..Default::default()
+            }),
+            ..test_spec(
+                "app",
+                "1.0",
+                vec![File::new(bin.to_str().unwrap(), "/usr/bin/app")],
+            )
+        };
+        let deb = write_test_deb(spec, dir.path());
+
+        let root = dir.path().join("root");
+        fs::create_dir_all(&root).unwrap();
+        let report = verify(&deb, &root, "sh").unwrap();
+        let names: Vec<_> = report.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "preinst install",
+                "unpack",
+                "postinst configure",
+                "remove",
+                "postrm remove",
+                "purge",
+                "postrm purge"
+            ]
+        );
+        assert!(report.steps.iter().all(|s| s.ok), "{:?}", report);
+        assert_eq!(report.steps[0].calls, ["useradd --system app"]);
+        assert!(root.join("var/cache/app").is_dir());
+        assert_eq!(
+            report.problems,
+            [
+                "left after remove: /var",
+                "left after remove: /var/cache",
+                "left after remove: /var/cache/app",
+                "left after remove: /var/lib",
+                "left after remove: /var/lib/app",
+                "left after remove: /var/lib/app/state",
+                "left after purge: /var",
+                "left after purge: /var/cache",
+                "left after purge: /var/cache/app",
+                "left after purge: /var/lib",
+            ]
+        );
+        let left: BTreeSet<_> = super::snapshot(&root).unwrap();
+        assert!(!left.contains("/usr/bin/app"));
+        assert!(!left.contains("/var/lib/app"));
+    }
+
+    #[test]
+    fn debian_style_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let postinst = "#!/bin/sh\nset -e\nPATH=/usr/sbin:/usr/bin:/sbin:/bin\nexport PATH\n\
+            if [ \"$1\" = configure ]; then\n    \
+            /usr/sbin/adduser --system --group app\n    \
+            deluser --quiet old-app || true\n    \
+            mkdir -p /var/lib/app\n\
+            fi\n";
+        let spec = BuildSpec {
+            scripts: Some(MaintainerScripts {
+                postinst: Some(postinst.to_string()),
+                ..Default::default()
+            }),
+            ..test_spec("app", "1.0", vec![])
+        };
+        let deb = write_test_deb(spec, dir.path());
+        let root = dir.path().join("root");
+        fs::create_dir_all(&root).unwrap();
+        let report = verify(&deb, &root, "sh").unwrap();
+        let postinst = report
+            .steps
+            .iter()
+            .find(|s| s.name == "postinst configure")
+            .unwrap();
+        assert!(postinst.ok, "{:?}", report);
+        assert_eq!(
+            postinst.calls,
+            ["adduser --system --group app", "deluser --quiet old-app"]
+        );
+        assert!(root.join("var/lib/app").is_dir());
+    }
+}
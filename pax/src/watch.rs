@@ -0,0 +1,179 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// How often the tracked paths are checked for changes.
+pub(crate) static POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the inputs have to stay unchanged before a rebuild starts, so that a burst of saves
+/// only triggers one rebuild.
+pub(crate) static DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Directories that are never scanned when a whole source tree is tracked.
+static IGNORED_DIRS: &[&str] = &[".git", ".pax", "target", "node_modules", "dist"];
+
+// Packages can be built on other threads so these are shared by the whole process.
+static TRACKED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+static BUILT: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Record a file or directory that the current run depends on.
+pub(crate) fn track<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    TRACKED.lock().unwrap().insert(path);
+}
+
+/// Record a package file written by the current run.
+pub(crate) fn built<S: Into<String>>(package: S) {
+    BUILT.lock().unwrap().push(package.into());
+}
+
+/// Clear everything recorded by the previous run.
+pub(crate) fn reset() {
+    TRACKED.lock().unwrap().clear();
+    BUILT.lock().unwrap().clear();
+}
+
+pub(crate) fn tracked() -> Vec<PathBuf> {
+    TRACKED.lock().unwrap().iter().cloned().collect()
+}
+
+pub(crate) fn built_packages() -> Vec<String> {
+    BUILT.lock().unwrap().clone()
+}
+
+/// Modification times of every file below a set of paths. Paths that don't exist are recorded
+/// too so that creating them counts as a change.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Snapshot(BTreeMap<PathBuf, Option<SystemTime>>);
+
+impl Snapshot {
+    pub(crate) fn capture(paths: &[PathBuf]) -> Self {
+        let mut snap = Self::default();
+        for path in paths {
+            snap.add(path);
+        }
+        snap
+    }
+
+    fn add(&mut self, path: &Path) {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(_) => {
+                self.0.insert(path.to_path_buf(), None);
+                return;
+            }
+        };
+        // Links are never followed into directories, which could loop, but a link to a file
+        // records when the file changed.
+        if meta.is_symlink() {
+            let meta = fs::metadata(path)
+                .ok()
+                .filter(|target| !target.is_dir())
+                .unwrap_or(meta);
+            self.0.insert(path.to_path_buf(), meta.modified().ok());
+            return;
+        }
+        if !meta.is_dir() {
+            self.0.insert(path.to_path_buf(), meta.modified().ok());
+            return;
+        }
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let p = entry.path();
+            let ignored = entry.file_type().is_ok_and(|t| t.is_dir())
+                && p.file_name()
+                    .is_some_and(|n| IGNORED_DIRS.iter().any(|d| n == *d));
+            if !ignored {
+                self.add(&p);
+            }
+        }
+    }
+
+    /// Paths that were added, removed or modified since `self`.
+    pub(crate) fn changes(&self, newer: &Self) -> Vec<PathBuf> {
+        let mut changed: Vec<_> = newer
+            .0
+            .iter()
+            .filter(|(p, t)| self.0.get(*p) != Some(t))
+            .map(|(p, _)| p.clone())
+            .collect();
+        changed.extend(self.0.keys().filter(|p| !newer.0.contains_key(*p)).cloned());
+        changed
+    }
+}
+
+/// Block until something below `paths` changes and then stays unchanged for the debounce
+/// period. Returns the paths that changed.
+pub(crate) fn wait(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let start = Snapshot::capture(paths);
+    let mut last = loop {
+        thread::sleep(POLL_INTERVAL);
+        let snap = Snapshot::capture(paths);
+        if snap != start {
+            break snap;
+        }
+    };
+    loop {
+        thread::sleep(DEBOUNCE);
+        let snap = Snapshot::capture(paths);
+        if snap == last {
+            return Ok(start.changes(&snap));
+        }
+        last = snap;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::Snapshot;
+
+    #[test]
+    fn snapshot_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("src/main.rs"), "one").unwrap();
+        fs::write(dir.join("target/out"), "one").unwrap();
+        // a link back up the tree is not followed
+        std::os::unix::fs::symlink("..", dir.join("src/loop")).unwrap();
+        let paths = vec![dir.to_path_buf(), dir.join("missing.lua")];
+
+        let before = Snapshot::capture(&paths);
+        assert!(before.changes(&Snapshot::capture(&paths)).is_empty());
+
+        // target directories are ignored
+        fs::write(dir.join("target/out"), "two").unwrap();
+        assert!(before.changes(&Snapshot::capture(&paths)).is_empty());
+
+        std::thread::sleep(Duration::from_millis(10));
+        let f = fs::File::options()
+            .write(true)
+            .open(dir.join("src/main.rs"))
+            .unwrap();
+        f.set_modified(std::time::SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        fs::write(dir.join("missing.lua"), "").unwrap();
+        let mut changes = before.changes(&Snapshot::capture(&paths));
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![dir.join("missing.lua"), dir.join("src/main.rs")]
+        );
+
+        fs::remove_file(dir.join("src/main.rs")).unwrap();
+        let after = Snapshot::capture(&paths);
+        assert!(before.changes(&after).contains(&dir.join("src/main.rs")));
+        assert!(after.0.contains_key(&dir.join("src/loop")));
+    }
+}